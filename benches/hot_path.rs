@@ -0,0 +1,31 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use discord_mediaplayer_rpc::template::{Placeholder, Template};
+use std::hint::black_box;
+
+/// What a typical local track fills the placeholders with.
+fn value(placeholder: Placeholder) -> String {
+    match placeholder {
+        Placeholder::Title => "River",
+        Placeholder::Artist => "Joni Mitchell",
+        Placeholder::Album => "Blue",
+        Placeholder::Player => "Audacious",
+        Placeholder::Length => "4:00",
+        Placeholder::Position => "1:01",
+        Placeholder::Genre => "Folk, Pop",
+        Placeholder::Status => "Playing",
+    }
+    .to_owned()
+}
+
+fn render_template(c: &mut Criterion) {
+    let template = Template::parse("{artist} – {title} ({album}, {length}) [{genre}]").unwrap();
+    c.bench_function("parse_template", |b| {
+        b.iter(|| Template::parse(black_box("{artist} – {title} ({album}, {length})")))
+    });
+    c.bench_function("render_template", |b| {
+        b.iter(|| black_box(&template).render(value))
+    });
+}
+
+criterion_group!(benches, render_template);
+criterion_main!(benches);
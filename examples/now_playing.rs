@@ -0,0 +1,28 @@
+//! Prints a line whenever a player changes what it's playing, the way a
+//! status bar would take it.
+//!
+//!     cargo run --example now_playing -- vlc
+
+use discord_mediaplayer_rpc::template::Template;
+use discord_mediaplayer_rpc::{pipe, Mpris, PresenceSink, Reading};
+
+struct Stdout(Template);
+
+impl PresenceSink for Stdout {
+    fn show(&mut self, reading: &Reading) -> anyhow::Result<()> {
+        match reading.format(&self.0) {
+            Some(line) => println!("{}", line),
+            None => println!("({:?})", reading.status()),
+        }
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let player = std::env::args().nth(1).unwrap_or("audacious".to_owned());
+    let source = Mpris::connect(&player).await?;
+    let template = Template::parse("{artist} - {title} [{position}/{length}]")?;
+    pipe(source, Stdout(template)).await;
+    Ok(())
+}
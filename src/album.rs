@@ -0,0 +1,112 @@
+use crate::events::PlayerState;
+use crate::position::Timestamps;
+use crate::{MediaInfo, PlaybackStatus};
+
+/// Remembers since when the current album has been playing, so album-level
+/// presence counts up across its tracks rather than restarting with each.
+#[derive(Debug, Default)]
+pub struct AlbumSession {
+    album: Option<String>,
+    started: u64,
+}
+
+impl AlbumSession {
+    /// Notes what the player is doing, given its track's own timestamps. A
+    /// stop ends the album, so playing it again later starts it afresh.
+    pub fn observe(&mut self, state: &PlayerState, timestamps: Option<Timestamps>) {
+        if matches!(
+            state.status,
+            PlaybackStatus::Stopped | PlaybackStatus::Closed
+        ) {
+            self.album = None;
+            return;
+        }
+        let Some(mi) = &state.track else {
+            return;
+        };
+        if mi.album.is_empty() || self.album.as_ref() == Some(&mi.album) {
+            return;
+        }
+        if let Some(timestamps) = timestamps {
+            self.album = Some(mi.album.clone());
+            self.started = timestamps.start;
+        }
+    }
+
+    /// When `mi`'s album started playing, in unix seconds.
+    pub fn started(&self, mi: &MediaInfo) -> Option<u64> {
+        (self.album.as_ref() == Some(&mi.album)).then_some(self.started)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(album: &str, title: &str) -> MediaInfo {
+        MediaInfo {
+            album: album.to_owned(),
+            title: title.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn playing(album: &str, title: &str) -> PlayerState {
+        PlayerState {
+            track: Some(track(album, title)),
+            status: PlaybackStatus::Playing,
+            progress: None,
+        }
+    }
+
+    fn starting(start: u64) -> Option<Timestamps> {
+        Some(Timestamps { start, end: None })
+    }
+
+    #[test]
+    fn album_start_survives_track_changes() {
+        let mut session = AlbumSession::default();
+        session.observe(&playing("Blue", "1"), starting(100));
+        session.observe(&playing("Blue", "2"), starting(300));
+        assert_eq!(session.started(&track("Blue", "2")), Some(100));
+    }
+
+    #[test]
+    fn new_album_starts_again() {
+        let mut session = AlbumSession::default();
+        session.observe(&playing("Blue", "1"), starting(100));
+        session.observe(&playing("Court and Spark", "1"), starting(500));
+        assert_eq!(session.started(&track("Court and Spark", "1")), Some(500));
+        assert_eq!(session.started(&track("Blue", "1")), None);
+    }
+
+    #[test]
+    fn album_played_again_after_a_stop_starts_again() {
+        let mut session = AlbumSession::default();
+        session.observe(&playing("Blue", "1"), starting(100));
+        session.observe(&PlayerState::not_playing(PlaybackStatus::Stopped), None);
+        assert_eq!(session.started(&track("Blue", "1")), None);
+        session.observe(&playing("Blue", "1"), starting(900));
+        assert_eq!(session.started(&track("Blue", "1")), Some(900));
+    }
+
+    #[test]
+    fn pausing_keeps_the_album_going() {
+        let mut session = AlbumSession::default();
+        session.observe(&playing("Blue", "1"), starting(100));
+        let paused = PlayerState {
+            status: PlaybackStatus::Paused,
+            ..playing("Blue", "1")
+        };
+        session.observe(&paused, None);
+        session.observe(&playing("Blue", "2"), starting(400));
+        assert_eq!(session.started(&track("Blue", "2")), Some(100));
+    }
+
+    #[test]
+    fn tracks_without_album_have_no_session() {
+        let mut session = AlbumSession::default();
+        session.observe(&playing("", "1"), starting(100));
+        assert_eq!(session.started(&track("", "1")), None);
+    }
+}
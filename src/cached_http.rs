@@ -0,0 +1,158 @@
+use crate::metrics::{Failure, METRICS};
+use crate::paths;
+use crate::track::stable_hash;
+use anyhow::Context;
+use log::debug;
+use reqwest::Url;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Instant};
+
+/// Responses are reused for this long before being fetched again.
+const CACHE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const TIMEOUT: Duration = Duration::from_secs(10);
+// MusicBrainz blocks clients that don't say who they are and how to reach
+// whoever runs them, and it does no harm to tell the others.
+pub const USER_AGENT: &str = concat!(
+    "discord-mediaplayer-rpc/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/dyercode/discord-mediaplayer-rpc )"
+);
+
+/// GETs from a rate-limited web service: requests are spaced to its limit
+/// across all callers, and responses are cached on disk.
+pub struct CachedHttp {
+    http: reqwest::Client,
+    cache_dir: Option<PathBuf>,
+    interval: Duration,
+    next_request: Mutex<Instant>,
+    failure: Failure,
+}
+
+impl CachedHttp {
+    /// A client caching under `service` in the user's cache directory, and
+    /// counting failed requests as `failure`.
+    pub fn new(service: &str, interval: Duration, failure: Failure) -> anyhow::Result<Self> {
+        let cache_dir = paths::cache_dir().map(|dir| dir.join(service));
+        CachedHttp::with_cache_dir(cache_dir, interval, failure)
+    }
+
+    fn with_cache_dir(
+        cache_dir: Option<PathBuf>,
+        interval: Duration,
+        failure: Failure,
+    ) -> anyhow::Result<Self> {
+        Ok(CachedHttp {
+            http: reqwest::Client::builder()
+                .user_agent(USER_AGENT)
+                .timeout(TIMEOUT)
+                .build()?,
+            cache_dir,
+            interval,
+            next_request: Mutex::new(Instant::now()),
+            failure,
+        })
+    }
+
+    pub async fn get(&self, url: &Url) -> anyhow::Result<String> {
+        self.get_with(url, |body| Ok(String::from_utf8(body.to_vec())?))
+            .await
+    }
+
+    /// Fetches `url` and caches what `derive` makes of the response rather
+    /// than the response itself, for bodies such as images, which are only
+    /// wanted for what's worked out from them.
+    pub async fn get_with(
+        &self,
+        url: &Url,
+        derive: impl FnOnce(&[u8]) -> anyhow::Result<String>,
+    ) -> anyhow::Result<String> {
+        let cached = self.cache_path(url);
+        if let Some(body) = cached.as_deref().and_then(read_fresh) {
+            return Ok(body);
+        }
+        self.wait_turn().await;
+        let response = self.fetch(url).await.inspect_err(|_| {
+            METRICS.fail(self.failure);
+        })?;
+        let body = derive(&response)?;
+        if let Some(path) = cached {
+            if let Err(e) = paths::write_atomic(&path, &body) {
+                debug!("couldn't cache response from {}: {}", url, e);
+            }
+        }
+        Ok(body)
+    }
+
+    async fn fetch(&self, url: &Url) -> anyhow::Result<Vec<u8>> {
+        let response = self.http.get(url.clone()).send().await?;
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("querying {}", url))?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    // Holding the lock while sleeping queues callers up in turn.
+    async fn wait_turn(&self) {
+        let mut next = self.next_request.lock().await;
+        sleep_until(*next).await;
+        *next = Instant::now() + self.interval;
+    }
+
+    fn cache_path(&self, url: &Url) -> Option<PathBuf> {
+        self.cache_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{:016x}.json", stable_hash(&[url.as_str()]))))
+    }
+}
+
+fn read_fresh(path: &Path) -> Option<String> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default();
+    match age < CACHE_TTL {
+        true => std::fs::read_to_string(path).ok(),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dmr-http-{}-{}", name, std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn cached_response_is_reused() {
+        let dir = temp_dir("cache");
+        let client = CachedHttp::with_cache_dir(
+            Some(dir.clone()),
+            Duration::ZERO,
+            Failure::MusicBrainzRequest,
+        )
+        .unwrap();
+        let url = Url::parse("https://musicbrainz.invalid/ws/2/release/?query=x").unwrap();
+        paths::write_atomic(&client.cache_path(&url).unwrap(), "{\"releases\":[]}").unwrap();
+        assert_eq!(client.get(&url).await.unwrap(), "{\"releases\":[]}");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn requests_are_spaced_by_interval() {
+        let client = CachedHttp::with_cache_dir(
+            None,
+            Duration::from_millis(50),
+            Failure::MusicBrainzRequest,
+        )
+        .unwrap();
+        let started = Instant::now();
+        for _ in 0..3 {
+            client.wait_turn().await;
+        }
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+}
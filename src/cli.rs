@@ -0,0 +1,287 @@
+use clap::{Arg, ArgAction, ArgMatches};
+
+/// How the daemon runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// In the foreground, stopping on a newline when run from a terminal.
+    Console,
+    /// As a service, under systemd or the like: stdin is left alone, and
+    /// the log goes to the journal when there is one.
+    Service,
+    /// Starts another copy as a service in the background, and returns.
+    Detach,
+}
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Follows the player and keeps the presence up to date.
+    Daemon(Mode),
+    Healthcheck,
+    Now {
+        format: Option<String>,
+    },
+    Stats,
+    Recent,
+    Check,
+    Setup,
+    MetricsDump,
+    ImportLastfm {
+        source: String,
+    },
+    /// With `on` unset to flip the toggle over.
+    Toggle {
+        name: Option<String>,
+        on: Option<bool>,
+    },
+    /// `play`, `pause` or `seek`, with the seconds to seek by.
+    Control {
+        action: String,
+        seconds: Option<String>,
+    },
+    Simulate {
+        scenario: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub trace_wakeups: bool,
+    pub command: Command,
+}
+
+impl Cli {
+    /// Whether the daemon is running as a service, and so without a console.
+    pub fn as_service(&self) -> bool {
+        self.command == Command::Daemon(Mode::Service)
+    }
+
+    /// The arguments that start the same daemon as a service, for
+    /// `--detach` to start it in the background with.
+    pub fn service_args(&self) -> Vec<&'static str> {
+        let mut args = vec!["--daemon"];
+        if self.trace_wakeups {
+            args.push("--trace-wakeups");
+        }
+        args
+    }
+}
+
+fn flag(name: &'static str) -> Arg {
+    Arg::new(name).long(name).action(ArgAction::SetTrue)
+}
+
+fn command() -> clap::Command {
+    use clap::Command as C;
+    C::new("discord-mediaplayer-rpc")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Shows what your media player is playing on Discord")
+        .arg(
+            flag("daemon")
+                .short('d')
+                .help("Run as a service: no console, logging to the journal when there is one"),
+        )
+        .arg(flag("detach").help("Start the daemon in the background and return"))
+        .arg(flag("trace-wakeups").help("Log every time the daemon wakes up, and why"))
+        .subcommand(
+            C::new("healthcheck")
+                .about("Check that the daemon is running and getting through to Discord"),
+        )
+        .subcommand(
+            C::new("now").about("Print the track playing").arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_name("TEMPLATE")
+                    .help("Print with this template in place of the configured one"),
+            ),
+        )
+        .subcommand(C::new("stats").about("Summarise the play history"))
+        .subcommand(C::new("recent").about("List what the daemon recently showed"))
+        .subcommand(C::new("check").about("Check the config and overrides files"))
+        .subcommand(C::new("setup").about("Write a config file by answering questions"))
+        .subcommand(
+            C::new("metrics")
+                .about("Read the running daemon's metrics")
+                .subcommand_required(true)
+                .subcommand(C::new("dump").about("Print the metrics")),
+        )
+        .subcommand(
+            C::new("import")
+                .about("Add plays from elsewhere to the history")
+                .subcommand_required(true)
+                .subcommand(
+                    C::new("lastfm")
+                        .about("Import scrobbles, from an export or with LASTFM_API_KEY set")
+                        .arg(
+                            Arg::new("source")
+                                .required(true)
+                                .value_name("EXPORT_OR_USER"),
+                        ),
+                ),
+        )
+        .subcommand(
+            C::new("toggle")
+                .about("List the toggles, or switch one on, off, or over")
+                .arg(Arg::new("name"))
+                .arg(Arg::new("state").value_parser(["on", "off"])),
+        )
+        .subcommand(C::new("play").about("Tell the player to play"))
+        .subcommand(C::new("pause").about("Tell the player to pause"))
+        .subcommand(
+            C::new("seek")
+                .about("Move by a number of seconds, back if negative")
+                .arg(
+                    Arg::new("seconds")
+                        .required(true)
+                        .allow_negative_numbers(true),
+                ),
+        )
+        // A development aid for trying out sinks without a player.
+        .subcommand(
+            C::new("simulate")
+                .hide(true)
+                .arg(Arg::new("scenario").required(true)),
+        )
+}
+
+fn value(matches: &ArgMatches, name: &str) -> Option<String> {
+    matches.get_one::<String>(name).cloned()
+}
+
+/// Parses the command line, `args` including the program's name. Asking
+/// for help or the version is an error too, for clap to print.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, clap::Error> {
+    let matches = command().try_get_matches_from(args)?;
+    let command = match matches.subcommand() {
+        None if matches.get_flag("detach") => Command::Daemon(Mode::Detach),
+        None if matches.get_flag("daemon") => Command::Daemon(Mode::Service),
+        None => Command::Daemon(Mode::Console),
+        Some(("healthcheck", _)) => Command::Healthcheck,
+        Some(("now", sub)) => Command::Now {
+            format: value(sub, "format"),
+        },
+        Some(("stats", _)) => Command::Stats,
+        Some(("recent", _)) => Command::Recent,
+        Some(("check", _)) => Command::Check,
+        Some(("setup", _)) => Command::Setup,
+        Some(("metrics", _)) => Command::MetricsDump,
+        Some(("import", sub)) => {
+            let (_, lastfm) = sub.subcommand().expect("a source is required");
+            Command::ImportLastfm {
+                source: value(lastfm, "source").expect("the source is required"),
+            }
+        }
+        Some(("toggle", sub)) => Command::Toggle {
+            name: value(sub, "name"),
+            on: value(sub, "state").map(|state| state == "on"),
+        },
+        Some((action @ ("play" | "pause" | "seek"), sub)) => Command::Control {
+            action: action.to_owned(),
+            seconds: (action == "seek").then(|| value(sub, "seconds")).flatten(),
+        },
+        Some(("simulate", sub)) => Command::Simulate {
+            scenario: value(sub, "scenario").expect("the scenario is required"),
+        },
+        Some((other, _)) => unreachable!("no such subcommand `{}`", other),
+    };
+    Ok(Cli {
+        trace_wakeups: matches.get_flag("trace-wakeups"),
+        command,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Cli, clap::Error> {
+        super::parse(
+            std::iter::once("discord-mediaplayer-rpc")
+                .chain(args.iter().copied())
+                .map(str::to_owned),
+        )
+    }
+
+    #[test]
+    fn command_line_is_valid() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn flags_go_in_any_order() {
+        for args in [["--trace-wakeups", "-d"], ["-d", "--trace-wakeups"]] {
+            let cli = parse(&args).unwrap();
+            assert!(cli.trace_wakeups);
+            assert!(cli.as_service());
+        }
+        assert_eq!(parse(&[]).unwrap().command, Command::Daemon(Mode::Console));
+        assert_eq!(
+            parse(&["--detach"]).unwrap().command,
+            Command::Daemon(Mode::Detach)
+        );
+    }
+
+    #[test]
+    fn detached_daemon_is_started_as_a_service() {
+        for args in [
+            &["--detach", "--trace-wakeups"][..],
+            &["--detach", "-d", "--trace-wakeups"],
+        ] {
+            let cli = parse(args).unwrap();
+            assert_eq!(cli.command, Command::Daemon(Mode::Detach));
+            let service = parse(&cli.service_args()).unwrap();
+            assert!(service.as_service());
+            assert!(service.trace_wakeups);
+        }
+    }
+
+    #[test]
+    fn format_accepts_separate_or_inline_value() {
+        let format = |args: &[&str]| match parse(args).unwrap().command {
+            Command::Now { format } => format,
+            other => panic!("not now: {:?}", other),
+        };
+        assert_eq!(
+            format(&["now", "--format", "{title}"]).as_deref(),
+            Some("{title}")
+        );
+        assert_eq!(
+            format(&["now", "--format={title}"]).as_deref(),
+            Some("{title}")
+        );
+        assert_eq!(format(&["now"]), None);
+        assert!(parse(&["now", "--format"]).is_err());
+        assert!(parse(&["now", "--json"]).is_err());
+    }
+
+    #[test]
+    fn seek_takes_negative_seconds() {
+        assert_eq!(
+            parse(&["seek", "-10"]).unwrap().command,
+            Command::Control {
+                action: "seek".to_owned(),
+                seconds: Some("-10".to_owned()),
+            }
+        );
+        assert!(parse(&["seek"]).is_err());
+    }
+
+    #[test]
+    fn toggle_state_is_on_or_off() {
+        assert_eq!(
+            parse(&["toggle", "art", "off"]).unwrap().command,
+            Command::Toggle {
+                name: Some("art".to_owned()),
+                on: Some(false),
+            }
+        );
+        assert!(parse(&["toggle", "art", "maybe"]).is_err());
+    }
+
+    #[test]
+    fn unknown_commands_are_refused() {
+        assert!(parse(&["lyrics"]).is_err());
+        assert!(parse(&["import", "spotify", "me"]).is_err());
+        assert!(parse(&["metrics"]).is_err());
+    }
+}
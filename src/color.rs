@@ -0,0 +1,121 @@
+use crate::cached_http::CachedHttp;
+use crate::metrics::Failure;
+use log::warn;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+/// The Cover Art Archive sets no limit, but there's no call to hurry it.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Bigger covers are shrunk to this many pixels across before counting
+/// colours.
+const SAMPLE_SIZE: u32 = 64;
+
+/// Fetches covers and works out their colours, which are cached in place
+/// of the images.
+pub struct Client {
+    http: CachedHttp,
+}
+
+impl Client {
+    /// The client every feature shares.
+    pub fn shared() -> Option<Arc<Client>> {
+        static SHARED: OnceLock<Option<Arc<Client>>> = OnceLock::new();
+        SHARED
+            .get_or_init(|| {
+                CachedHttp::new("cover_art", MIN_INTERVAL, Failure::CoverArtRequest)
+                    .map_err(|e| warn!("can't create cover art client: {}", e))
+                    .ok()
+                    .map(|http| Arc::new(Client { http }))
+            })
+            .clone()
+    }
+
+    /// The dominant colour of the image at `url`, as `#rrggbb`.
+    pub async fn dominant(&self, url: &str) -> anyhow::Result<String> {
+        self.http.get_with(&Url::parse(url)?, dominant).await
+    }
+}
+
+/// The colour that most of `image` is, found by counting pixels in coarse
+/// buckets so that near shades pool together. Colourful pixels count for
+/// more than grey ones, so greys only win when there's little else.
+pub fn dominant(image: &[u8]) -> anyhow::Result<String> {
+    let mut image = image::load_from_memory(image)?;
+    if image.width() > SAMPLE_SIZE || image.height() > SAMPLE_SIZE {
+        image = image.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE);
+    }
+    let image = image.to_rgb8();
+    let mut buckets: HashMap<[u8; 3], Bucket> = HashMap::new();
+    for pixel in image.pixels() {
+        let [r, g, b] = pixel.0;
+        buckets
+            .entry([r >> 5, g >> 5, b >> 5])
+            .or_default()
+            .add(pixel.0);
+    }
+    let bucket = buckets
+        .values()
+        .max_by_key(|bucket| bucket.weight)
+        .ok_or_else(|| anyhow::anyhow!("the image is empty"))?;
+    let [r, g, b] = bucket.mean();
+    Ok(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    weight: u64,
+    pixels: u64,
+    sums: [u64; 3],
+}
+
+impl Bucket {
+    fn add(&mut self, pixel: [u8; 3]) {
+        let chroma = pixel.iter().max().unwrap() - pixel.iter().min().unwrap();
+        self.weight += 1 + u64::from(chroma) / 16;
+        self.pixels += 1;
+        for (sum, channel) in self.sums.iter_mut().zip(pixel) {
+            *sum += u64::from(channel);
+        }
+    }
+
+    fn mean(&self) -> [u8; 3] {
+        self.sums.map(|sum| (sum / self.pixels.max(1)) as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    /// A 10×10 PNG, `count` pixels of `main` and the rest `other`.
+    fn png(main: [u8; 3], count: u32, other: [u8; 3]) -> Vec<u8> {
+        let image = RgbImage::from_fn(10, 10, |x, y| match y * 10 + x < count {
+            true => Rgb(main),
+            false => Rgb(other),
+        });
+        let mut bytes = Cursor::new(Vec::new());
+        image.write_to(&mut bytes, ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn colour_outweighs_more_grey() {
+        let image = png([200, 30, 30], 30, [128, 128, 128]);
+        assert_eq!(dominant(&image).unwrap(), "#c81e1e");
+    }
+
+    #[test]
+    fn grey_wins_with_little_else() {
+        let image = png([0, 0, 255], 5, [20, 20, 20]);
+        assert_eq!(dominant(&image).unwrap(), "#141414");
+    }
+
+    #[test]
+    fn not_an_image_is_an_error() {
+        assert!(dominant(b"<html>").is_err());
+    }
+}
@@ -0,0 +1,1041 @@
+use crate::content::ContentType;
+use crate::odesli::Service;
+use crate::paths;
+use crate::player;
+use crate::schedule::{Schedule, Window};
+use crate::template::Template;
+use crate::validate;
+use crate::PlaybackStatus;
+use anyhow::Context;
+use jiff::tz::TimeZone;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub throttle: Throttle,
+    pub genre_images: Vec<GenreImage>,
+    /// Display names keyed by player bus name, overriding the player's own identity.
+    pub player_names: HashMap<String, String>,
+    /// Bus names of players to never report, `*` matching any characters.
+    pub ignore_players: Vec<String>,
+    /// Strict mode: when set, only players matching one of these bus names
+    /// are ever reported, and anything else is ignored.
+    pub only_players: Option<Vec<String>>,
+    /// Bus names of players to follow over any others, best first, with
+    /// `*` matching any characters. Otherwise it's whichever most recently
+    /// started playing.
+    pub player_priority: Vec<String>,
+    /// Tracks shorter than this many seconds, such as sound effects, are
+    /// ignored. Tracks of unknown length never are.
+    pub min_length: u64,
+    /// Links shown under the presence; Discord shows at most two.
+    pub buttons: Vec<ButtonTemplate>,
+    /// Buttons keyed by player bus name, in place of `buttons` for that
+    /// player. An empty list shows none.
+    pub player_buttons: HashMap<String, Vec<ButtonTemplate>>,
+    /// The Discord application to show as, in place of the built-in one;
+    /// `applications` may still pick others.
+    pub client_id: Option<u64>,
+    pub applications: Vec<Application>,
+    pub presence: Presence,
+    pub time_shown: TimeShown,
+    pub status_display: StatusDisplay,
+    pub screen_share: ScreenShare,
+    pub last_played: LastPlayed,
+    pub status_images: StatusImages,
+    pub party: Party,
+    pub on_close: OnClose,
+    pub quiet_hours: QuietHours,
+    pub pause_while_running: PauseWhileRunning,
+    pub enrichment: Enrichment,
+    pub templates: Templates,
+    pub webhook: Webhook,
+    pub notify: Notify,
+    pub hooks: Hooks,
+    pub history: History,
+    pub session_bus: SessionBus,
+    /// More buses to find the player on besides the session bus, such as
+    /// one a headless service runs: D-Bus addresses like
+    /// `unix:path=/run/user/1000/bus`, or `system`.
+    pub extra_buses: Vec<String>,
+    pub resilience: Resilience,
+    pub status: Status,
+    pub locale: Locale,
+    pub debug: DebugOptions,
+}
+
+impl Config {
+    pub fn excludes_player(&self, bus_name: &str) -> bool {
+        player::is_excluded(&self.ignore_players, self.only_players.as_deref(), bus_name)
+    }
+
+    /// Cuts the config down to just the player and Discord, leaving out
+    /// enrichment and every other sink and watcher, one of which may be
+    /// what keeps crashing.
+    pub fn safe_mode(&mut self) {
+        self.enrichment.stages.clear();
+        self.webhook.url = None;
+        self.notify.channels.clear();
+        self.hooks = Hooks::default();
+        self.history.enabled = false;
+        self.screen_share = ScreenShare::Show;
+        self.pause_while_running.processes.clear();
+        self.extra_buses.clear();
+    }
+
+    /// Whether to keep track of the last track played, for showing after
+    /// playback stops or the player exits.
+    pub fn remembers_last_played(&self) -> bool {
+        self.last_played.enabled || self.on_close == OnClose::LastPlayed
+    }
+
+    /// Whether to show the last track played once playback is `status`;
+    /// only once it's over, as a paused track is still the one playing.
+    pub fn shows_last_played(&self, status: PlaybackStatus) -> bool {
+        match (status, self.on_close) {
+            (PlaybackStatus::Closed, OnClose::Clear) => false,
+            (PlaybackStatus::Closed, OnClose::LastPlayed) => true,
+            (PlaybackStatus::Stopped | PlaybackStatus::Closed, _) => self.last_played.enabled,
+            (PlaybackStatus::Playing | PlaybackStatus::Paused, _) => false,
+        }
+    }
+
+    pub fn buttons_for(&self, bus_name: &str) -> &[ButtonTemplate] {
+        self.player_buttons.get(bus_name).unwrap_or(&self.buttons)
+    }
+}
+
+/// Discord won't show more buttons than this.
+pub const MAX_BUTTONS: usize = 2;
+/// Nor labels longer than this many characters.
+pub const MAX_BUTTON_LABEL: usize = 32;
+
+/// A button whose URL is filled in from the track, as in
+/// `https://www.last.fm/music/{artist}/_/{title}`. Values are
+/// percent-encoded, so they can go anywhere in it.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ButtonTemplate {
+    pub label: String,
+    pub url: Template,
+}
+
+fn validate_buttons(name: &str, buttons: &[ButtonTemplate]) -> anyhow::Result<()> {
+    if buttons.len() > MAX_BUTTONS {
+        anyhow::bail!("{} has more than {} buttons", name, MAX_BUTTONS);
+    }
+    for button in buttons {
+        let length = button.label.chars().count();
+        if length == 0 || length > MAX_BUTTON_LABEL {
+            anyhow::bail!(
+                "{}: button label `{}` must be 1 to {} characters",
+                name,
+                button.label,
+                MAX_BUTTON_LABEL
+            );
+        }
+        if !validate::is_web_url(button.url.prefix()) {
+            anyhow::bail!(
+                "{}: the URL of button `{}` must start with https:// or http://",
+                name,
+                button.label
+            );
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Enrichment {
+    /// Enrichers to run, in order; leave one out to disable it.
+    pub stages: Vec<Stage>,
+    /// How sure (0-100) a search-based stage must be that it found the
+    /// right thing before its result is shown.
+    pub min_confidence: u8,
+    /// The service the listen button goes straight to when the track is
+    /// found there. Otherwise it goes to Odesli's page for the track, where
+    /// whoever opens it can pick their own.
+    pub link_to: Option<Service>,
+    /// Large image shown while the stages are still looking, replaced by
+    /// what they find once they're done.
+    pub placeholder: Option<String>,
+}
+
+impl Default for Enrichment {
+    fn default() -> Self {
+        Enrichment {
+            stages: vec![Stage::GenreImages],
+            min_confidence: 90,
+            link_to: None,
+            placeholder: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    GenreImages,
+    /// Album covers from the Cover Art Archive, found through MusicBrainz,
+    /// which also says how many tracks the album has for album presence.
+    CoverArt,
+    /// A button linking to the track on streaming services, found through
+    /// Odesli.
+    StreamingLinks,
+    /// The dominant colour of the cover found by the stages before, for
+    /// widgets reading the status file to theme themselves by.
+    CoverColor,
+}
+
+/// Times when nothing is shown on Discord, whatever's playing.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuietHours {
+    /// Windows like `Mon-Fri 09:00-17:00` or `23:00-07:00`.
+    pub windows: Vec<Window>,
+    /// An IANA time zone name for the windows; the system's by default.
+    pub time_zone: Option<String>,
+}
+
+impl QuietHours {
+    pub fn schedule(&self) -> Option<Schedule> {
+        if self.windows.is_empty() {
+            return None;
+        }
+        let time_zone = match &self.time_zone {
+            Some(name) => TimeZone::get(name).ok()?,
+            None => TimeZone::system(),
+        };
+        Some(Schedule::new(self.windows.clone(), time_zone))
+    }
+}
+
+/// Processes, such as `obs` or `zoom`, while any of which runs nothing is
+/// shown on Discord, for streaming or calls without the music.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PauseWhileRunning {
+    /// Process names, as `ps -e` shows them.
+    pub processes: Vec<String>,
+    /// How often to check for them, in seconds.
+    pub poll: u64,
+}
+
+impl Default for PauseWhileRunning {
+    fn default() -> Self {
+        PauseWhileRunning {
+            processes: Vec::new(),
+            poll: 5,
+        }
+    }
+}
+
+/// How each sink formats a track. A sink without its own template uses
+/// `default`, and failing that its built-in layout.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Templates {
+    pub default: Option<Template>,
+    /// The `now` command, unless given `--format`.
+    pub now: Option<Template>,
+    /// The `text` field of the status file, for widgets that just want a line.
+    pub status: Option<Template>,
+    /// The description of the webhook message.
+    pub webhook: Option<Template>,
+    pub discord: DiscordTemplates,
+}
+
+/// Discord's two lines. Only `details` falls back to the default template,
+/// as the default is usually a one-liner.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscordTemplates {
+    pub details: Option<Template>,
+    pub state: Option<Template>,
+}
+
+impl Templates {
+    pub fn now(&self) -> Option<&Template> {
+        self.now.as_ref().or(self.default.as_ref())
+    }
+
+    pub fn status(&self) -> Option<&Template> {
+        self.status.as_ref().or(self.default.as_ref())
+    }
+
+    pub fn webhook(&self) -> Option<&Template> {
+        self.webhook.as_ref().or(self.default.as_ref())
+    }
+
+    pub fn discord_details(&self) -> Option<&Template> {
+        self.discord.details.as_ref().or(self.default.as_ref())
+    }
+}
+
+/// Keeps a "now playing" message up to date in a Discord channel, apart
+/// from Rich Presence.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Webhook {
+    /// The channel webhook's URL; nothing is posted without one.
+    pub url: Option<String>,
+    /// The fewest seconds between edits of the message.
+    pub min_interval: u64,
+}
+
+impl Default for Webhook {
+    fn default() -> Self {
+        Webhook {
+            url: None,
+            min_interval: 5,
+        }
+    }
+}
+
+/// Tells the user when Discord stays out of reach or a sink keeps failing,
+/// rather than leaving it to the log.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notify {
+    /// Where to send notices; none are sent without any.
+    pub channels: Vec<NotifyChannel>,
+    /// Notices less serious than this are left to the log.
+    pub min_severity: Severity,
+    /// Where the `webhook` channel posts, such as a Discord channel's
+    /// webhook.
+    pub webhook_url: Option<String>,
+    /// Seconds Discord may be out of reach, while there's something to
+    /// show, before it's worth a notice.
+    pub discord_after: u64,
+}
+
+impl Default for Notify {
+    fn default() -> Self {
+        Notify {
+            channels: Vec::new(),
+            min_severity: Severity::Warning,
+            webhook_url: None,
+            discord_after: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyChannel {
+    /// A desktop notification.
+    Desktop,
+    /// A warning or error in the log, with `EVENT=problem` in the journal.
+    Log,
+    /// A message posted to `webhook_url`.
+    Webhook,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, PartialOrd)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Something that had gone wrong has come right.
+    Info,
+    Warning,
+    Error,
+}
+
+/// Shows friends listening to the same album together on Discord. Everyone
+/// picks the same key, and the party is matched on the album and artist
+/// here, without any server involved.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Party {
+    /// Shared with the friends to be grouped with; no party without one.
+    pub key: Option<String>,
+    /// How many the party has room for, shown as "1 of N". No count is
+    /// shown without one.
+    pub size: Option<u32>,
+}
+
+/// Shell commands run on player events, given the track in `MPRIS_*`
+/// environment variables.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    pub track_changed: Option<String>,
+    pub paused: Option<String>,
+    /// Run when playback stops or the player closes.
+    pub stopped: Option<String>,
+}
+
+/// Records each play, and whether it was skipped, for `stats`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct History {
+    pub enabled: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History { enabled: true }
+    }
+}
+
+/// Keeps showing the last track for a while after playback stops.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LastPlayed {
+    pub enabled: bool,
+    /// Seconds after stopping before the presence is cleared.
+    pub expiry: u64,
+    /// Large image to show instead of the genre image, e.g. a dimmed logo.
+    pub image: Option<String>,
+}
+
+impl Default for LastPlayed {
+    fn default() -> Self {
+        LastPlayed {
+            enabled: false,
+            expiry: 1800,
+            image: None,
+        }
+    }
+}
+
+/// Small images, asset keys or web URLs, saying whether the track shown is
+/// playing or paused. The paused one shows with the last played track.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatusImages {
+    pub playing: Option<String>,
+    pub paused: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DebugOptions {
+    /// Log a mock-up of the presence every time it is published.
+    pub preview: bool,
+}
+
+/// How durations and numbers are written in templates and command output.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Locale {
+    /// A locale such as `de_DE`; taken from the environment when unset.
+    pub tag: Option<String>,
+    pub hours: Hours,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Hours {
+    /// `1:05:30` from an hour up.
+    #[default]
+    Auto,
+    /// Minutes keep counting: `65:30`.
+    Never,
+}
+
+/// What the status file keeps for `now`, `recent` and widgets.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Status {
+    /// How many recently published presences to keep.
+    pub recent: usize,
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Status { recent: 20 }
+    }
+}
+
+/// How patiently to wait on, and how often to retry, the things we depend
+/// on. All in seconds, and none may be zero.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Resilience {
+    /// How often to check whether Discord has (re)connected, or stopped
+    /// answering.
+    pub discord_poll: u64,
+    /// Seconds the Discord client may go on failing to reach Discord before
+    /// it's thrown away for a new one.
+    pub discord_dead_after: u64,
+    /// How long to wait for Discord to accept a handshake after switching
+    /// application before publishing anyway.
+    pub discord_handshake: u64,
+    /// How long to wait for the player to answer a D-Bus call.
+    pub dbus_timeout: u64,
+    /// Seconds between readings of a player that doesn't say when it
+    /// changes, or hasn't yet.
+    pub player_poll: u64,
+    /// How long a player may be gone before it's taken as closed, so one
+    /// that's only restarting leaves the presence be.
+    pub vanish_grace: u64,
+    /// How long instead once it's come and gone three times in a minute,
+    /// as a browser's can with each tab.
+    pub flap_cooldown: u64,
+}
+
+impl Default for Resilience {
+    fn default() -> Self {
+        Resilience {
+            discord_poll: 1,
+            discord_dead_after: 20,
+            discord_handshake: 10,
+            dbus_timeout: 5,
+            player_poll: 2,
+            vanish_grace: 2,
+            flap_cooldown: 30,
+        }
+    }
+}
+
+impl Resilience {
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("discord_poll", self.discord_poll),
+            ("discord_dead_after", self.discord_dead_after),
+            ("discord_handshake", self.discord_handshake),
+            ("dbus_timeout", self.dbus_timeout),
+            ("player_poll", self.player_poll),
+            ("vanish_grace", self.vanish_grace),
+            ("flap_cooldown", self.flap_cooldown),
+        ] {
+            if value == 0 {
+                anyhow::bail!("resilience.{} must be at least 1", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What to show once the player has exited.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnClose {
+    /// The same as when playback stops.
+    #[default]
+    AsStopped,
+    /// Nothing, straight away, even if `last_played` is enabled.
+    Clear,
+    /// The last track played, for `last_played.expiry`, even if
+    /// `last_played` isn't enabled.
+    LastPlayed,
+    /// The next player by `player_priority`, on the same bus or another of
+    /// the `extra_buses`, if there is one, even if it's stopped; otherwise
+    /// the same as when playback stops. Without this, another player is
+    /// only followed once it starts playing.
+    NextPlayer,
+}
+
+/// Which session bus to find the player on.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionBus {
+    /// That of the graphical session logind says is active on the user's
+    /// seat, so a second session (say, a nested test one) doesn't capture us.
+    Active,
+    /// Whatever `DBUS_SESSION_BUS_ADDRESS` says.
+    #[default]
+    Environment,
+}
+
+/// How much detail the presence goes into.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Presence {
+    /// The playing track's artist, title and album.
+    #[default]
+    Track,
+    /// The album and artist with a track number, which only ticks over as
+    /// the album plays through. It's out of the album's tracks when the
+    /// `cover_art` stage finds how many there are, and the time counts
+    /// from the start again once the album is stopped and played anew.
+    Album,
+}
+
+/// How Discord shows the time of a playing track.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeShown {
+    /// A bar with the time left, for tracks of known length.
+    #[default]
+    Remaining,
+    /// Only the time since the track started.
+    Elapsed,
+}
+
+/// What the friends list names beside "Listening to" while a track plays.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusDisplay {
+    /// The Discord application's name.
+    #[default]
+    App,
+    /// The artist, put in the state line in place of the album unless
+    /// `templates.discord.state` says otherwise.
+    Artist,
+    /// The details line, the artist and title by default.
+    Track,
+}
+
+/// What to show while the screen is being shared through the desktop portal.
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreenShare {
+    /// Carry on as normal.
+    #[default]
+    Show,
+    /// Clear the presence until sharing stops.
+    Hide,
+    /// Show that something is playing, but not what.
+    Generic,
+}
+
+/// Refresh cadences, in seconds. Each facet may publish at most once per its
+/// cadence, and all facets share Discord's own limit of `burst` updates per
+/// `window`.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Throttle {
+    pub metadata: u64,
+    pub playback: u64,
+    pub timestamps: u64,
+    pub burst: usize,
+    pub window: u64,
+    /// Quiet period after attaching to a player, letting it finish
+    /// restoring its session before anything is published.
+    pub settle: u64,
+    /// Milliseconds to wait after a change for others to follow, so a
+    /// burst of them from the player goes out as one update.
+    pub coalesce_ms: u64,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle {
+            metadata: 0,
+            playback: 0,
+            timestamps: 0,
+            burst: 5,
+            window: 20,
+            settle: 0,
+            coalesce_ms: 300,
+        }
+    }
+}
+
+/// Shows `image` as the large image for tracks with a genre matching `pattern`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawGenreImage")]
+pub struct GenreImage {
+    pub pattern: Regex,
+    pub image: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawGenreImage {
+    pattern: String,
+    image: String,
+}
+
+impl TryFrom<RawGenreImage> for GenreImage {
+    type Error = regex::Error;
+
+    fn try_from(raw: RawGenreImage) -> Result<Self, Self::Error> {
+        Ok(GenreImage {
+            pattern: Regex::new(&raw.pattern)?,
+            image: raw.image,
+        })
+    }
+}
+
+impl PartialEq for GenreImage {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern.as_str() == other.pattern.as_str() && self.image == other.image
+    }
+}
+
+/// Presents as Discord application `client_id` (and so under its name) when
+/// playing from `player` and/or a track with a genre matching `genre`.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawApplication")]
+pub struct Application {
+    pub client_id: u64,
+    /// A player display name, as shown in the hover text.
+    pub player: Option<String>,
+    pub genre: Option<Regex>,
+    pub content: Option<ContentType>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawApplication {
+    client_id: u64,
+    player: Option<String>,
+    genre: Option<String>,
+    content: Option<ContentType>,
+}
+
+impl TryFrom<RawApplication> for Application {
+    type Error = regex::Error;
+
+    fn try_from(raw: RawApplication) -> Result<Self, Self::Error> {
+        Ok(Application {
+            client_id: raw.client_id,
+            player: raw.player,
+            genre: raw.genre.as_deref().map(Regex::new).transpose()?,
+            content: raw.content,
+        })
+    }
+}
+
+impl PartialEq for Application {
+    fn eq(&self, other: &Self) -> bool {
+        self.client_id == other.client_id
+            && self.player == other.player
+            && self.content == other.content
+            && self.genre.as_ref().map(Regex::as_str) == other.genre.as_ref().map(Regex::as_str)
+    }
+}
+
+pub fn path() -> Option<PathBuf> {
+    paths::config_dir().map(|dir| dir.join("config.toml"))
+}
+
+/// Loads the config file, falling back to defaults when there is none.
+pub fn load() -> anyhow::Result<Config> {
+    match path() {
+        Some(path) if path.exists() => {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            parse(&text).with_context(|| format!("parsing {}", path.display()))
+        }
+        _ => Ok(Config::default()),
+    }
+}
+
+pub fn parse(text: &str) -> anyhow::Result<Config> {
+    let config: Config = toml::from_str(text)?;
+    if config.enrichment.min_confidence > 100 {
+        anyhow::bail!("enrichment.min_confidence is out of 100");
+    }
+    if config.throttle.burst == 0 {
+        anyhow::bail!("throttle.burst must be at least 1");
+    }
+    config.resilience.validate()?;
+    if config.pause_while_running.poll == 0 {
+        anyhow::bail!("pause_while_running.poll must be at least 1");
+    }
+    if config.notify.discord_after == 0 {
+        anyhow::bail!("notify.discord_after must be at least 1");
+    }
+    if config.notify.channels.contains(&NotifyChannel::Webhook)
+        && config.notify.webhook_url.is_none()
+    {
+        anyhow::bail!("notify.channels has webhook, but notify.webhook_url isn't set");
+    }
+    if config.party.size == Some(0) {
+        anyhow::bail!("party.size must be at least 1");
+    }
+    validate_buttons("buttons", &config.buttons)?;
+    for (player, buttons) in &config.player_buttons {
+        validate_buttons(&format!("player_buttons.\"{}\"", player), buttons)?;
+    }
+    if let Some(name) = &config.quiet_hours.time_zone {
+        TimeZone::get(name).with_context(|| format!("quiet_hours.time_zone `{}`", name))?;
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_file_gives_defaults() {
+        assert_eq!(parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn partial_sections_keep_remaining_defaults() {
+        let config = parse("[throttle]\nplayback = 3\n").unwrap();
+        assert_eq!(config.throttle.playback, 3);
+        assert_eq!(config.throttle.burst, 5);
+        assert_eq!(config.throttle.coalesce_ms, 300);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(parse("[throttle]\nlyrics = 15\n").is_err());
+    }
+
+    #[test]
+    fn genre_images_keep_their_order() {
+        let config = parse(
+            r#"
+            [[genre_images]]
+            pattern = "(?i)metal"
+            image = "metal"
+
+            [[genre_images]]
+            pattern = "(?i)jazz"
+            image = "jazz"
+            "#,
+        )
+        .unwrap();
+        let images: Vec<_> = config.genre_images.iter().map(|g| &g.image).collect();
+        assert_eq!(images, ["metal", "jazz"]);
+    }
+
+    #[test]
+    fn invalid_genre_pattern_is_rejected() {
+        assert!(parse("[[genre_images]]\npattern = \"(\"\nimage = \"x\"\n").is_err());
+    }
+
+    #[test]
+    fn player_names_keyed_by_bus_name() {
+        let config = parse(
+            r#"
+            [player_names]
+            "org.mpris.MediaPlayer2.jriver" = "JRiver"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.player_names["org.mpris.MediaPlayer2.jriver"],
+            "JRiver"
+        );
+    }
+
+    #[test]
+    fn player_buttons_replace_the_default_ones() {
+        let config = parse(
+            r#"
+            [[buttons]]
+            label = "Last.fm"
+            url = "https://www.last.fm/music/{artist}/_/{title}"
+
+            [player_buttons]
+            "org.mpris.MediaPlayer2.mpv" = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.buttons_for("org.mpris.MediaPlayer2.mpv"), []);
+        assert_eq!(
+            config.buttons_for("org.mpris.MediaPlayer2.vlc")[0].label,
+            "Last.fm"
+        );
+    }
+
+    #[test]
+    fn buttons_are_validated() {
+        let button = |label: &str, url: &str| {
+            format!("[[buttons]]\nlabel = \"{}\"\nurl = \"{}\"\n", label, url)
+        };
+        assert!(parse(&button("Search", "javascript:alert(1)")).is_err());
+        assert!(parse(&button("{title}", "{artist}")).is_err());
+        assert!(parse(&button(&"x".repeat(33), "https://example.com")).is_err());
+        assert!(parse(&button("", "https://example.com")).is_err());
+        assert!(parse(&button("Search", "https://example.com/?q={title")).is_err());
+        assert!(parse(&button("x", "https://example.com").repeat(3)).is_err());
+        let err = parse(
+            r#"
+            [player_buttons]
+            "org.mpris.MediaPlayer2.mpv" = [{ label = "x", url = "ftp://x" }]
+            "#,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("org.mpris.MediaPlayer2.mpv"));
+    }
+
+    #[test]
+    fn applications_parse_optional_conditions() {
+        let config = parse(
+            r#"
+            [[applications]]
+            client_id = 42
+            player = "mpv"
+
+            [[applications]]
+            client_id = 43
+            genre = "(?i)podcast"
+            content = "stream"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.applications[1].content, Some(ContentType::Stream));
+        assert_eq!(config.applications[0].player.as_deref(), Some("mpv"));
+        assert!(config.applications[0].genre.is_none());
+        assert!(config.applications[1]
+            .genre
+            .as_ref()
+            .unwrap()
+            .is_match("Podcast"));
+    }
+
+    #[test]
+    fn pause_while_running_needs_a_poll_interval() {
+        let config = parse("[pause_while_running]\nprocesses = [\"obs\"]\n").unwrap();
+        assert_eq!(config.pause_while_running.poll, 5);
+        assert!(parse("[pause_while_running]\npoll = 0\n").is_err());
+    }
+
+    #[test]
+    fn on_close_overrides_last_played() {
+        let config = parse("on_close = \"last_played\"\n").unwrap();
+        assert!(config.remembers_last_played());
+        assert!(config.shows_last_played(PlaybackStatus::Closed));
+        assert!(!config.shows_last_played(PlaybackStatus::Stopped));
+        let config = parse("on_close = \"clear\"\n[last_played]\nenabled = true\n").unwrap();
+        assert!(!config.shows_last_played(PlaybackStatus::Closed));
+        assert!(config.shows_last_played(PlaybackStatus::Stopped));
+        assert!(!config.shows_last_played(PlaybackStatus::Paused));
+        assert!(!config.shows_last_played(PlaybackStatus::Playing));
+    }
+
+    #[test]
+    fn screen_share_mode_is_lowercase() {
+        assert_eq!(
+            parse("screen_share = \"generic\"\n").unwrap().screen_share,
+            ScreenShare::Generic
+        );
+        assert!(parse("screen_share = \"blur\"\n").is_err());
+    }
+
+    #[test]
+    fn client_id_is_optional() {
+        assert_eq!(parse("").unwrap().client_id, None);
+        assert_eq!(parse("client_id = 1234\n").unwrap().client_id, Some(1234));
+    }
+
+    #[test]
+    fn time_shown_defaults_to_remaining() {
+        assert_eq!(parse("").unwrap().time_shown, TimeShown::Remaining);
+        assert_eq!(
+            parse("time_shown = \"elapsed\"\n").unwrap().time_shown,
+            TimeShown::Elapsed
+        );
+    }
+
+    #[test]
+    fn session_bus_defaults_to_environment() {
+        assert_eq!(parse("").unwrap().session_bus, SessionBus::Environment);
+        assert_eq!(
+            parse("session_bus = \"active\"\n").unwrap().session_bus,
+            SessionBus::Active
+        );
+    }
+
+    #[test]
+    fn status_display_defaults_to_app() {
+        assert_eq!(parse("").unwrap().status_display, StatusDisplay::App);
+        assert_eq!(
+            parse("status_display = \"artist\"\n")
+                .unwrap()
+                .status_display,
+            StatusDisplay::Artist
+        );
+        assert!(parse("status_display = \"album\"\n").is_err());
+    }
+
+    #[test]
+    fn presence_defaults_to_track() {
+        assert_eq!(parse("").unwrap().presence, Presence::Track);
+        assert_eq!(
+            parse("presence = \"album\"\n").unwrap().presence,
+            Presence::Album
+        );
+    }
+
+    #[test]
+    fn last_played_keeps_default_expiry() {
+        let config = parse("[last_played]\nenabled = true\n").unwrap();
+        assert!(config.last_played.enabled);
+        assert_eq!(config.last_played.expiry, 1800);
+    }
+
+    #[test]
+    fn unknown_enrichment_stage_is_rejected() {
+        assert!(parse("[enrichment]\nstages = [\"lyrics\"]\n").is_err());
+    }
+
+    #[test]
+    fn sink_templates_fall_back_to_default() {
+        let config = parse(
+            r#"
+            [templates]
+            default = "{artist} - {title}"
+            now = "{title}"
+
+            [templates.discord]
+            state = "on {album}"
+            "#,
+        )
+        .unwrap();
+        let templates = &config.templates;
+        assert_eq!(templates.now(), Some(&Template::parse("{title}").unwrap()));
+        assert_eq!(templates.status(), templates.default.as_ref());
+        assert_eq!(templates.discord_details(), templates.default.as_ref());
+        assert!(templates.discord.state.is_some());
+    }
+
+    #[test]
+    fn invalid_template_is_rejected() {
+        assert!(parse("[templates]\ndefault = \"{lyrics}\"\n").is_err());
+    }
+
+    #[test]
+    fn quiet_hours_need_a_known_time_zone() {
+        let config = parse("[quiet_hours]\nwindows = [\"Mon-Fri 09:00-17:00\"]\n").unwrap();
+        assert!(config.quiet_hours.schedule().is_some());
+        assert!(parse("[quiet_hours]\ntime_zone = \"Mars/Olympus_Mons\"\n").is_err());
+        assert!(parse("[quiet_hours]\nwindows = [\"Mon-Fri\"]\n").is_err());
+    }
+
+    #[test]
+    fn link_to_names_a_service() {
+        let config = parse("[enrichment]\nlink_to = \"apple_music\"\n").unwrap();
+        assert_eq!(config.enrichment.link_to, Some(Service::AppleMusic));
+        assert!(parse("[enrichment]\nlink_to = \"napster\"\n").is_err());
+    }
+
+    #[test]
+    fn min_confidence_is_a_percentage() {
+        assert_eq!(parse("").unwrap().enrichment.min_confidence, 90);
+        assert!(parse("[enrichment]\nmin_confidence = 101\n").is_err());
+    }
+
+    #[test]
+    fn resilience_rejects_zero_intervals() {
+        let config = parse("[resilience]\ndbus_timeout = 2\n").unwrap();
+        assert_eq!(config.resilience.dbus_timeout, 2);
+        assert_eq!(config.resilience.discord_poll, 1);
+        let err = parse("[resilience]\ndiscord_poll = 0\n").unwrap_err();
+        assert!(err.to_string().contains("resilience.discord_poll"));
+    }
+
+    #[test]
+    fn zero_burst_is_rejected() {
+        assert!(parse("[throttle]\nburst = 0\n").is_err());
+    }
+
+    #[test]
+    fn webhook_edits_are_spaced_out_by_default() {
+        assert_eq!(parse("").unwrap().webhook.min_interval, 5);
+        let config = parse("[webhook]\nmin_interval = 30\n").unwrap();
+        assert_eq!(config.webhook.min_interval, 30);
+    }
+
+    #[test]
+    fn notify_webhook_needs_a_url() {
+        let config = parse("[notify]\nchannels = [\"desktop\", \"log\"]\n").unwrap();
+        assert_eq!(config.notify.min_severity, Severity::Warning);
+        let err = parse("[notify]\nchannels = [\"webhook\"]\n").unwrap_err();
+        assert!(err.to_string().contains("notify.webhook_url"));
+    }
+}
@@ -0,0 +1,114 @@
+use crate::duration::TrackDuration;
+use crate::ipc::ActivityType;
+use serde::{Deserialize, Serialize};
+
+const VIDEO_EXTENSIONS: [&str; 12] = [
+    "mp4", "m4v", "mkv", "webm", "avi", "mov", "wmv", "flv", "mpg", "mpeg", "ogv", "ts",
+];
+const PLAYLIST_EXTENSIONS: [&str; 3] = ["m3u", "m3u8", "pls"];
+const STREAM_SCHEMES: [&str; 6] = ["http", "https", "rtsp", "rtmp", "mms", "icy"];
+
+/// What kind of media is playing, which decides how it is presented.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    #[default]
+    Audio,
+    Video,
+    /// Live radio and other endless streams.
+    Stream,
+}
+
+impl ContentType {
+    /// Guesses from the track's `xesam:url`: its extension, or a network
+    /// scheme with no known length.
+    pub fn detect(url: Option<&str>, length: Option<TrackDuration>) -> Self {
+        let Some(url) = url else {
+            return ContentType::Audio;
+        };
+        let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+        let path = rest.split(['?', '#']).next().unwrap_or_default();
+        let extension = path
+            .rsplit_once('.')
+            .filter(|(_, extension)| !extension.contains('/'))
+            .map(|(_, extension)| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some(ext) if VIDEO_EXTENSIONS.contains(&ext) => ContentType::Video,
+            Some(ext) if PLAYLIST_EXTENSIONS.contains(&ext) => ContentType::Stream,
+            _ if length.is_none() && STREAM_SCHEMES.contains(&scheme) => ContentType::Stream,
+            _ => ContentType::Audio,
+        }
+    }
+
+    pub fn verb(self) -> &'static str {
+        match self {
+            ContentType::Audio | ContentType::Stream => "Playing",
+            ContentType::Video => "Watching",
+        }
+    }
+
+    pub fn activity_type(self) -> ActivityType {
+        match self {
+            ContentType::Audio | ContentType::Stream => ActivityType::Listening,
+            ContentType::Video => ActivityType::Watching,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn length() -> Option<TrackDuration> {
+        TrackDuration::from_micros(180_000_000)
+    }
+
+    #[test]
+    fn no_url_is_audio() {
+        assert_eq!(ContentType::detect(None, None), ContentType::Audio);
+    }
+
+    #[test]
+    fn video_extension_is_video() {
+        assert_eq!(
+            ContentType::detect(Some("file:///home/me/Films/Heat.MKV"), length()),
+            ContentType::Video
+        );
+    }
+
+    #[test]
+    fn query_string_is_ignored() {
+        assert_eq!(
+            ContentType::detect(Some("https://cdn.example/clip.webm?token=a.b"), length()),
+            ContentType::Video
+        );
+    }
+
+    #[test]
+    fn network_url_without_length_is_stream() {
+        assert_eq!(
+            ContentType::detect(Some("http://radio.example:8000/live"), None),
+            ContentType::Stream
+        );
+        assert_eq!(
+            ContentType::detect(Some("http://radio.example/song.mp3"), length()),
+            ContentType::Audio
+        );
+    }
+
+    #[test]
+    fn playlist_is_stream() {
+        assert_eq!(
+            ContentType::detect(Some("file:///tmp/station.pls"), None),
+            ContentType::Stream
+        );
+    }
+
+    #[test]
+    fn dot_in_directory_is_not_an_extension() {
+        assert_eq!(
+            ContentType::detect(Some("file:///music/v1.mp4-rips/track"), length()),
+            ContentType::Audio
+        );
+    }
+}
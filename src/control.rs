@@ -0,0 +1,154 @@
+use crate::config::Config;
+use crate::{discovery, seat, PLAYER_INTERFACE};
+use anyhow::{anyhow, bail};
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{Proxy, SyncConnection};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What the player says it can do just now. A player that won't say is
+/// taken to be able to, and left to refuse for itself.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Capabilities {
+    pub can_play: bool,
+    pub can_pause: bool,
+    pub can_seek: bool,
+}
+
+impl Capabilities {
+    pub async fn read(proxy: &Proxy<'_, Arc<SyncConnection>>) -> Self {
+        Capabilities {
+            can_play: can(proxy, "CanPlay").await,
+            can_pause: can(proxy, "CanPause").await,
+            can_seek: can_seek(proxy).await,
+        }
+    }
+}
+
+pub async fn can_seek(proxy: &Proxy<'_, Arc<SyncConnection>>) -> bool {
+    can(proxy, "CanSeek").await
+}
+
+async fn can(proxy: &Proxy<'_, Arc<SyncConnection>>, property: &str) -> bool {
+    proxy
+        .get::<bool>(PLAYER_INTERFACE, property)
+        .await
+        .unwrap_or(true)
+}
+
+/// A playback command passed on from the command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Play,
+    Pause,
+    /// Microseconds to move by, back if negative, as MPRIS takes them.
+    Seek(i64),
+}
+
+impl Command {
+    fn parse(name: &str, mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        match name {
+            "play" => Ok(Command::Play),
+            "pause" => Ok(Command::Pause),
+            "seek" => {
+                let offset = args
+                    .next()
+                    .ok_or_else(|| anyhow!("seek needs a number of seconds"))?;
+                let seconds: i64 = offset
+                    .parse()
+                    .map_err(|_| anyhow!("`{}` isn't a number of seconds", offset))?;
+                let micros = seconds
+                    .checked_mul(1_000_000)
+                    .ok_or_else(|| anyhow!("{} seconds is too far to seek", offset))?;
+                Ok(Command::Seek(micros))
+            }
+            other => bail!("unknown command `{}`", other),
+        }
+    }
+
+    /// Refuses what the player has said it can't do, naming the property
+    /// that says so.
+    fn check(self, capabilities: Capabilities) -> anyhow::Result<()> {
+        let (able, property) = match self {
+            Command::Play => (capabilities.can_play, "CanPlay"),
+            Command::Pause => (capabilities.can_pause, "CanPause"),
+            Command::Seek(_) => (capabilities.can_seek, "CanSeek"),
+        };
+        match able {
+            true => Ok(()),
+            false => bail!("the player can't do that just now ({} is false)", property),
+        }
+    }
+
+    async fn send(self, proxy: &Proxy<'_, Arc<SyncConnection>>) -> Result<(), dbus::Error> {
+        match self {
+            Command::Play => proxy.method_call(PLAYER_INTERFACE, "Play", ()).await,
+            Command::Pause => proxy.method_call(PLAYER_INTERFACE, "Pause", ()).await,
+            Command::Seek(micros) => proxy.method_call(PLAYER_INTERFACE, "Seek", (micros,)).await,
+        }
+    }
+}
+
+/// Runs `play`, `pause` or `seek <seconds>` against the player.
+pub async fn run(
+    config: &Config,
+    name: &str,
+    args: impl Iterator<Item = String>,
+) -> anyhow::Result<()> {
+    let command = Command::parse(name, args)?;
+    let (resource, conn) = seat::connect(config.session_bus).await?;
+    let resource = tokio::spawn(resource);
+    let result = async {
+        let name = discovery::find(&conn, config)
+            .await?
+            .ok_or_else(|| anyhow!("no player is running"))?;
+        let proxy = Proxy::new(
+            name,
+            "/org/mpris/MediaPlayer2",
+            Duration::from_secs(config.resilience.dbus_timeout),
+            conn.clone(),
+        );
+        command.check(Capabilities::read(&proxy).await)?;
+        Ok(command.send(&proxy).await?)
+    }
+    .await;
+    resource.abort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(a: &[&str]) -> impl Iterator<Item = String> {
+        a.iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn seek_takes_signed_seconds() {
+        assert_eq!(
+            Command::parse("seek", args(&["-10"])).unwrap(),
+            Command::Seek(-10_000_000)
+        );
+        assert!(Command::parse("seek", args(&[])).is_err());
+        let err = Command::parse("seek", args(&["9223372036855"])).unwrap_err();
+        assert!(err.to_string().contains("too far"));
+        assert!(Command::parse("seek", args(&["soon"])).is_err());
+        assert!(Command::parse("next", args(&[])).is_err());
+    }
+
+    #[test]
+    fn refusal_names_the_capability() {
+        let capabilities = Capabilities {
+            can_play: true,
+            can_pause: true,
+            can_seek: false,
+        };
+        assert!(Command::Pause.check(capabilities).is_ok());
+        let err = Command::Seek(5).check(capabilities).unwrap_err();
+        assert!(err.to_string().contains("CanSeek is false"));
+    }
+}
@@ -0,0 +1,122 @@
+use crate::paths;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// This many crashes within `WINDOW` and the next start is in safe mode.
+const LIMIT: usize = 3;
+const WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Which run of the daemon is going, and when recent ones ended without
+/// exiting cleanly.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Record {
+    /// The pid of the run going now, or that was when it crashed.
+    running: Option<u32>,
+    /// When crashes were noticed, in unix seconds.
+    crashes: Vec<u64>,
+}
+
+impl Record {
+    /// Takes over from whichever run came before, counting it as a crash if
+    /// it's gone without saying so. Gives whether to start in safe mode.
+    fn start(&mut self, pid: u32, now: u64, alive: impl Fn(u32) -> bool) -> bool {
+        if self
+            .running
+            .is_some_and(|previous| previous != pid && !alive(previous))
+        {
+            self.crashes.push(now);
+        }
+        self.crashes
+            .retain(|&crashed| now.saturating_sub(crashed) < WINDOW.as_secs());
+        self.running = Some(pid);
+        self.crashes.len() >= LIMIT
+    }
+}
+
+/// Marks this run as going until `exited` is called; a run that never
+/// calls it is taken for a crash by the next.
+pub struct Guard {
+    path: PathBuf,
+    record: Record,
+}
+
+/// Notes that the daemon has started, giving whether it's crashed so often
+/// lately that it should start in safe mode.
+pub fn start() -> anyhow::Result<(Guard, bool)> {
+    let path = path().context("no state directory")?;
+    let mut record = read_from(&path);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let safe = record.start(std::process::id(), now, |pid| {
+        Path::new(&format!("/proc/{}", pid)).exists()
+    });
+    write_to(&path, &record)?;
+    Ok((Guard { path, record }, safe))
+}
+
+impl Guard {
+    pub fn exited(mut self) {
+        self.record.running = None;
+        let _ = write_to(&self.path, &self.record);
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    paths::state_dir().map(|dir| dir.join("crashes.json"))
+}
+
+fn read_from(path: &Path) -> Record {
+    std::fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default()
+}
+
+fn write_to(path: &Path, record: &Record) -> anyhow::Result<()> {
+    paths::write_atomic(path, serde_json::to_vec(record)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn dead(_: u32) -> bool {
+        false
+    }
+
+    #[test]
+    fn repeated_crashes_start_safe_mode() {
+        let mut record = Record::default();
+        assert!(!record.start(10, NOW, dead));
+        assert!(!record.start(11, NOW + 10, dead));
+        assert!(!record.start(12, NOW + 20, dead));
+        assert!(record.start(13, NOW + 30, dead));
+        assert_eq!(record.crashes.len(), 3);
+    }
+
+    #[test]
+    fn clean_exits_and_old_crashes_dont_count() {
+        let mut record = Record {
+            running: None,
+            crashes: vec![NOW - 3600, NOW - 60, NOW - 30],
+        };
+        assert!(!record.start(10, NOW, dead));
+        assert_eq!(record.crashes, [NOW - 60, NOW - 30]);
+    }
+
+    #[test]
+    fn a_run_still_going_isnt_a_crash() {
+        let mut record = Record {
+            running: Some(10),
+            crashes: Vec::new(),
+        };
+        record.start(11, NOW, |pid| pid == 10);
+        assert!(record.crashes.is_empty());
+    }
+}
@@ -0,0 +1,119 @@
+use crate::events::PlayerState;
+use std::fmt::Display;
+
+/// One field that differs between two readings.
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    field: &'static str,
+    from: String,
+    to: String,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shown = |value: &str| match value {
+            "" => "∅".to_owned(),
+            value => value.to_owned(),
+        };
+        write!(
+            f,
+            "{}: {} → {}",
+            self.field,
+            shown(&self.from),
+            shown(&self.to)
+        )
+    }
+}
+
+fn fields(state: Option<&PlayerState>) -> [(&'static str, String); 5] {
+    let track = state.and_then(|state| state.track.as_ref());
+    let field = |get: fn(&crate::MediaInfo) -> &String| track.map(get).cloned().unwrap_or_default();
+    [
+        (
+            "status",
+            state
+                .map(|state| format!("{:?}", state.status))
+                .unwrap_or_default(),
+        ),
+        ("title", field(|mi| &mi.title)),
+        ("artist", field(|mi| &mi.artist)),
+        ("album", field(|mi| &mi.album)),
+        ("player", field(|mi| &mi.player)),
+    ]
+}
+
+/// What's different about `next`, field by field. Compared with no
+/// reading at all, every field with a value has changed.
+pub fn changes(previous: Option<&PlayerState>, next: &PlayerState) -> Vec<Change> {
+    fields(previous)
+        .into_iter()
+        .zip(fields(Some(next)))
+        .filter(|((_, from), (_, to))| from != to)
+        .map(|((field, from), (_, to))| Change { field, from, to })
+        .collect()
+}
+
+/// The changes as one line, like `title: A → B, status: Playing → Paused`,
+/// or nothing when there are none.
+pub fn describe(previous: Option<&PlayerState>, next: &PlayerState) -> Option<String> {
+    let changes = changes(previous, next);
+    (!changes.is_empty()).then(|| {
+        changes
+            .iter()
+            .map(Change::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MediaInfo, PlaybackStatus};
+
+    fn playing(title: &str, status: PlaybackStatus) -> PlayerState {
+        PlayerState {
+            track: Some(MediaInfo {
+                title: title.to_owned(),
+                artist: "Joni Mitchell".to_owned(),
+                ..Default::default()
+            }),
+            status,
+            progress: None,
+        }
+    }
+
+    #[test]
+    fn only_changed_fields_are_described() {
+        let before = playing("River", PlaybackStatus::Playing);
+        let after = playing("Blue", PlaybackStatus::Paused);
+        assert_eq!(
+            describe(Some(&before), &after).as_deref(),
+            Some("status: Playing → Paused, title: River → Blue")
+        );
+    }
+
+    #[test]
+    fn identical_readings_have_nothing_to_say() {
+        let state = playing("River", PlaybackStatus::Playing);
+        assert_eq!(describe(Some(&state), &state), None);
+    }
+
+    #[test]
+    fn first_reading_describes_what_it_has() {
+        assert_eq!(
+            describe(None, &playing("River", PlaybackStatus::Playing)).as_deref(),
+            Some("status: ∅ → Playing, title: ∅ → River, artist: ∅ → Joni Mitchell")
+        );
+    }
+
+    #[test]
+    fn stopping_empties_the_track() {
+        let before = playing("River", PlaybackStatus::Playing);
+        let after = PlayerState::not_playing(PlaybackStatus::Stopped);
+        assert_eq!(
+            describe(Some(&before), &after).as_deref(),
+            Some("status: Playing → Stopped, title: River → ∅, artist: Joni Mitchell → ∅")
+        );
+    }
+}
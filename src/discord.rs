@@ -0,0 +1,210 @@
+use crate::config::Application;
+use crate::ipc::Client;
+use crate::presence::{Activity, DiscordClient};
+use crate::MediaInfo;
+use log::{debug, info};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The application ID to present as: the first rule matching the track, or
+/// `default` when none do.
+pub fn application_id(rules: &[Application], default: u64, mi: &MediaInfo) -> u64 {
+    rules
+        .iter()
+        .find(|rule| {
+            rule.player
+                .as_ref()
+                .is_none_or(|player| *player == mi.player)
+                && rule.content.is_none_or(|content| content == mi.content)
+                && rule
+                    .genre
+                    .as_ref()
+                    .is_none_or(|pattern| mi.genres.iter().any(|genre| pattern.is_match(genre)))
+        })
+        .map_or(default, |rule| rule.client_id)
+}
+
+/// How the client's link to Discord has been going.
+#[derive(Debug, Default)]
+struct Health {
+    /// When connecting started failing, if it hasn't succeeded since.
+    failing_since: Option<Instant>,
+}
+
+impl Health {
+    fn ready(&mut self) {
+        self.failing_since = None;
+    }
+
+    fn failed(&mut self, now: Instant) {
+        self.failing_since.get_or_insert(now);
+    }
+
+    fn is_dead(&self, now: Instant, window: Duration) -> bool {
+        self.failing_since
+            .is_some_and(|since| now.saturating_duration_since(since) >= window)
+    }
+}
+
+/// A Discord IPC client that can be reconnected under a different
+/// application ID.
+pub struct Connection {
+    client: Client,
+    health: Health,
+}
+
+impl Connection {
+    /// Connects as `client_id`, giving Discord `handshake_timeout` to answer
+    /// each time. Not getting through is only noted, to be tried again.
+    pub fn start(client_id: u64, handshake_timeout: Duration) -> Self {
+        let mut connection = Connection {
+            client: Client::new(client_id, handshake_timeout),
+            health: Health::default(),
+        };
+        connection.is_ready();
+        connection
+    }
+
+    /// Whether Discord has taken the handshake, trying it again if it
+    /// hasn't.
+    pub fn is_ready(&mut self) -> bool {
+        if self.client.is_connected() {
+            return true;
+        }
+        let connected = self.client.connect();
+        self.noted(connected).is_ok()
+    }
+
+    /// Whether the client has failed to reach Discord since it last
+    /// handshook. Updates sent meanwhile would wait for it to come back.
+    pub fn is_failing(&self) -> bool {
+        self.health.failing_since.is_some()
+    }
+
+    /// Whether the client has been failing for `window`, so that starting
+    /// over may be what it takes.
+    pub fn is_dead(&self, window: Duration) -> bool {
+        self.health.is_dead(Instant::now(), window)
+    }
+
+    /// Reconnects as `client_id` if not already connected as it, waiting
+    /// for the new handshake so the next update isn't lost.
+    pub async fn switch_to(&mut self, client_id: u64) {
+        if client_id == self.client.client_id() {
+            return;
+        }
+        info!(
+            "switching Discord application {} -> {}",
+            self.client.client_id(),
+            client_id
+        );
+        let _ = self.client.clear_activity();
+        self.client.disconnect();
+        let timeout = self.client.timeout();
+        self.client = Client::new(client_id, timeout);
+        self.is_ready();
+    }
+
+    /// Drops the connection and makes a new one under the same
+    /// application.
+    pub async fn restart(&mut self) {
+        self.client.disconnect();
+        self.is_ready();
+    }
+
+    /// Keeps track of how getting through to Discord is going.
+    fn noted<T>(&mut self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        match &result {
+            Ok(_) => self.health.ready(),
+            Err(e) => {
+                debug!("couldn't get through to Discord: {}", e);
+                self.health.failed(Instant::now());
+            }
+        }
+        result
+    }
+}
+
+impl DiscordClient for Connection {
+    fn set_activity(&mut self, activity: Activity) -> anyhow::Result<()> {
+        let sent = self.client.set_activity(&activity.into());
+        self.noted(sent)
+    }
+
+    fn clear_activity(&mut self) -> anyhow::Result<()> {
+        let cleared = self.client.clear_activity();
+        self.noted(cleared)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::ContentType;
+    use regex::Regex;
+
+    fn rule(client_id: u64, player: Option<&str>, genre: Option<&str>) -> Application {
+        Application {
+            client_id,
+            player: player.map(str::to_owned),
+            genre: genre.map(|genre| Regex::new(genre).unwrap()),
+            content: None,
+        }
+    }
+
+    fn track(player: &str, genre: &str) -> MediaInfo {
+        MediaInfo {
+            player: player.to_owned(),
+            genres: vec![genre.to_owned()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn default_without_matching_rule() {
+        let rules = [rule(2, Some("mpv"), None)];
+        assert_eq!(application_id(&rules, 1, &track("Audacious", "Rock")), 1);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = [rule(2, None, Some("Rock")), rule(3, Some("mpv"), None)];
+        assert_eq!(application_id(&rules, 1, &track("mpv", "Rock")), 2);
+        assert_eq!(application_id(&rules, 1, &track("mpv", "Jazz")), 3);
+    }
+
+    #[test]
+    fn content_type_can_pick_application() {
+        let rules = [Application {
+            content: Some(ContentType::Video),
+            ..rule(2, None, None)
+        }];
+        let video = MediaInfo {
+            content: ContentType::Video,
+            ..Default::default()
+        };
+        assert_eq!(application_id(&rules, 1, &video), 2);
+        assert_eq!(application_id(&rules, 1, &MediaInfo::default()), 1);
+    }
+
+    #[test]
+    fn dead_once_failing_for_the_whole_window() {
+        let start = Instant::now();
+        let window = Duration::from_secs(20);
+        let mut health = Health::default();
+        assert!(!health.is_dead(start + window, window));
+        health.failed(start);
+        health.failed(start + Duration::from_secs(15));
+        assert!(!health.is_dead(start + Duration::from_secs(19), window));
+        assert!(health.is_dead(start + window, window));
+        health.ready();
+        assert!(!health.is_dead(start + window * 2, window));
+    }
+
+    #[test]
+    fn all_conditions_must_match() {
+        let rules = [rule(2, Some("mpv"), Some("Rock"))];
+        assert_eq!(application_id(&rules, 1, &track("mpv", "Jazz")), 1);
+        assert_eq!(application_id(&rules, 1, &track("Audacious", "Rock")), 1);
+    }
+}
@@ -0,0 +1,387 @@
+use crate::config::{Config, OnClose};
+use crate::flap::Flapping;
+use crate::player;
+use crate::updates::Detector;
+use crate::Trigger;
+use dbus::arg::PropMap;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{Proxy, SyncConnection};
+use log::{debug, info};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The MPRIS players on one bus, and which of them is followed: the first
+/// `player_priority` matches, then whichever most recently started playing,
+/// then the one already followed. When the one followed exits, the next is
+/// followed straight away only with `on_close = "next_player"`; otherwise
+/// it's closed until one of the others starts playing or a new one comes.
+#[derive(Debug)]
+pub struct Players {
+    ignore: Vec<String>,
+    only: Option<Vec<String>>,
+    priority: Vec<String>,
+    /// Each player's bus name, and the unique name of whoever owns it,
+    /// which is what its signals come from.
+    owners: BTreeMap<String, String>,
+    /// When each last started playing, counting every start on the bus.
+    started: HashMap<String, u64>,
+    starts: u64,
+    followed: Option<(String, String)>,
+    next_on_close: bool,
+    /// Those about when the player followed exited, which are passed over
+    /// until they start playing.
+    passed_over: HashSet<String>,
+    flapping: Flapping,
+    /// How long to give the player followed last to come back, once it's
+    /// let go of its name.
+    grace: Duration,
+    /// How each player is found to announce its changes.
+    detectors: HashMap<String, Detector>,
+}
+
+impl Players {
+    pub fn new(config: &Config) -> Self {
+        Players {
+            ignore: config.ignore_players.clone(),
+            only: config.only_players.clone(),
+            priority: config.player_priority.clone(),
+            owners: BTreeMap::new(),
+            started: HashMap::new(),
+            starts: 0,
+            followed: None,
+            next_on_close: config.on_close == OnClose::NextPlayer,
+            passed_over: HashSet::new(),
+            flapping: Flapping::new(
+                Duration::from_secs(config.resilience.vanish_grace),
+                Duration::from_secs(config.resilience.flap_cooldown),
+            ),
+            grace: Duration::from_secs(config.resilience.vanish_grace),
+            detectors: HashMap::new(),
+        }
+    }
+
+    /// How long the player that was followed has to come back before it's
+    /// taken as closed.
+    pub fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    /// How `name` is found to announce its changes, which is worked out
+    /// afresh each time a player takes the name.
+    pub fn detector(&mut self, name: &str) -> &mut Detector {
+        self.detectors.entry(name.to_owned()).or_default()
+    }
+
+    /// The bus name of the player followed, if there's one to follow.
+    pub fn followed(&self) -> Option<&str> {
+        self.followed.as_ref().map(|(name, _)| name.as_str())
+    }
+
+    /// Whether a signal from `sender`, a unique name, is from the player
+    /// followed, rather than from any of the others about.
+    pub fn follows(&self, sender: &str) -> bool {
+        self.followed
+            .as_ref()
+            .is_some_and(|(_, owner)| owner == sender)
+    }
+
+    /// `name` changed hands, to `owner`, or to no one when that's empty.
+    /// Gives what to tell the reader if it changes who's followed.
+    pub fn owner_changed(&mut self, name: &str, owner: &str) -> Option<Trigger> {
+        if !player::is_player(name) || player::is_excluded(&self.ignore, self.only.as_deref(), name)
+        {
+            return None;
+        }
+        match owner.is_empty() {
+            true => {
+                self.owners.remove(name);
+                self.started.remove(name);
+                self.detectors.remove(name);
+                self.passed_over.remove(name);
+                let grace = self.flapping.vanished(name, Instant::now());
+                if self.followed() == Some(name) {
+                    self.grace = grace;
+                    if !self.next_on_close {
+                        self.passed_over = self.owners.keys().cloned().collect();
+                    }
+                }
+            }
+            false => {
+                self.owners.insert(name.to_owned(), owner.to_owned());
+            }
+        }
+        self.refollow()
+    }
+
+    /// The player at `sender` says it started playing, which may be reason
+    /// enough to follow it.
+    pub fn started_playing(&mut self, sender: &str) -> Option<Trigger> {
+        let name = self
+            .owners
+            .iter()
+            .find(|(_, owner)| *owner == sender)?
+            .0
+            .clone();
+        self.starts += 1;
+        self.passed_over.remove(&name);
+        self.started.insert(name, self.starts);
+        self.refollow()
+    }
+
+    fn rank(&self, name: &str) -> impl Ord {
+        (
+            listed(&self.priority, name),
+            self.started.get(name).copied(),
+            self.followed() == Some(name),
+        )
+    }
+
+    fn refollow(&mut self) -> Option<Trigger> {
+        let choice = self
+            .owners
+            .iter()
+            // Reversed, so that among equals the first by name wins.
+            .rev()
+            .filter(|(name, _)| !self.passed_over.contains(*name))
+            .max_by_key(|(name, _)| self.rank(name))
+            .map(|(name, owner)| (name.clone(), owner.clone()));
+        if choice == self.followed {
+            return None;
+        }
+        let before = std::mem::replace(&mut self.followed, choice);
+        match &self.followed {
+            Some((name, _)) => {
+                if before.as_ref().is_some_and(|(was, _)| was != name) {
+                    info!(player = name.as_str(); "following {} now", name);
+                }
+                Some(Trigger::Appeared)
+            }
+            None => Some(Trigger::Vanished),
+        }
+    }
+}
+
+/// Where `name` comes in `player_priority`, the first listed ranking
+/// highest and those not listed lowest.
+pub fn listed(priority: &[String], name: &str) -> Reverse<usize> {
+    let position = priority
+        .iter()
+        .position(|pattern| player::glob_match(pattern.as_bytes(), name.as_bytes()));
+    Reverse(position.unwrap_or(usize::MAX))
+}
+
+/// Whether a player's `PropertiesChanged` says it's started playing.
+pub fn says_playing(changed: &PropMap) -> bool {
+    changed
+        .get("PlaybackStatus")
+        .and_then(|status| status.0.as_str())
+        == Some("Playing")
+}
+
+/// Finds the players already on the bus, reading whether each is playing
+/// so that one that is can be followed.
+pub async fn discover(
+    conn: &Arc<SyncConnection>,
+    players: &Mutex<Players>,
+    timeout: Duration,
+) -> Result<(), dbus::Error> {
+    let bus = Proxy::new(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        timeout,
+        conn.clone(),
+    );
+    let (names,): (Vec<String>,) = bus
+        .method_call("org.freedesktop.DBus", "ListNames", ())
+        .await?;
+    for name in names.into_iter().filter(|name| player::is_player(name)) {
+        let owner: Result<(String,), _> = bus
+            .method_call("org.freedesktop.DBus", "GetNameOwner", (&name,))
+            .await;
+        // Gone again already.
+        let Ok((owner,)) = owner else { continue };
+        let player = Proxy::new(
+            name.as_str(),
+            "/org/mpris/MediaPlayer2",
+            timeout,
+            conn.clone(),
+        );
+        let status: Option<String> = player
+            .get(crate::PLAYER_INTERFACE, "PlaybackStatus")
+            .await
+            .ok();
+        debug!("found {}, {:?}", name, status);
+        let mut players = players.lock().unwrap();
+        players.owner_changed(&name, &owner);
+        if status.as_deref() == Some("Playing") {
+            players.started_playing(&owner);
+        }
+    }
+    Ok(())
+}
+
+/// The player to follow on the bus right now, for commands run once.
+pub async fn find(
+    conn: &Arc<SyncConnection>,
+    config: &Config,
+) -> Result<Option<String>, dbus::Error> {
+    let players = Mutex::new(Players::new(config));
+    let timeout = Duration::from_secs(config.resilience.dbus_timeout);
+    discover(conn, &players, timeout).await?;
+    let followed = players.into_inner().unwrap().followed;
+    Ok(followed.map(|(name, _)| name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUDACIOUS: &str = "org.mpris.MediaPlayer2.audacious";
+    const SPOTIFY: &str = "org.mpris.MediaPlayer2.spotify";
+    const FIREFOX: &str = "org.mpris.MediaPlayer2.firefox.instance_1_7";
+
+    fn players(config: &str) -> Players {
+        Players::new(&crate::config::parse(config).unwrap())
+    }
+
+    #[test]
+    fn first_player_found_is_followed() {
+        let mut players = players("");
+        assert_eq!(
+            players.owner_changed(SPOTIFY, ":1.7"),
+            Some(Trigger::Appeared)
+        );
+        assert_eq!(players.owner_changed(AUDACIOUS, ":1.9"), None);
+        assert_eq!(players.followed(), Some(SPOTIFY));
+        assert!(players.follows(":1.7"));
+        assert!(!players.follows(":1.9"));
+        assert_eq!(
+            players.owner_changed("org.freedesktop.Notifications", ":1.2"),
+            None
+        );
+    }
+
+    #[test]
+    fn latest_to_start_playing_is_followed() {
+        let mut players = players("");
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.owner_changed(FIREFOX, ":1.12");
+        assert_eq!(players.followed(), Some(AUDACIOUS));
+        assert_eq!(players.started_playing(":1.12"), Some(Trigger::Appeared));
+        assert_eq!(players.followed(), Some(FIREFOX));
+        assert_eq!(players.started_playing(":1.9"), Some(Trigger::Appeared));
+        assert_eq!(players.followed(), Some(AUDACIOUS));
+        assert_eq!(players.started_playing(":1.404"), None);
+    }
+
+    #[test]
+    fn priority_beats_playing() {
+        let mut players = players("player_priority = [\"org.mpris.MediaPlayer2.spot*\"]\n");
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.started_playing(":1.9");
+        players.owner_changed(SPOTIFY, ":1.7");
+        assert_eq!(players.followed(), Some(SPOTIFY));
+        assert_eq!(players.started_playing(":1.9"), None);
+    }
+
+    #[test]
+    fn grace_is_for_the_player_that_left() {
+        let mut players = players("[resilience]\nvanish_grace = 2\nflap_cooldown = 30\n");
+        for owner in [":1.12", ":1.13", ":1.14"] {
+            players.owner_changed(FIREFOX, owner);
+            players.owner_changed(FIREFOX, "");
+        }
+        assert_eq!(players.grace(), Duration::from_secs(30));
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.owner_changed(AUDACIOUS, "");
+        assert_eq!(players.grace(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn each_player_is_detected_on_its_own() {
+        let mut players = players("");
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.owner_changed(SPOTIFY, ":1.7");
+        players.detector(AUDACIOUS).signalled();
+        assert!(!players.detector(AUDACIOUS).polls());
+        assert!(players.detector(SPOTIFY).polls());
+        // A player taking the name again may behave differently.
+        players.owner_changed(AUDACIOUS, "");
+        players.owner_changed(AUDACIOUS, ":1.10");
+        assert!(players.detector(AUDACIOUS).polls());
+    }
+
+    #[test]
+    fn losing_the_player_moves_on_or_vanishes() {
+        let mut players = players(
+            "on_close = \"next_player\"\nignore_players = [\"org.mpris.MediaPlayer2.firefox.*\"]\n",
+        );
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.owner_changed(FIREFOX, ":1.12");
+        players.owner_changed(SPOTIFY, ":1.7");
+        players.started_playing(":1.9");
+        assert_eq!(
+            players.owner_changed(AUDACIOUS, ""),
+            Some(Trigger::Appeared)
+        );
+        assert_eq!(players.followed(), Some(SPOTIFY));
+        assert_eq!(players.owner_changed(SPOTIFY, ""), Some(Trigger::Vanished));
+        assert_eq!(players.followed(), None);
+    }
+
+    #[test]
+    fn next_player_goes_by_priority() {
+        let mut players = players(
+            "on_close = \"next_player\"\nplayer_priority = [\"org.mpris.MediaPlayer2.firefox.*\", \"org.mpris.MediaPlayer2.spotify\"]\n",
+        );
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.owner_changed(SPOTIFY, ":1.7");
+        players.owner_changed(FIREFOX, ":1.12");
+        players.owner_changed(FIREFOX, "");
+        assert_eq!(players.followed(), Some(SPOTIFY));
+    }
+
+    #[test]
+    fn losing_the_player_is_closed_until_another_plays() {
+        let mut players = players("");
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.owner_changed(SPOTIFY, ":1.7");
+        players.started_playing(":1.9");
+        assert_eq!(
+            players.owner_changed(AUDACIOUS, ""),
+            Some(Trigger::Vanished)
+        );
+        assert_eq!(players.followed(), None);
+        assert_eq!(players.started_playing(":1.7"), Some(Trigger::Appeared));
+        assert_eq!(players.followed(), Some(SPOTIFY));
+    }
+
+    #[test]
+    fn player_arriving_after_a_close_is_followed() {
+        let mut players = players("");
+        players.owner_changed(SPOTIFY, ":1.7");
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.owner_changed(SPOTIFY, "");
+        assert_eq!(players.followed(), None);
+        assert_eq!(
+            players.owner_changed(FIREFOX, ":1.12"),
+            Some(Trigger::Appeared)
+        );
+        assert_eq!(players.followed(), Some(FIREFOX));
+    }
+
+    #[test]
+    fn restarting_is_a_new_player() {
+        let mut players = players("");
+        players.owner_changed(AUDACIOUS, ":1.9");
+        players.owner_changed(AUDACIOUS, "");
+        assert_eq!(
+            players.owner_changed(AUDACIOUS, ":1.30"),
+            Some(Trigger::Appeared)
+        );
+        assert!(players.follows(":1.30"));
+    }
+}
@@ -0,0 +1,116 @@
+use dbus::arg::{PropMap, RefArg};
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::time::Duration;
+
+/// A span of playback time. MPRIS reports `mpris:length` and `Position` as
+/// signed microseconds, which this keeps non-negative.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TrackDuration(Duration);
+
+impl TrackDuration {
+    /// `None` for negative values, which some players use to mean "unknown".
+    pub fn from_micros(micros: i64) -> Option<Self> {
+        u64::try_from(micros)
+            .ok()
+            .map(|micros| TrackDuration(Duration::from_micros(micros)))
+    }
+
+    /// Reads a microsecond property, accepting any integer type since players
+    /// don't agree on `x` (int64) versus `t` (uint64).
+    pub fn from_prop(props: &PropMap, key: &str) -> Option<Self> {
+        Self::from_refarg(&props.get(key)?.0)
+    }
+
+    pub fn from_refarg(value: &dyn RefArg) -> Option<Self> {
+        let micros = value
+            .as_i64()
+            .or_else(|| value.as_u64().and_then(|u| i64::try_from(u).ok()))?;
+        Self::from_micros(micros)
+    }
+
+    pub fn as_duration(self) -> Duration {
+        self.0
+    }
+}
+
+impl From<Duration> for TrackDuration {
+    fn from(duration: Duration) -> Self {
+        TrackDuration(duration)
+    }
+}
+
+/// Formats as `m:ss`, or `h:mm:ss` from an hour up.
+impl Display for TrackDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.0.as_secs();
+        let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+        match hours {
+            0 => write!(f, "{}:{:02}", minutes, seconds),
+            _ => write!(f, "{}:{:02}:{:02}", hours, minutes, seconds),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dbus::arg::Variant;
+
+    fn props(value: Box<dyn RefArg>) -> PropMap {
+        PropMap::from([("mpris:length".to_owned(), Variant(value))])
+    }
+
+    #[test]
+    fn formats_minutes_and_seconds() {
+        let duration = TrackDuration::from_micros(225_000_000).unwrap();
+        assert_eq!(duration.to_string(), "3:45");
+    }
+
+    #[test]
+    fn formats_hours_with_padded_minutes() {
+        let duration = TrackDuration::from_micros(3_737_000_000).unwrap();
+        assert_eq!(duration.to_string(), "1:02:17");
+    }
+
+    #[test]
+    fn truncates_partial_seconds() {
+        let duration = TrackDuration::from_micros(59_999_999).unwrap();
+        assert_eq!(duration.to_string(), "0:59");
+    }
+
+    #[test]
+    fn negative_micros_are_rejected() {
+        assert_eq!(TrackDuration::from_micros(-1), None);
+    }
+
+    #[test]
+    fn reads_int64_and_uint64_props() {
+        let expected = TrackDuration::from_micros(1_000_000);
+        assert_eq!(
+            TrackDuration::from_prop(&props(Box::new(1_000_000i64)), "mpris:length"),
+            expected
+        );
+        assert_eq!(
+            TrackDuration::from_prop(&props(Box::new(1_000_000u64)), "mpris:length"),
+            expected
+        );
+    }
+
+    #[test]
+    fn uint64_beyond_int64_range_is_rejected() {
+        let props = props(Box::new(u64::MAX));
+        assert_eq!(TrackDuration::from_prop(&props, "mpris:length"), None);
+    }
+
+    #[test]
+    fn missing_or_mistyped_prop_is_none() {
+        assert_eq!(
+            TrackDuration::from_prop(&PropMap::new(), "mpris:length"),
+            None
+        );
+        let props = props(Box::new("3:45".to_owned()));
+        assert_eq!(TrackDuration::from_prop(&props, "mpris:length"), None);
+    }
+}
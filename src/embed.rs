@@ -0,0 +1,256 @@
+//! The MPRIS → presence pipeline on its own, for programs that want to show
+//! or use what's playing without running the daemon.
+
+use crate::album::AlbumSession;
+use crate::config::Config;
+use crate::discord::Connection;
+use crate::enrich::Enrichment;
+use crate::events::PlayerState;
+use crate::overrides::Overrides;
+use crate::position::{PositionTracker, Progress};
+use crate::template::Template;
+use crate::toggles::Toggles;
+use crate::{presence, read_player, seat, MediaInfo, PlaybackStatus};
+use dbus::message::MatchRule;
+use dbus::nonblock::{MsgMatch, Proxy, SyncConnection};
+use futures::prelude::*;
+use log::{debug, warn};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinHandle;
+
+/// What a player was doing when it was read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reading(PlayerState);
+
+impl Reading {
+    /// A reading from somewhere other than MPRIS, `position` into the track
+    /// as of now.
+    pub fn new(
+        track: Option<MediaInfo>,
+        status: PlaybackStatus,
+        position: Option<Duration>,
+    ) -> Self {
+        let progress = position.map(|position| Progress {
+            position: position.into(),
+            rate: 1.0,
+            at: SystemTime::now(),
+        });
+        Reading(PlayerState {
+            track,
+            status,
+            progress,
+        })
+    }
+
+    pub fn track(&self) -> Option<&MediaInfo> {
+        self.0.track.as_ref()
+    }
+
+    pub fn status(&self) -> PlaybackStatus {
+        self.0.status
+    }
+
+    /// How far into the track the player is now, counting on from the
+    /// reading while it plays.
+    pub fn position(&self) -> Option<Duration> {
+        let playing = self.0.status == PlaybackStatus::Playing;
+        self.0.progress.map(|progress| {
+            progress
+                .position_at(SystemTime::now(), playing)
+                .as_duration()
+        })
+    }
+
+    /// Fills in `template` with the track, or `None` when nothing's loaded.
+    pub fn format(&self, template: &Template) -> Option<String> {
+        self.track()
+            .map(|mi| mi.render(template, self.0.status, self.0.progress.as_ref()))
+    }
+}
+
+/// Somewhere readings of a player come from.
+pub trait MediaSource {
+    /// Waits for the player to change and reads it; `None` once there'll be
+    /// no more readings. The first call reads it straight away.
+    fn next(&mut self) -> impl Future<Output = Option<Reading>> + Send;
+}
+
+/// Somewhere readings are shown.
+pub trait PresenceSink {
+    fn show(&mut self, reading: &Reading) -> anyhow::Result<()>;
+}
+
+/// An MPRIS player on the session bus, read whenever its properties change.
+pub struct Mpris {
+    proxy: Proxy<'static, Arc<SyncConnection>>,
+    changes: Pin<Box<dyn Stream<Item = ()> + Send>>,
+    _signal: MsgMatch,
+    tracker: Mutex<PositionTracker>,
+    config: Config,
+    read_yet: bool,
+    resource: JoinHandle<()>,
+}
+
+impl Mpris {
+    /// Follows the player that owns `org.mpris.MediaPlayer2.<player>`, such
+    /// as `audacious` or `vlc`.
+    pub async fn connect(player: &str) -> anyhow::Result<Self> {
+        let config = Config::default();
+        let (resource, conn) = seat::connect(config.session_bus).await?;
+        let resource = tokio::spawn(async move {
+            let e = resource.await;
+            warn!("lost the D-Bus connection: {}", e);
+        });
+        let destination = format!("org.mpris.MediaPlayer2.{}", player);
+        let rule = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+            .with_path("/org/mpris/MediaPlayer2")
+            .with_sender(destination.clone());
+        let (signal, changes) = conn.add_match(rule).await?.msg_stream();
+        let proxy = Proxy::new(
+            destination,
+            "/org/mpris/MediaPlayer2",
+            Duration::from_secs(config.resilience.dbus_timeout),
+            conn,
+        );
+        Ok(Mpris {
+            proxy,
+            changes: changes.map(|_| ()).boxed(),
+            _signal: signal,
+            tracker: Mutex::new(PositionTracker::default()),
+            config,
+            read_yet: false,
+            resource,
+        })
+    }
+}
+
+impl MediaSource for Mpris {
+    async fn next(&mut self) -> Option<Reading> {
+        loop {
+            if std::mem::replace(&mut self.read_yet, true) {
+                self.changes.next().await?;
+            }
+            let state = read_player(
+                &self.config,
+                &Overrides::default(),
+                &self.proxy,
+                &self.tracker,
+            )
+            .await;
+            match state {
+                Ok(state) => return Some(Reading(state)),
+                Err(e) => debug!("couldn't read {}: {}", self.proxy.destination, e),
+            }
+        }
+    }
+}
+
+impl Drop for Mpris {
+    fn drop(&mut self) {
+        self.resource.abort();
+    }
+}
+
+/// A Discord presence, shown as the daemon would with its default config.
+pub struct Discord {
+    connection: Connection,
+    config: Config,
+    album: AlbumSession,
+}
+
+impl Discord {
+    /// Connects to the Discord client as the application `client_id`.
+    pub fn new(client_id: u64) -> Self {
+        let config = Config::default();
+        let handshake = Duration::from_secs(config.resilience.discord_handshake);
+        Discord {
+            connection: Connection::start(client_id, handshake),
+            config,
+            album: AlbumSession::default(),
+        }
+    }
+}
+
+impl PresenceSink for Discord {
+    fn show(&mut self, reading: &Reading) -> anyhow::Result<()> {
+        self.album
+            .observe(&reading.0, presence::timestamps(&reading.0));
+        presence::publish(
+            &mut self.connection,
+            &reading.0,
+            &self.config,
+            &Toggles::default(),
+            None,
+            &Enrichment::default(),
+            &self.album,
+        )?;
+        Ok(())
+    }
+}
+
+/// Shows every reading from `source` in `sink`, until the source runs dry.
+pub async fn pipe(mut source: impl MediaSource, mut sink: impl PresenceSink) {
+    while let Some(reading) = source.next().await {
+        if let Err(e) = sink.show(&reading) {
+            warn!("couldn't show the reading: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Replay(Vec<Reading>);
+
+    impl MediaSource for Replay {
+        async fn next(&mut self) -> Option<Reading> {
+            match self.0.is_empty() {
+                true => None,
+                false => Some(self.0.remove(0)),
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Recording(Arc<Mutex<Vec<Option<String>>>>);
+
+    impl PresenceSink for Recording {
+        fn show(&mut self, reading: &Reading) -> anyhow::Result<()> {
+            let template = Template::parse("{artist} - {title}")?;
+            self.0.lock().unwrap().push(reading.format(&template));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn pipe_shows_each_reading_in_turn() {
+        let source = Replay(vec![
+            Reading::new(
+                Some(MediaInfo::new("T", "A")),
+                PlaybackStatus::Playing,
+                None,
+            ),
+            Reading::new(None, PlaybackStatus::Stopped, None),
+        ]);
+        let sink = Recording::default();
+        let shown = sink.0.clone();
+        pipe(source, sink).await;
+        assert_eq!(*shown.lock().unwrap(), [Some("A - T".to_owned()), None]);
+    }
+
+    #[test]
+    fn position_holds_while_paused() {
+        let reading = Reading::new(
+            Some(MediaInfo::new("T", "A")),
+            PlaybackStatus::Paused,
+            Some(Duration::from_secs(42)),
+        );
+        assert_eq!(reading.position(), Some(Duration::from_secs(42)));
+        let template = Template::parse("{title} at {position}").unwrap();
+        assert_eq!(reading.format(&template).as_deref(), Some("T at 0:42"));
+    }
+}
@@ -0,0 +1,523 @@
+use crate::color;
+use crate::config::{Config, GenreImage, Presence, Stage};
+use crate::content::ContentType;
+use crate::musicbrainz::{self, Release};
+use crate::odesli::{self, Links};
+use crate::presence;
+use crate::track::TrackKey;
+use crate::validate;
+use crate::MediaInfo;
+use futures::future::{self, BoxFuture};
+use log::debug;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Presentation found for a track beyond what its own metadata says.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Enrichment {
+    pub large_image: Option<String>,
+    pub links: Links,
+    /// The cover's dominant colour, as `#rrggbb`.
+    pub color: Option<String>,
+    /// How many tracks the track's album has.
+    pub album_tracks: Option<u32>,
+}
+
+/// One stage of the enrichment pipeline. Stages run in the configured
+/// order, and each only fills in what earlier stages left empty.
+pub trait Enricher: Send + Sync {
+    fn enrich<'a>(&'a self, track: &'a MediaInfo, found: &'a mut Enrichment) -> BoxFuture<'a, ()>;
+}
+
+/// The configured enrichers, in order.
+pub struct Pipeline(Vec<Box<dyn Enricher>>);
+
+impl Pipeline {
+    pub fn new(config: &Config) -> Self {
+        Pipeline(
+            config
+                .enrichment
+                .stages
+                .iter()
+                .filter_map(|stage| -> Option<Box<dyn Enricher>> {
+                    match stage {
+                        Stage::GenreImages => {
+                            Some(Box::new(GenreImages(config.genre_images.clone())))
+                        }
+                        Stage::CoverArt => musicbrainz::Client::shared().map(|client| {
+                            Box::new(CoverArt {
+                                client,
+                                min_confidence: config.enrichment.min_confidence,
+                                count_tracks: config.presence == Presence::Album,
+                            }) as Box<dyn Enricher>
+                        }),
+                        Stage::StreamingLinks => odesli::Client::shared().map(|odesli| {
+                            Box::new(StreamingLinks {
+                                odesli,
+                                musicbrainz: musicbrainz::Client::shared(),
+                                min_confidence: config.enrichment.min_confidence,
+                            }) as Box<dyn Enricher>
+                        }),
+                        Stage::CoverColor => color::Client::shared()
+                            .map(|client| Box::new(CoverColor(client)) as Box<dyn Enricher>),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    pub async fn run(&self, track: &MediaInfo) -> Enrichment {
+        let mut found = Enrichment::default();
+        for stage in &self.0 {
+            stage.enrich(track, &mut found).await;
+        }
+        found
+    }
+}
+
+/// Runs the pipeline off the publishing path, so the plain presence goes out
+/// straight away and enrichment follows as a second update once it's ready.
+pub struct Background {
+    pipeline: Arc<Pipeline>,
+    tx: mpsc::Sender<(TrackKey, Enrichment)>,
+    rx: mpsc::Receiver<(TrackKey, Enrichment)>,
+    running: Option<(TrackKey, JoinHandle<()>)>,
+    done: Option<(TrackKey, Enrichment)>,
+    /// The large image shown while a lookup is under way.
+    placeholder: Option<String>,
+}
+
+impl Background {
+    pub fn new(pipeline: Pipeline, placeholder: Option<String>) -> Self {
+        let (tx, rx) = mpsc::channel(1);
+        // With nothing to look up, there's nothing to wait for.
+        let placeholder = placeholder.filter(|_| !pipeline.0.is_empty());
+        Background {
+            pipeline: Arc::new(pipeline),
+            tx,
+            rx,
+            running: None,
+            done: None,
+            placeholder,
+        }
+    }
+
+    /// Starts enriching `track` unless that's already under way, abandoning
+    /// any lookup for a previous track.
+    pub fn request(&mut self, track: &MediaInfo) {
+        let key = track.key();
+        if self
+            .running
+            .as_ref()
+            .is_some_and(|(running, _)| *running == key)
+        {
+            return;
+        }
+        if let Some((_, task)) = self.running.take() {
+            task.abort();
+        }
+        let pipeline = self.pipeline.clone();
+        let tx = self.tx.clone();
+        let (owned, sent_key) = (track.clone(), key.clone());
+        let task = tokio::spawn(async move {
+            let found = pipeline.run(&owned).await;
+            let _ = tx.send((sent_key, found)).await;
+        });
+        self.running = Some((key, task));
+    }
+
+    /// Waits until enrichment that adds something arrives for the track
+    /// most recently requested, or any at all once a placeholder is showing
+    /// that it should replace.
+    pub async fn finished(&mut self) {
+        loop {
+            let Some((track, found)) = self.rx.recv().await else {
+                return future::pending().await;
+            };
+            if self
+                .running
+                .as_ref()
+                .is_some_and(|(running, _)| *running == track)
+            {
+                let useful = found != Enrichment::default();
+                self.done = Some((track, found));
+                if useful || self.placeholder.is_some() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// What's known so far about `track`; just the placeholder until its
+    /// lookup finishes.
+    pub fn for_track(&self, track: &MediaInfo) -> Enrichment {
+        let key = track.key();
+        match &self.done {
+            Some((done, found)) if *done == key => found.clone(),
+            _ if self
+                .running
+                .as_ref()
+                .is_some_and(|(running, _)| *running == key) =>
+            {
+                Enrichment {
+                    large_image: self.placeholder.clone(),
+                    ..Default::default()
+                }
+            }
+            _ => Enrichment::default(),
+        }
+    }
+}
+
+/// Picks a large image from the user's genre rules.
+struct GenreImages(Vec<GenreImage>);
+
+impl Enricher for GenreImages {
+    fn enrich<'a>(&'a self, track: &'a MediaInfo, found: &'a mut Enrichment) -> BoxFuture<'a, ()> {
+        if found.large_image.is_none() {
+            found.large_image = genre_image(&self.0, &track.genres).map(str::to_owned);
+        }
+        Box::pin(future::ready(()))
+    }
+}
+
+/// Looks the album up on MusicBrainz and shows its front cover, unless
+/// something shown ahead of it has already been found. For album presence
+/// it also counts the album's tracks.
+struct CoverArt {
+    client: Arc<musicbrainz::Client>,
+    min_confidence: u8,
+    count_tracks: bool,
+}
+
+impl Enricher for CoverArt {
+    fn enrich<'a>(&'a self, track: &'a MediaInfo, found: &'a mut Enrichment) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            let cover = wants_cover(track, found);
+            let count = self.count_tracks && found.album_tracks.is_none() && on_album(track);
+            if !cover && !count {
+                return;
+            }
+            match self
+                .client
+                .search_release(&track.artist, &track.album)
+                .await
+            {
+                Ok(releases) => {
+                    let release = confident(&releases, self.min_confidence);
+                    if cover {
+                        found.large_image = release.map(|release| cover_url(&release.id));
+                    }
+                    if count {
+                        found.album_tracks = release.and_then(|release| release.track_count);
+                    }
+                }
+                Err(e) => debug!("MusicBrainz lookup for {} failed: {}", track, e),
+            }
+        })
+    }
+}
+
+/// Works out the dominant colour of the image the presence shows, when
+/// that's a picture on the web rather than one of the application's assets.
+struct CoverColor(Arc<color::Client>);
+
+impl Enricher for CoverColor {
+    fn enrich<'a>(&'a self, track: &'a MediaInfo, found: &'a mut Enrichment) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if found.color.is_some() {
+                return;
+            }
+            let cover =
+                presence::large_image(track, found).filter(|image| validate::is_web_url(image));
+            let Some(cover) = cover else {
+                return;
+            };
+            match self.0.dominant(&cover).await {
+                Ok(color) => found.color = Some(color),
+                Err(e) => debug!("couldn't find the colour of {}: {}", cover, e),
+            }
+        })
+    }
+}
+
+/// Finds the track on streaming services through Odesli, starting from a
+/// link given in the overrides file, the track's own page when it's
+/// playing from a service, or failing those the page of a MusicBrainz
+/// release confidently matching its album.
+struct StreamingLinks {
+    odesli: Arc<odesli::Client>,
+    musicbrainz: Option<Arc<musicbrainz::Client>>,
+    min_confidence: u8,
+}
+
+impl StreamingLinks {
+    async fn source(&self, track: &MediaInfo) -> Option<String> {
+        if let Some(page) = track.url.as_deref().and_then(odesli::on_service) {
+            return Some(page);
+        }
+        let musicbrainz = self.musicbrainz.as_ref()?;
+        if track.album.is_empty() || track.content != ContentType::Audio {
+            return None;
+        }
+        let releases = musicbrainz
+            .search_release(&track.artist, &track.album)
+            .await
+            .map_err(|e| debug!("MusicBrainz lookup for {} failed: {}", track, e))
+            .ok()?;
+        let release = confident(&releases, self.min_confidence)?;
+        let pages = musicbrainz
+            .streaming_links(&release.id)
+            .await
+            .map_err(|e| debug!("MusicBrainz links for {} failed: {}", release.id, e))
+            .ok()?;
+        pages.iter().find_map(|page| odesli::on_service(page))
+    }
+}
+
+impl Enricher for StreamingLinks {
+    fn enrich<'a>(&'a self, track: &'a MediaInfo, found: &'a mut Enrichment) -> BoxFuture<'a, ()> {
+        Box::pin(async move {
+            if !found.links.is_empty() {
+                return;
+            }
+            let source = match &track.link {
+                // A page Odesli doesn't know is still worth linking to.
+                Some(link) if odesli::on_service(link).is_none() => {
+                    found.links.page = Some(link.clone());
+                    return;
+                }
+                Some(link) => link.clone(),
+                None => match self.source(track).await {
+                    Some(source) => source,
+                    None => return,
+                },
+            };
+            match self.odesli.links(&source).await {
+                Ok(links) => found.links = links,
+                Err(e) => debug!("Odesli lookup for {} failed: {}", track, e),
+            }
+        })
+    }
+}
+
+/// The best match, unless even that is too doubtful to show.
+fn confident(releases: &[Release], min_confidence: u8) -> Option<&Release> {
+    releases
+        .iter()
+        .max_by_key(|release| release.score)
+        .filter(|release| release.score >= min_confidence)
+}
+
+/// Whether a cover from MusicBrainz would be shown: not when the track
+/// brings its own image or an earlier stage found one, which come first.
+fn wants_cover(track: &MediaInfo, found: &Enrichment) -> bool {
+    presence::large_image(track, found).is_none() && on_album(track)
+}
+
+/// Whether the track is music from an album MusicBrainz might know.
+fn on_album(track: &MediaInfo) -> bool {
+    !track.album.is_empty() && track.content == ContentType::Audio
+}
+
+fn cover_url(release_id: &str) -> String {
+    format!(
+        "https://coverartarchive.org/release/{}/front-250",
+        release_id
+    )
+}
+
+fn genre_image<'a>(rules: &'a [GenreImage], genres: &[String]) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|rule| genres.iter().any(|genre| rule.pattern.is_match(genre)))
+        .map(|rule| rule.image.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+
+    #[test]
+    fn genre_image_uses_first_matching_rule() {
+        let config = config::parse(
+            r#"
+            [[genre_images]]
+            pattern = "(?i)metal"
+            image = "metal"
+
+            [[genre_images]]
+            pattern = "(?i)death"
+            image = "death"
+            "#,
+        )
+        .unwrap();
+        let genres = vec!["Pop".to_owned(), "Death Metal".to_owned()];
+        assert_eq!(genre_image(&config.genre_images, &genres), Some("metal"));
+    }
+
+    #[test]
+    fn genre_image_absent_without_match() {
+        let config =
+            config::parse("[[genre_images]]\npattern = \"Jazz\"\nimage = \"jazz\"\n").unwrap();
+        assert_eq!(
+            genre_image(&config.genre_images, &["jazz".to_owned()]),
+            None
+        );
+    }
+
+    fn release(id: &str, score: u8) -> Release {
+        Release {
+            id: id.to_owned(),
+            title: String::new(),
+            score,
+            track_count: None,
+        }
+    }
+
+    #[test]
+    fn best_confident_release_is_chosen() {
+        let releases = [release("a", 92), release("b", 100)];
+        assert_eq!(confident(&releases, 90).map(|r| r.id.as_str()), Some("b"));
+    }
+
+    #[test]
+    fn doubtful_releases_are_ignored() {
+        assert_eq!(confident(&[release("a", 80)], 90), None);
+        assert_eq!(confident(&[], 0), None);
+    }
+
+    struct Fixed(&'static str);
+
+    impl Enricher for Fixed {
+        fn enrich<'a>(&'a self, _: &'a MediaInfo, found: &'a mut Enrichment) -> BoxFuture<'a, ()> {
+            found.large_image.get_or_insert_with(|| self.0.to_owned());
+            Box::pin(future::ready(()))
+        }
+    }
+
+    #[test]
+    fn cover_wanted_only_when_nothing_shows_ahead_of_it() {
+        let track = MediaInfo {
+            album: "Blue".to_owned(),
+            ..Default::default()
+        };
+        assert!(wants_cover(&track, &Enrichment::default()));
+        let with_art = MediaInfo {
+            art_url: Some("https://example.com/blue.jpg".to_owned()),
+            ..track.clone()
+        };
+        assert!(!wants_cover(&with_art, &Enrichment::default()));
+        // A local file's art can't be shown, so the cover is still wanted.
+        let with_local_art = MediaInfo {
+            art_url: Some("file:///music/blue.jpg".to_owned()),
+            ..track.clone()
+        };
+        assert!(wants_cover(&with_local_art, &Enrichment::default()));
+        let found = Enrichment {
+            large_image: Some("jazz".to_owned()),
+            ..Default::default()
+        };
+        assert!(!wants_cover(&track, &found));
+    }
+
+    #[tokio::test]
+    async fn earlier_stages_win() {
+        let pipeline = Pipeline(vec![Box::new(Fixed("first")), Box::new(Fixed("second"))]);
+        let found = pipeline.run(&MediaInfo::default()).await;
+        assert_eq!(found.large_image.as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn background_result_follows_request() {
+        let mut background = Background::new(Pipeline(vec![Box::new(Fixed("art"))]), None);
+        let track = MediaInfo {
+            title: "a".to_owned(),
+            ..Default::default()
+        };
+        background.request(&track);
+        assert_eq!(background.for_track(&track), Enrichment::default());
+        background.finished().await;
+        assert_eq!(
+            background.for_track(&track).large_image.as_deref(),
+            Some("art")
+        );
+        assert_eq!(
+            background.for_track(&MediaInfo::default()),
+            Enrichment::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn placeholder_shows_until_lookup_finishes() {
+        let mut background = Background::new(
+            Pipeline(vec![Box::new(GenreImages(Vec::new()))]),
+            Some("enriching".to_owned()),
+        );
+        let track = MediaInfo {
+            title: "a".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(background.for_track(&track), Enrichment::default());
+        background.request(&track);
+        assert_eq!(
+            background.for_track(&track).large_image.as_deref(),
+            Some("enriching")
+        );
+        // Finding nothing still has to take the placeholder down.
+        background.finished().await;
+        assert_eq!(background.for_track(&track), Enrichment::default());
+    }
+
+    #[tokio::test]
+    async fn placeholder_unused_without_stages() {
+        let mut background = Background::new(Pipeline(Vec::new()), Some("enriching".to_owned()));
+        let track = MediaInfo::default();
+        background.request(&track);
+        assert_eq!(background.for_track(&track), Enrichment::default());
+    }
+
+    #[tokio::test]
+    async fn user_link_off_any_service_is_used_as_is() {
+        let stage = StreamingLinks {
+            odesli: odesli::Client::shared().unwrap(),
+            musicbrainz: None,
+            min_confidence: 90,
+        };
+        let track = MediaInfo {
+            link: Some("https://artist.bandcamp.com/track/river".to_owned()),
+            ..Default::default()
+        };
+        let mut found = Enrichment::default();
+        stage.enrich(&track, &mut found).await;
+        assert_eq!(
+            found.links.page.as_deref(),
+            Some("https://artist.bandcamp.com/track/river")
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_stages_do_nothing() {
+        let config = config::parse(
+            r#"
+            [enrichment]
+            stages = []
+
+            [[genre_images]]
+            pattern = "Jazz"
+            image = "jazz"
+            "#,
+        )
+        .unwrap();
+        let track = MediaInfo {
+            genres: vec!["Jazz".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(
+            Pipeline::new(&config).run(&track).await,
+            Enrichment::default()
+        );
+    }
+}
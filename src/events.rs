@@ -0,0 +1,265 @@
+use crate::enrich::Enrichment;
+use crate::metrics::{Failure, METRICS};
+use crate::notify::Problem;
+use crate::position::Progress;
+use crate::track::TrackKey;
+use crate::updates::Strategy;
+use crate::{MediaInfo, PlaybackStatus};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+/// How many events a slow sink may fall behind before it starts missing them.
+const CAPACITY: usize = 64;
+/// A track back within this of its start has been restarted...
+const REPLAY_START: Duration = Duration::from_secs(3);
+/// ...provided it had got at least this far first, so a seek back over the
+/// first few seconds isn't mistaken for one.
+const REPLAY_PLAYED: Duration = Duration::from_secs(10);
+
+/// Everything read from the player in one go.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerState {
+    pub track: Option<MediaInfo>,
+    pub status: PlaybackStatus,
+    pub progress: Option<Progress>,
+}
+
+impl PlayerState {
+    pub fn not_playing(status: PlaybackStatus) -> Self {
+        PlayerState {
+            track: None,
+            status,
+            progress: None,
+        }
+    }
+}
+
+/// What was shown on Discord, and when (in unix seconds).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Published {
+    pub at: u64,
+    pub details: String,
+    pub state: Option<String>,
+}
+
+/// Something that happened, broadcast to every sink.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// A fresh reading of the player, sent whenever anything about it may
+    /// have changed.
+    State(PlayerState),
+    /// The reading is of a different track from the one before.
+    TrackChanged(MediaInfo),
+    /// The same track went back to its start, by seeking or by looping, and
+    /// counts as being played again.
+    Replayed(MediaInfo),
+    StatusChanged(PlaybackStatus),
+    PlayerAppeared,
+    PlayerVanished,
+    DiscordConnected,
+    /// Discord stopped taking what's sent to it.
+    DiscordLost,
+    /// The daemon is on the session bus, and will see the player.
+    BusConnected,
+    /// The session bus went away, so the daemon is stopping.
+    BusLost,
+    Published(Published),
+    /// What the enrichment stages found for a track, once they're done.
+    Enriched(TrackKey, Enrichment),
+    /// How the player's changes are now being noticed.
+    UpdatesBy(Strategy),
+    /// Something going wrong, or coming right again, for `notify`.
+    Problem(Problem),
+    /// The daemon is exiting, so whatever's shown should be cleared.
+    Stopping,
+}
+
+#[derive(Clone)]
+pub struct Bus(broadcast::Sender<Event>);
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus(broadcast::channel(CAPACITY).0)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.0.subscribe()
+    }
+
+    pub fn send(&self, event: Event) {
+        if self.0.send(event).is_err() {
+            METRICS.fail(Failure::ChannelSend);
+        }
+    }
+}
+
+/// Turns successive player readings into events, adding `TrackChanged`,
+/// `Replayed` and `StatusChanged` when the reading differs from the previous
+/// one.
+#[derive(Default)]
+pub struct Reporter {
+    previous: Option<PlayerState>,
+}
+
+impl Reporter {
+    pub fn previous(&self) -> Option<&PlayerState> {
+        self.previous.as_ref()
+    }
+
+    pub fn report(&mut self, state: PlayerState) -> Vec<Event> {
+        let previous = self.previous.as_ref();
+        let mut events = vec![Event::State(state.clone())];
+        if let Some(mi) = &state.track {
+            let before = previous.and_then(|p| p.track.as_ref());
+            if !before.is_some_and(|before| before.same_track(mi)) {
+                events.push(Event::TrackChanged(mi.clone()));
+            } else if previous.is_some_and(|p| restarted(p, &state)) {
+                events.push(Event::Replayed(mi.clone()));
+            }
+        }
+        if previous.map(|p| p.status) != Some(state.status) {
+            events.push(Event::StatusChanged(state.status));
+        }
+        self.previous = Some(state);
+        events
+    }
+}
+
+/// Whether `next` finds the track back at its start when, going by
+/// `previous`, it should have been well into it.
+pub fn restarted(previous: &PlayerState, next: &PlayerState) -> bool {
+    let (Some(before), Some(after)) = (&previous.progress, &next.progress) else {
+        return false;
+    };
+    let expected = before.position_at(after.at, previous.status == PlaybackStatus::Playing);
+    after.position.as_duration() <= REPLAY_START && expected.as_duration() >= REPLAY_PLAYED
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duration::TrackDuration;
+    use std::time::SystemTime;
+
+    fn playing(title: &str) -> PlayerState {
+        PlayerState {
+            track: Some(MediaInfo {
+                title: title.to_owned(),
+                ..Default::default()
+            }),
+            status: PlaybackStatus::Playing,
+            progress: None,
+        }
+    }
+
+    #[test]
+    fn first_reading_announces_track_and_status() {
+        let events = Reporter::default().report(playing("a"));
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[1], Event::TrackChanged(_)));
+        assert_eq!(events[2], Event::StatusChanged(PlaybackStatus::Playing));
+    }
+
+    #[test]
+    fn unchanged_reading_is_only_state() {
+        let mut reporter = Reporter::default();
+        reporter.report(playing("a"));
+        assert_eq!(
+            reporter.report(playing("a")),
+            vec![Event::State(playing("a"))]
+        );
+    }
+
+    fn with_id(title: &str, track_id: Option<&str>) -> PlayerState {
+        let mut state = playing(title);
+        if let Some(mi) = &mut state.track {
+            mi.track_id = track_id.map(str::to_owned);
+        }
+        state
+    }
+
+    #[test]
+    fn corrected_tags_are_the_same_track() {
+        let mut reporter = Reporter::default();
+        reporter.report(with_id("Track 1", Some("/t/1")));
+        assert_eq!(reporter.report(with_id("River", Some("/t/1"))).len(), 1);
+        let events = reporter.report(with_id("River", Some("/t/2")));
+        assert!(matches!(events[1], Event::TrackChanged(_)));
+    }
+
+    #[test]
+    fn trackid_turning_up_is_the_same_track() {
+        let mut reporter = Reporter::default();
+        reporter.report(with_id("River", None));
+        assert_eq!(reporter.report(with_id("River", Some("/t/1"))).len(), 1);
+        let events = reporter.report(with_id("Blue", None));
+        assert!(matches!(events[1], Event::TrackChanged(_)));
+    }
+
+    #[test]
+    fn new_track_is_announced() {
+        let mut reporter = Reporter::default();
+        reporter.report(playing("a"));
+        let events = reporter.report(playing("b"));
+        assert!(matches!(&events[1], Event::TrackChanged(mi) if mi.title == "b"));
+        assert_eq!(events.len(), 2);
+    }
+
+    fn at(title: &str, position: u64, at: SystemTime) -> PlayerState {
+        PlayerState {
+            progress: Some(Progress {
+                position: TrackDuration::from(Duration::from_secs(position)),
+                rate: 1.0,
+                at,
+            }),
+            ..playing(title)
+        }
+    }
+
+    #[test]
+    fn return_to_start_is_a_replay() {
+        let now = SystemTime::now();
+        let mut reporter = Reporter::default();
+        reporter.report(at("a", 170, now));
+        let events = reporter.report(at("a", 0, now + Duration::from_secs(10)));
+        assert!(matches!(&events[1], Event::Replayed(mi) if mi.title == "a"));
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn playing_past_the_threshold_counts_towards_a_replay() {
+        let now = SystemTime::now();
+        let mut reporter = Reporter::default();
+        reporter.report(at("a", 5, now));
+        let events = reporter.report(at("a", 1, now + Duration::from_secs(20)));
+        assert!(matches!(events[1], Event::Replayed(_)));
+    }
+
+    #[test]
+    fn seeking_within_the_opening_is_not_a_replay() {
+        let now = SystemTime::now();
+        let mut reporter = Reporter::default();
+        reporter.report(at("a", 6, now));
+        assert_eq!(reporter.report(at("a", 0, now)).len(), 1);
+    }
+
+    #[test]
+    fn new_track_at_start_is_not_a_replay() {
+        let now = SystemTime::now();
+        let mut reporter = Reporter::default();
+        reporter.report(at("a", 170, now));
+        let events = reporter.report(at("b", 0, now));
+        assert!(matches!(events[1], Event::TrackChanged(_)));
+        assert_eq!(events.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn every_subscriber_sees_every_event() {
+        let bus = Bus::new();
+        let (mut first, mut second) = (bus.subscribe(), bus.subscribe());
+        bus.send(Event::PlayerAppeared);
+        assert_eq!(first.recv().await.unwrap(), Event::PlayerAppeared);
+        assert_eq!(second.recv().await.unwrap(), Event::PlayerAppeared);
+    }
+}
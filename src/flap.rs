@@ -0,0 +1,148 @@
+use crate::Trigger;
+use futures::prelude::*;
+use log::debug;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// This many departures within `FLAP_WINDOW` and the player is flapping.
+const FLAPS: usize = 3;
+const FLAP_WINDOW: Duration = Duration::from_secs(60);
+
+/// Tells a player that's restarting from one that keeps coming and going,
+/// as a browser's can with each tab, and decides how long each has to be
+/// gone before it's taken as closed. Players are told apart by bus name,
+/// so one flapping doesn't cost another on the same bus its grace.
+#[derive(Debug)]
+pub struct Flapping {
+    grace: Duration,
+    cooldown: Duration,
+    departures: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Flapping {
+    pub fn new(grace: Duration, cooldown: Duration) -> Self {
+        Flapping {
+            grace,
+            cooldown,
+            departures: HashMap::new(),
+        }
+    }
+
+    /// The player let go of `name` at `now`; how long to wait for it to
+    /// come back.
+    pub fn vanished(&mut self, name: &str, now: Instant) -> Duration {
+        self.departures.retain(|_, departures| {
+            while departures
+                .front()
+                .is_some_and(|&departed| now.duration_since(departed) > FLAP_WINDOW)
+            {
+                departures.pop_front();
+            }
+            !departures.is_empty()
+        });
+        let departures = self.departures.entry(name.to_owned()).or_default();
+        departures.push_back(now);
+        match departures.len() >= FLAPS {
+            true => self.cooldown,
+            false => self.grace,
+        }
+    }
+}
+
+/// Passes on one bus's triggers, holding each `Vanished` back until the
+/// player has been gone as long as `grace` says. If it's back by then, it's
+/// as though it never left: only the `Appeared` goes on, and nothing in
+/// between does, there being no player to read.
+pub fn hold_vanishing<G>(
+    triggers: impl Stream<Item = Trigger> + Send + 'static,
+    grace: G,
+) -> impl Stream<Item = Trigger>
+where
+    G: FnMut() -> Duration,
+{
+    stream::unfold(
+        (triggers.boxed(), None, grace),
+        |(mut triggers, mut closing, mut grace): (_, Option<Instant>, G)| async move {
+            loop {
+                let deadline = closing;
+                tokio::select! {
+                    trigger = triggers.next() => match trigger? {
+                        Trigger::Vanished => {
+                            closing = Some(Instant::now() + grace());
+                        }
+                        Trigger::Appeared => {
+                            if closing.take().is_some() {
+                                debug!("the player came back before it was taken as closed");
+                            }
+                            return Some((Trigger::Appeared, (triggers, closing, grace)));
+                        }
+                        _ if closing.is_some() => {}
+                        trigger => return Some((trigger, (triggers, closing, grace))),
+                    },
+                    _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                        return Some((Trigger::Vanished, (triggers, None, grace)));
+                    }
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+
+    const GRACE: Duration = Duration::from_millis(50);
+    const AUDACIOUS: &str = "org.mpris.MediaPlayer2.audacious";
+    const FIREFOX: &str = "org.mpris.MediaPlayer2.firefox.instance_1_7";
+
+    #[test]
+    fn coming_and_going_earns_the_cooldown() {
+        let cooldown = Duration::from_secs(30);
+        let mut flapping = Flapping::new(GRACE, cooldown);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(flapping.vanished(FIREFOX, start), GRACE);
+        assert_eq!(flapping.vanished(FIREFOX, at(1)), GRACE);
+        assert_eq!(flapping.vanished(FIREFOX, at(2)), cooldown);
+        // Once it's settled down, it's given the usual grace again.
+        assert_eq!(flapping.vanished(FIREFOX, at(100)), GRACE);
+    }
+
+    #[test]
+    fn another_player_flapping_costs_none_of_the_grace() {
+        let cooldown = Duration::from_secs(30);
+        let mut flapping = Flapping::new(GRACE, cooldown);
+        let start = Instant::now();
+        for secs in 0..3 {
+            flapping.vanished(FIREFOX, start + Duration::from_secs(secs));
+        }
+        let then = start + Duration::from_secs(3);
+        assert_eq!(flapping.vanished(AUDACIOUS, then), GRACE);
+        assert_eq!(flapping.vanished(FIREFOX, then), cooldown);
+    }
+
+    #[tokio::test]
+    async fn quick_return_hides_the_departure() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut held = hold_vanishing(rx, || GRACE).boxed();
+        tx.unbounded_send(Trigger::Vanished).unwrap();
+        tx.unbounded_send(Trigger::Poll).unwrap();
+        tx.unbounded_send(Trigger::Appeared).unwrap();
+        tx.unbounded_send(Trigger::Signal).unwrap();
+        assert_eq!(held.next().await, Some(Trigger::Appeared));
+        assert_eq!(held.next().await, Some(Trigger::Signal));
+    }
+
+    #[tokio::test]
+    async fn departure_goes_on_after_grace() {
+        let (tx, rx) = mpsc::unbounded();
+        let mut held = hold_vanishing(rx, || GRACE).boxed();
+        let start = Instant::now();
+        tx.unbounded_send(Trigger::Vanished).unwrap();
+        assert_eq!(held.next().await, Some(Trigger::Vanished));
+        assert!(start.elapsed() >= GRACE);
+    }
+}
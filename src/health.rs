@@ -0,0 +1,125 @@
+use crate::config::SessionBus;
+use crate::{ipc, seat, status};
+use dbus::nonblock::{Proxy, SyncConnection};
+use log::debug;
+use std::fmt::Display;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, PartialEq)]
+pub struct Health {
+    /// Whether the daemon's status file is there, and its process alive.
+    pub daemon: bool,
+    pub dbus: bool,
+    pub discord: bool,
+}
+
+impl Health {
+    pub fn is_healthy(&self) -> bool {
+        self.daemon && self.dbus && self.discord
+    }
+}
+
+impl Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let describe = |alive: bool| if alive { "ok" } else { "unreachable" };
+        write!(
+            f,
+            "daemon: {}, dbus: {}, discord: {}",
+            if self.daemon {
+                "running"
+            } else {
+                "not running"
+            },
+            describe(self.dbus),
+            describe(self.discord)
+        )
+    }
+}
+
+/// Asks the running daemon, through its status file, whether it's getting
+/// through to the bus and Discord, and checks the bus it would use answers.
+pub async fn check(policy: SessionBus) -> Health {
+    let snapshot = status::read_live();
+    Health {
+        daemon: snapshot.is_some(),
+        dbus: snapshot.as_ref().is_some_and(|s| s.dbus) && dbus_alive(policy).await,
+        discord: snapshot.is_some_and(|s| s.discord),
+    }
+}
+
+async fn dbus_alive(policy: SessionBus) -> bool {
+    let Ok((resource, conn)) = seat::connect(policy).await else {
+        return false;
+    };
+    let resource = tokio::spawn(resource);
+    let proxy: Proxy<Arc<SyncConnection>> = Proxy::new(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        Duration::from_secs(2),
+        conn,
+    );
+    let ping: Result<(), _> = proxy
+        .method_call("org.freedesktop.DBus.Peer", "Ping", ())
+        .await;
+    resource.abort();
+    debug!("dbus ping: {:?}", ping);
+    ping.is_ok()
+}
+
+pub fn discord_alive() -> bool {
+    ipc::socket_paths().any(|path| UnixStream::connect(path).is_ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn healthy_only_when_daemon_and_both_endpoints_alive() {
+        let healthy = Health {
+            daemon: true,
+            dbus: true,
+            discord: true,
+        };
+        assert!(healthy.is_healthy());
+        assert!(!Health {
+            discord: false,
+            ..healthy
+        }
+        .is_healthy());
+        assert!(!Health {
+            dbus: false,
+            ..healthy
+        }
+        .is_healthy());
+        assert!(!Health {
+            daemon: false,
+            ..healthy
+        }
+        .is_healthy());
+    }
+
+    #[test]
+    fn display_reports_each_endpoint() {
+        let health = Health {
+            daemon: true,
+            dbus: true,
+            discord: false,
+        };
+        assert_eq!(
+            health.to_string(),
+            "daemon: running, dbus: ok, discord: unreachable"
+        );
+        let health = Health {
+            daemon: false,
+            dbus: false,
+            discord: false,
+        };
+        assert_eq!(
+            health.to_string(),
+            "daemon: not running, dbus: unreachable, discord: unreachable"
+        );
+    }
+}
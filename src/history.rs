@@ -0,0 +1,244 @@
+use crate::events::{self, PlayerState};
+use crate::paths;
+use crate::track::TrackKey;
+use crate::PlaybackStatus;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A track counts as completed once this much of it has played...
+const COMPLETED_FRACTION: f64 = 0.9;
+/// ...or once no more than this is left, for the sake of long tails.
+const COMPLETED_REMAINING: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Completed,
+    Skipped,
+}
+
+/// One finished play of a track, as stored in the history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Play {
+    pub track: TrackKey,
+    pub artist: String,
+    pub title: String,
+    pub album: String,
+    pub player: String,
+    /// When it stopped playing, in unix seconds.
+    pub ended_at: u64,
+    /// How far into the track it got, in seconds.
+    pub reached: u64,
+    pub outcome: Outcome,
+}
+
+/// Watches successive readings for the moment each play ends, whether by
+/// moving on, starting over or stopping, and judges how far it got.
+#[derive(Default)]
+pub struct PlayTracker {
+    previous: Option<PlayerState>,
+}
+
+impl PlayTracker {
+    pub fn observe(&mut self, state: &PlayerState, now: SystemTime) -> Option<Play> {
+        let previous = self.previous.replace(state.clone())?;
+        let before = previous.track.as_ref()?;
+        let moved_on = !state.track.as_ref().is_some_and(|mi| mi.same_track(before));
+        if !moved_on && !events::restarted(&previous, state) {
+            return None;
+        }
+        finish(&previous, now)
+    }
+}
+
+fn finish(state: &PlayerState, now: SystemTime) -> Option<Play> {
+    let mi = state.track.as_ref()?;
+    // Nothing to judge a stream or a track of unknown length against.
+    let length = mi.length?.as_duration();
+    let reached = state
+        .progress?
+        .position_at(now, state.status == PlaybackStatus::Playing)
+        .as_duration()
+        .min(length);
+    let completed = reached.as_secs_f64() >= length.as_secs_f64() * COMPLETED_FRACTION
+        || length - reached <= COMPLETED_REMAINING;
+    Some(Play {
+        track: mi.key(),
+        artist: mi.artist.clone(),
+        title: mi.title.clone(),
+        album: mi.album.clone(),
+        player: mi.player.clone(),
+        ended_at: now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        reached: reached.as_secs(),
+        outcome: match completed {
+            true => Outcome::Completed,
+            false => Outcome::Skipped,
+        },
+    })
+}
+
+pub fn path() -> Option<PathBuf> {
+    paths::data_dir().map(|dir| dir.join("history.jsonl"))
+}
+
+/// Adds a play to the end of the history, one JSON object per line.
+pub fn append(path: &Path, play: &Play) -> anyhow::Result<()> {
+    append_all(path, std::slice::from_ref(play))
+}
+
+/// Adds plays to the end of the history in one write.
+pub fn append_all(path: &Path, plays: &[Play]) -> anyhow::Result<()> {
+    std::fs::create_dir_all(path.parent().context("history path has no parent")?)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let mut lines = Vec::new();
+    for play in plays {
+        serde_json::to_writer(&mut lines, play)?;
+        lines.push(b'\n');
+    }
+    file.write_all(&lines)?;
+    Ok(())
+}
+
+/// Every play recorded so far, skipping lines that don't parse (such as one
+/// cut short by a crash).
+pub fn read(path: &Path) -> anyhow::Result<Vec<Play>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duration::TrackDuration;
+    use crate::position::Progress;
+    use crate::MediaInfo;
+
+    fn secs(secs: u64) -> TrackDuration {
+        TrackDuration::from(Duration::from_secs(secs))
+    }
+
+    fn at(title: &str, position: u64, at: SystemTime) -> PlayerState {
+        PlayerState {
+            track: Some(MediaInfo {
+                title: title.to_owned(),
+                length: Some(secs(200)),
+                ..Default::default()
+            }),
+            status: PlaybackStatus::Playing,
+            progress: Some(Progress {
+                position: secs(position),
+                rate: 1.0,
+                at,
+            }),
+        }
+    }
+
+    #[test]
+    fn moving_on_early_is_a_skip() {
+        let now = SystemTime::now();
+        let mut tracker = PlayTracker::default();
+        assert_eq!(tracker.observe(&at("a", 0, now), now), None);
+        let later = now + Duration::from_secs(30);
+        let play = tracker.observe(&at("b", 0, later), later).unwrap();
+        assert_eq!(play.title, "a");
+        assert_eq!(play.reached, 30);
+        assert_eq!(play.outcome, Outcome::Skipped);
+    }
+
+    #[test]
+    fn playing_to_the_end_is_completed() {
+        let now = SystemTime::now();
+        let mut tracker = PlayTracker::default();
+        tracker.observe(&at("a", 150, now), now);
+        let later = now + Duration::from_secs(45);
+        let play = tracker.observe(&at("b", 0, later), later).unwrap();
+        assert_eq!(play.outcome, Outcome::Completed);
+    }
+
+    #[test]
+    fn stopping_ends_the_play() {
+        let now = SystemTime::now();
+        let mut tracker = PlayTracker::default();
+        tracker.observe(&at("a", 10, now), now);
+        let stopped = PlayerState::not_playing(PlaybackStatus::Stopped);
+        let play = tracker.observe(&stopped, now).unwrap();
+        assert_eq!(play.outcome, Outcome::Skipped);
+        assert_eq!(tracker.observe(&stopped, now), None);
+    }
+
+    #[test]
+    fn replay_ends_the_previous_play() {
+        let now = SystemTime::now();
+        let mut tracker = PlayTracker::default();
+        tracker.observe(&at("a", 195, now), now);
+        let later = now + Duration::from_secs(5);
+        let play = tracker.observe(&at("a", 0, later), later).unwrap();
+        assert_eq!(play.reached, 200);
+        assert_eq!(play.outcome, Outcome::Completed);
+    }
+
+    #[test]
+    fn continuing_the_same_track_is_not_a_play() {
+        let now = SystemTime::now();
+        let mut tracker = PlayTracker::default();
+        tracker.observe(&at("a", 10, now), now);
+        assert_eq!(tracker.observe(&at("a", 20, now), now), None);
+    }
+
+    #[test]
+    fn corrected_tags_are_counted_once() {
+        let now = SystemTime::now();
+        let identified = |title: &str, position: u64| {
+            let mut state = at(title, position, now);
+            if let Some(mi) = &mut state.track {
+                mi.track_id = Some("/org/fake/Track/1".to_owned());
+            }
+            state
+        };
+        let mut tracker = PlayTracker::default();
+        tracker.observe(&identified("Track 01", 5), now);
+        assert_eq!(tracker.observe(&identified("River", 10), now), None);
+        let play = tracker.observe(&at("Blue", 0, now), now).unwrap();
+        assert_eq!(play.title, "River");
+    }
+
+    #[test]
+    fn history_round_trips_and_skips_torn_lines() {
+        let dir = std::env::temp_dir().join(format!("dmr-history-{}", std::process::id()));
+        let path = dir.join("history.jsonl");
+        let now = SystemTime::now();
+        let mut tracker = PlayTracker::default();
+        tracker.observe(&at("a", 0, now), now);
+        let play = tracker.observe(&at("b", 0, now), now).unwrap();
+        append(&path, &play).unwrap();
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"track\":")
+            .unwrap();
+        assert_eq!(read(&path).unwrap(), vec![play]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn missing_history_is_empty() {
+        assert!(read(Path::new("/nonexistent/history.jsonl"))
+            .unwrap()
+            .is_empty());
+    }
+}
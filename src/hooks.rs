@@ -0,0 +1,213 @@
+use crate::config::{Config, Hooks};
+use crate::events::Event;
+use crate::{MediaInfo, PlaybackStatus};
+use log::{debug, warn};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Which hook an event sets off.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Hook {
+    TrackChanged,
+    Paused,
+    Stopped,
+}
+
+impl Hook {
+    fn of(event: &Event) -> Option<Hook> {
+        match event {
+            Event::TrackChanged(_) => Some(Hook::TrackChanged),
+            Event::StatusChanged(PlaybackStatus::Paused) => Some(Hook::Paused),
+            Event::StatusChanged(PlaybackStatus::Stopped | PlaybackStatus::Closed) => {
+                Some(Hook::Stopped)
+            }
+            _ => None,
+        }
+    }
+
+    fn command(self, hooks: &Hooks) -> Option<&str> {
+        match self {
+            Hook::TrackChanged => hooks.track_changed.as_deref(),
+            Hook::Paused => hooks.paused.as_deref(),
+            Hook::Stopped => hooks.stopped.as_deref(),
+        }
+        .filter(|command| !command.trim().is_empty())
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Hook::TrackChanged => "track_changed",
+            Hook::Paused => "paused",
+            Hook::Stopped => "stopped",
+        }
+    }
+}
+
+/// What a hook is told, as environment variables. Without a track they're
+/// all empty, so a command can rely on them being set.
+fn environment(
+    hook: Hook,
+    track: Option<&MediaInfo>,
+    status: PlaybackStatus,
+) -> Vec<(&'static str, String)> {
+    let mi = track.cloned().unwrap_or_default();
+    vec![
+        ("MPRIS_EVENT", hook.name().to_owned()),
+        ("MPRIS_STATUS", format!("{:?}", status)),
+        ("MPRIS_TITLE", mi.title),
+        ("MPRIS_ARTIST", mi.artist),
+        ("MPRIS_ALBUM", mi.album),
+        ("MPRIS_PLAYER", mi.player),
+        ("MPRIS_GENRE", mi.genres.join(", ")),
+        ("MPRIS_URL", mi.url.unwrap_or_default()),
+        (
+            "MPRIS_LENGTH",
+            mi.length
+                .map(|length| length.as_duration().as_secs().to_string())
+                .unwrap_or_default(),
+        ),
+    ]
+}
+
+/// Starts `command` in a shell without waiting for it, so a slow hook can't
+/// hold up the others; how it ends is only logged.
+fn spawn(hook: Hook, command: &str, env: Vec<(&'static str, String)>) {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env)
+        .stdin(Stdio::null())
+        .spawn();
+    match child {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                match child.wait().await {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => warn!("the {} hook failed: {}", hook.name(), status),
+                    Err(e) => warn!("couldn't wait for the {} hook: {}", hook.name(), e),
+                }
+            });
+        }
+        Err(e) => warn!("couldn't run the {} hook: {}", hook.name(), e),
+    }
+}
+
+/// Follows the bus until it closes, running the configured hook for each
+/// event that has one.
+pub async fn run(config: Arc<Config>, mut events: broadcast::Receiver<Event>) {
+    let mut track: Option<MediaInfo> = None;
+    let mut status = PlaybackStatus::Stopped;
+    let mut readings = 0u32;
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                debug!("hooks sink missed {} events", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        // The reading comes before the events it gives rise to, so the
+        // track is up to date by the time they arrive.
+        if let Event::State(state) = &event {
+            if state.track.is_some() {
+                track = state.track.clone();
+            }
+            status = state.status;
+            readings = readings.saturating_add(1);
+        }
+        let Some(hook) = Hook::of(&event) else {
+            continue;
+        };
+        // What the player was doing when we started isn't a change.
+        if readings <= 1 && hook != Hook::TrackChanged {
+            continue;
+        }
+        if let Some(command) = hook.command(&config.hooks) {
+            debug!("running the {} hook", hook.name());
+            spawn(hook, command, environment(hook, track.as_ref(), status));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config;
+    use crate::events::{Bus, PlayerState};
+    use std::time::Duration;
+
+    #[test]
+    fn stopping_and_closing_both_count_as_stopped() {
+        assert_eq!(
+            Hook::of(&Event::StatusChanged(PlaybackStatus::Closed)),
+            Some(Hook::Stopped)
+        );
+        assert_eq!(
+            Hook::of(&Event::StatusChanged(PlaybackStatus::Stopped)),
+            Some(Hook::Stopped)
+        );
+        assert_eq!(
+            Hook::of(&Event::StatusChanged(PlaybackStatus::Playing)),
+            None
+        );
+    }
+
+    #[test]
+    fn environment_describes_track() {
+        let mi = MediaInfo {
+            title: "River".to_owned(),
+            artist: "Joni Mitchell".to_owned(),
+            genres: vec!["Folk".to_owned(), "Pop".to_owned()],
+            length: Some(Duration::from_secs(243).into()),
+            ..Default::default()
+        };
+        let env = environment(Hook::Paused, Some(&mi), PlaybackStatus::Paused);
+        let get = |name| env.iter().find(|(key, _)| *key == name).unwrap().1.as_str();
+        assert_eq!(get("MPRIS_EVENT"), "paused");
+        assert_eq!(get("MPRIS_STATUS"), "Paused");
+        assert_eq!(get("MPRIS_TITLE"), "River");
+        assert_eq!(get("MPRIS_GENRE"), "Folk, Pop");
+        assert_eq!(get("MPRIS_LENGTH"), "243");
+        assert_eq!(get("MPRIS_ALBUM"), "");
+    }
+
+    #[tokio::test]
+    async fn hook_runs_with_track_in_environment() {
+        let dir = std::env::temp_dir().join(format!("dmr-hooks-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("out");
+        let config = config::parse(&format!(
+            "[hooks]\ntrack_changed = 'echo \"$MPRIS_ARTIST - $MPRIS_TITLE\" > {}'",
+            out.display()
+        ))
+        .unwrap();
+        let bus = Bus::new();
+        let task = tokio::spawn(run(Arc::new(config), bus.subscribe()));
+        let mi = MediaInfo {
+            title: "River".to_owned(),
+            artist: "Joni Mitchell".to_owned(),
+            ..Default::default()
+        };
+        bus.send(Event::State(PlayerState {
+            track: Some(mi.clone()),
+            status: PlaybackStatus::Playing,
+            progress: None,
+        }));
+        bus.send(Event::TrackChanged(mi));
+        drop(bus);
+        task.await.unwrap();
+        let mut written = String::new();
+        for _ in 0..50 {
+            written = std::fs::read_to_string(&out).unwrap_or_default();
+            if !written.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(written, "Joni Mitchell - River\n");
+    }
+}
@@ -0,0 +1,382 @@
+use anyhow::Context;
+use log::debug;
+use serde::{Serialize, Serializer};
+use serde_json::{json, Value};
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Discord listens on the first free one of these sockets.
+const SOCKET_SLOTS: u8 = 10;
+/// Frames bigger than this aren't anything Discord would answer with.
+const MAX_FRAME: u32 = 64 * 1024;
+
+/// What a frame is, from its header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpCode {
+    Handshake = 0,
+    Frame = 1,
+    Close = 2,
+    Ping = 3,
+    Pong = 4,
+}
+
+impl OpCode {
+    fn from_u32(op: u32) -> Option<Self> {
+        [
+            OpCode::Handshake,
+            OpCode::Frame,
+            OpCode::Close,
+            OpCode::Ping,
+            OpCode::Pong,
+        ]
+        .into_iter()
+        .find(|code| *code as u32 == op)
+    }
+}
+
+/// Where Discord puts its sockets.
+fn socket_dir() -> PathBuf {
+    env::var("XDG_RUNTIME_DIR")
+        .or_else(|_| env::var("TMPDIR"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| env::temp_dir())
+}
+
+/// Where Discord's sockets may be, in the order it takes them, including
+/// those of the Flatpak.
+pub fn socket_paths() -> impl Iterator<Item = PathBuf> {
+    socket_paths_in(socket_dir())
+}
+
+fn socket_paths_in(dir: PathBuf) -> impl Iterator<Item = PathBuf> {
+    let flatpak = dir.join("app").join("com.discordapp.Discord");
+    (0..SOCKET_SLOTS).flat_map(move |slot| {
+        let name = format!("discord-ipc-{}", slot);
+        [dir.join(&name), flatpak.join(name)]
+    })
+}
+
+/// The activity as Discord's `SET_ACTIVITY` takes it.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Activity {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+    pub details: String,
+    #[serde(rename = "type")]
+    pub kind: ActivityType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamps: Option<Timestamps>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assets: Option<Assets>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub party: Option<Party>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub buttons: Vec<Button>,
+    /// Which field the member list shows: 0 the name, 1 the state, 2 the
+    /// details. The name when left out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status_display_type: Option<u8>,
+}
+
+/// The verb Discord puts before the application's name.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum ActivityType {
+    #[default]
+    Listening = 2,
+    Watching = 3,
+}
+
+impl Serialize for ActivityType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Timestamps {
+    pub start: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Assets {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_image: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub small_text: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Party {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<(u32, u32)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Button {
+    pub label: String,
+    pub url: String,
+}
+
+#[derive(Serialize)]
+struct SetActivity<'a> {
+    pid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    activity: Option<&'a Activity>,
+}
+
+/// A connection to the Discord client on this machine, as one application.
+/// It's made on first use, and again after anything goes wrong with it.
+pub struct Client {
+    client_id: u64,
+    /// How long Discord has to answer before it's given up on.
+    timeout: Duration,
+    dir: PathBuf,
+    stream: Option<UnixStream>,
+    nonce: u64,
+}
+
+impl Client {
+    pub fn new(client_id: u64, timeout: Duration) -> Self {
+        Client {
+            client_id,
+            timeout,
+            dir: socket_dir(),
+            stream: None,
+            nonce: 0,
+        }
+    }
+
+    pub fn client_id(&self) -> u64 {
+        self.client_id
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Whether Discord has answered the handshake and not gone away since.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Connects and handshakes, unless that's been done already.
+    pub fn connect(&mut self) -> anyhow::Result<()> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+        let mut stream = socket_paths_in(self.dir.clone())
+            .find_map(|path| UnixStream::connect(path).ok())
+            .context("Discord isn't running")?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let handshake = json!({ "v": 1, "client_id": self.client_id.to_string() });
+        write_frame(&mut stream, OpCode::Handshake, &handshake)?;
+        match read_frame(&mut stream)? {
+            (OpCode::Frame, ready) if ready["evt"] == "READY" => {
+                debug!("Discord took the handshake as {}", self.client_id);
+                self.stream = Some(stream);
+                Ok(())
+            }
+            (_, refused) => anyhow::bail!("Discord refused the handshake: {}", refused),
+        }
+    }
+
+    pub fn disconnect(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            let _ = write_frame(&mut stream, OpCode::Close, &json!({}));
+        }
+    }
+
+    pub fn set_activity(&mut self, activity: &Activity) -> anyhow::Result<()> {
+        self.set(Some(activity))
+    }
+
+    pub fn clear_activity(&mut self) -> anyhow::Result<()> {
+        self.set(None)
+    }
+
+    fn set(&mut self, activity: Option<&Activity>) -> anyhow::Result<()> {
+        let args = SetActivity {
+            pid: std::process::id(),
+            activity,
+        };
+        let result = self.command("SET_ACTIVITY", args);
+        // Whatever went wrong, the next try starts on a new connection.
+        if result.is_err() {
+            self.stream = None;
+        }
+        result
+    }
+
+    /// Sends `cmd` and waits for Discord's answer to it.
+    fn command(&mut self, cmd: &str, args: impl Serialize) -> anyhow::Result<()> {
+        self.connect()?;
+        self.nonce += 1;
+        let nonce = format!("{}-{}", std::process::id(), self.nonce);
+        let stream = self.stream.as_mut().context("not connected")?;
+        let command = json!({ "cmd": cmd, "args": args, "nonce": nonce });
+        write_frame(stream, OpCode::Frame, &command)?;
+        loop {
+            match read_frame(stream)? {
+                (OpCode::Frame, answer) if answer["nonce"] == nonce.as_str() => {
+                    if answer["evt"] == "ERROR" {
+                        anyhow::bail!("Discord refused {}: {}", cmd, answer["data"]);
+                    }
+                    return Ok(());
+                }
+                (OpCode::Ping, ping) => write_frame(stream, OpCode::Pong, &ping)?,
+                (OpCode::Close, why) => anyhow::bail!("Discord closed the connection: {}", why),
+                // Events from earlier, which no one's waiting for.
+                _ => {}
+            }
+        }
+    }
+}
+
+fn write_frame(
+    stream: &mut impl Write,
+    op: OpCode,
+    payload: &impl Serialize,
+) -> anyhow::Result<()> {
+    let payload = serde_json::to_vec(payload)?;
+    let mut frame = Vec::with_capacity(8 + payload.len());
+    frame.extend((op as u32).to_le_bytes());
+    frame.extend(u32::try_from(payload.len())?.to_le_bytes());
+    frame.extend(payload);
+    stream.write_all(&frame)?;
+    Ok(())
+}
+
+fn read_frame(stream: &mut impl Read) -> anyhow::Result<(OpCode, Value)> {
+    let mut header = [0; 8];
+    stream.read_exact(&mut header)?;
+    let [o0, o1, o2, o3, l0, l1, l2, l3] = header;
+    let op = u32::from_le_bytes([o0, o1, o2, o3]);
+    let op = OpCode::from_u32(op).with_context(|| format!("unknown opcode {}", op))?;
+    let len = u32::from_le_bytes([l0, l1, l2, l3]);
+    if len > MAX_FRAME {
+        anyhow::bail!("a frame of {} bytes is too big", len);
+    }
+    let mut payload = vec![0; len as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((op, serde_json::from_slice(&payload)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn frames_round_trip() {
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, OpCode::Ping, &json!({ "n": 1 })).unwrap();
+        assert_eq!(&bytes[..8], [3, 0, 0, 0, 7, 0, 0, 0]);
+        let (op, payload) = read_frame(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(op, OpCode::Ping);
+        assert_eq!(payload, json!({ "n": 1 }));
+    }
+
+    #[test]
+    fn oversized_frames_are_refused() {
+        let mut bytes = vec![1, 0, 0, 0];
+        bytes.extend((MAX_FRAME + 1).to_le_bytes());
+        assert!(read_frame(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn clearing_leaves_out_the_activity() {
+        let args = SetActivity {
+            pid: 7,
+            activity: None,
+        };
+        assert_eq!(serde_json::to_value(args).unwrap(), json!({ "pid": 7 }));
+    }
+
+    /// Stands in for Discord in `dir`: takes the handshake, then answers
+    /// one command as `answer` says, and gives the command.
+    fn fake_discord(
+        dir: &std::path::Path,
+        answer: fn(&Value) -> Value,
+    ) -> std::thread::JoinHandle<Value> {
+        std::fs::create_dir_all(dir).unwrap();
+        let listener = std::os::unix::net::UnixListener::bind(dir.join("discord-ipc-0")).unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (op, _) = read_frame(&mut stream).unwrap();
+            assert_eq!(op, OpCode::Handshake);
+            let ready = json!({ "cmd": "DISPATCH", "evt": "READY" });
+            write_frame(&mut stream, OpCode::Frame, &ready).unwrap();
+            let (_, command) = read_frame(&mut stream).unwrap();
+            write_frame(&mut stream, OpCode::Frame, &answer(&command)).unwrap();
+            command
+        })
+    }
+
+    fn client_in(dir: &std::path::Path) -> Client {
+        Client {
+            dir: dir.to_owned(),
+            ..Client::new(1, Duration::from_secs(5))
+        }
+    }
+
+    #[test]
+    fn activity_is_sent_with_status_display_type() {
+        let dir = std::env::temp_dir().join(format!("dmr-ipc-{}-sent", std::process::id()));
+        let server = fake_discord(
+            &dir,
+            |command| json!({ "cmd": "SET_ACTIVITY", "evt": null, "nonce": command["nonce"] }),
+        );
+        let mut client = client_in(&dir);
+        let activity = Activity {
+            details: "Playing A - T".to_owned(),
+            status_display_type: Some(1),
+            ..Default::default()
+        };
+        client.set_activity(&activity).unwrap();
+        let command = server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(command["cmd"], "SET_ACTIVITY");
+        assert_eq!(command["args"]["pid"], std::process::id());
+        assert_eq!(
+            command["args"]["activity"],
+            json!({ "details": "Playing A - T", "type": 2, "status_display_type": 1 })
+        );
+        assert!(client.is_connected());
+    }
+
+    #[test]
+    fn refusal_is_an_error_and_drops_the_connection() {
+        let dir = std::env::temp_dir().join(format!("dmr-ipc-{}-refused", std::process::id()));
+        let server = fake_discord(&dir, |command| {
+            json!({
+                "cmd": "SET_ACTIVITY",
+                "evt": "ERROR",
+                "data": { "code": 4000, "message": "child \"activity\" fails" },
+                "nonce": command["nonce"],
+            })
+        });
+        let mut client = client_in(&dir);
+        let err = client.clear_activity().unwrap_err();
+        server.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(err.to_string().contains("4000"));
+        assert!(!client.is_connected());
+    }
+
+    #[test]
+    fn nobody_listening_is_an_error() {
+        let mut client = client_in(std::path::Path::new("/nonexistent"));
+        assert!(client.connect().is_err());
+    }
+}
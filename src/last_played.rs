@@ -0,0 +1,101 @@
+use crate::events::PlayerState;
+use crate::{MediaInfo, PlaybackStatus};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Remembers the last track played so it can still be shown for a while
+/// after playback stops.
+#[derive(Debug, Default)]
+pub struct LastPlayed {
+    track: Option<MediaInfo>,
+    stopped_at: Option<Instant>,
+}
+
+impl LastPlayed {
+    pub fn observe(&mut self, state: &PlayerState, now: Instant) {
+        match (&state.track, state.status) {
+            (Some(mi), PlaybackStatus::Playing) => {
+                self.track = Some(mi.clone());
+                self.stopped_at = None;
+            }
+            _ if self.track.is_some() && self.stopped_at.is_none() => self.stopped_at = Some(now),
+            _ => {}
+        }
+    }
+
+    /// The track to show as last played, if playback has stopped since.
+    pub fn track(&self) -> Option<&MediaInfo> {
+        self.stopped_at.and(self.track.as_ref())
+    }
+
+    pub fn expires_at(&self, expiry: Duration) -> Option<Instant> {
+        self.stopped_at.map(|stopped_at| stopped_at + expiry)
+    }
+
+    pub fn forget(&mut self) {
+        *self = LastPlayed::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(title: &str) -> PlayerState {
+        let mi = MediaInfo {
+            title: title.to_owned(),
+            ..Default::default()
+        };
+        PlayerState {
+            track: Some(mi),
+            status: PlaybackStatus::Playing,
+            progress: None,
+        }
+    }
+
+    const STOPPED: PlayerState = PlayerState {
+        track: None,
+        status: PlaybackStatus::Stopped,
+        progress: None,
+    };
+
+    #[test]
+    fn nothing_shown_while_playing() {
+        let mut last_played = LastPlayed::default();
+        last_played.observe(&playing("a"), Instant::now());
+        assert_eq!(last_played.track(), None);
+        assert_eq!(last_played.expires_at(Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn shows_last_track_until_expiry_after_stopping() {
+        let now = Instant::now();
+        let mut last_played = LastPlayed::default();
+        last_played.observe(&playing("a"), now);
+        last_played.observe(&playing("b"), now);
+        last_played.observe(&STOPPED, now + Duration::from_secs(5));
+        assert_eq!(last_played.track().map(|mi| mi.title.as_str()), Some("b"));
+        assert_eq!(
+            last_played.expires_at(Duration::from_secs(60)),
+            Some(now + Duration::from_secs(65))
+        );
+    }
+
+    #[test]
+    fn repeated_stops_keep_original_expiry() {
+        let now = Instant::now();
+        let mut last_played = LastPlayed::default();
+        last_played.observe(&playing("a"), now);
+        last_played.observe(&STOPPED, now);
+        last_played.observe(&STOPPED, now + Duration::from_secs(30));
+        assert_eq!(last_played.expires_at(Duration::ZERO), Some(now));
+    }
+
+    #[test]
+    fn nothing_remembered_without_playing() {
+        let mut last_played = LastPlayed::default();
+        last_played.observe(&STOPPED, Instant::now());
+        assert_eq!(last_played.track(), None);
+        assert_eq!(last_played.expires_at(Duration::ZERO), None);
+    }
+}
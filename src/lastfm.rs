@@ -0,0 +1,294 @@
+use crate::cached_http::USER_AGENT;
+use crate::history::{self, Outcome, Play};
+use crate::track::TrackKey;
+use anyhow::{anyhow, bail, Context};
+use log::debug;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+const API_URL: &str = "https://ws.audioscrobbler.com/2.0/";
+/// The most scrobbles Last.fm hands out a page at a time.
+const PAGE_SIZE: u32 = 200;
+/// Last.fm asks for no more than five requests a second.
+const MIN_INTERVAL: Duration = Duration::from_millis(250);
+/// What imported plays are put down to, so they can be told apart from
+/// the ones seen here.
+const PLAYER: &str = "Last.fm";
+/// A scrobble this soon before a play of the same track ended is taken to
+/// be that play, counted already.
+const SAME_PLAY: u64 = 30 * 60;
+
+/// One page of `user.getRecentTracks`, as the API returns it and as export
+/// tools that page through it save it.
+#[derive(Debug, Deserialize)]
+struct Page {
+    recenttracks: RecentTracks,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentTracks {
+    track: Vec<Scrobble>,
+    #[serde(rename = "@attr")]
+    attr: PageAttr,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageAttr {
+    total_pages: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Scrobble {
+    artist: Text,
+    name: String,
+    #[serde(default)]
+    album: Option<Text>,
+    /// Missing for whatever's playing now, which isn't a scrobble yet.
+    #[serde(default)]
+    date: Option<Date>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Text {
+    #[serde(rename = "#text")]
+    text: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Date {
+    uts: String,
+}
+
+/// An export: a single page, or every page in a list.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Export {
+    Page(Page),
+    Pages(Vec<Page>),
+}
+
+#[derive(Deserialize)]
+struct ApiError {
+    message: String,
+}
+
+impl Scrobble {
+    /// The scrobble as a completed play. Last.fm keeps when a track started
+    /// rather than ended, and not how far it got, so that's what there is.
+    fn play(self) -> Option<Play> {
+        let at = self.date?.uts.parse().ok()?;
+        let (artist, title) = (self.artist.text, self.name);
+        Some(Play {
+            track: TrackKey::new(None, &artist, &title, None),
+            artist,
+            title,
+            album: self.album.map(|album| album.text).unwrap_or_default(),
+            player: PLAYER.to_owned(),
+            ended_at: at,
+            reached: 0,
+            outcome: Outcome::Completed,
+        })
+    }
+}
+
+impl Page {
+    fn total_pages(&self) -> u32 {
+        self.recenttracks.attr.total_pages.parse().unwrap_or(1)
+    }
+
+    fn plays(self) -> impl Iterator<Item = Play> {
+        self.recenttracks
+            .track
+            .into_iter()
+            .filter_map(Scrobble::play)
+    }
+}
+
+fn parse_page(body: &str) -> anyhow::Result<Page> {
+    if let Ok(error) = serde_json::from_str::<ApiError>(body) {
+        bail!("Last.fm refused: {}", error.message);
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+/// The plays in an export file.
+fn read_export(path: &Path) -> anyhow::Result<Vec<Play>> {
+    let body =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    let pages = match serde_json::from_str(&body)
+        .with_context(|| format!("{} isn't a Last.fm export", path.display()))?
+    {
+        Export::Page(page) => vec![page],
+        Export::Pages(pages) => pages,
+    };
+    Ok(pages.into_iter().flat_map(Page::plays).collect())
+}
+
+/// Every scrobble of `user`'s since `from`, in unix seconds, a page at a
+/// time.
+async fn fetch(user: &str, api_key: &str, from: Option<u64>) -> anyhow::Result<Vec<Play>> {
+    let http = reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(Duration::from_secs(30))
+        .build()?;
+    let mut plays = Vec::new();
+    let mut page = 1;
+    loop {
+        let mut url = Url::parse(API_URL)?;
+        url.query_pairs_mut()
+            .append_pair("method", "user.getrecenttracks")
+            .append_pair("user", user)
+            .append_pair("api_key", api_key)
+            .append_pair("format", "json")
+            .append_pair("limit", &PAGE_SIZE.to_string())
+            .append_pair("page", &page.to_string());
+        if let Some(from) = from {
+            url.query_pairs_mut().append_pair("from", &from.to_string());
+        }
+        // Errors come with a body saying what went wrong, so the status is
+        // left to parse_page.
+        let body = http.get(url).send().await?.text().await?;
+        let fetched = parse_page(&body).with_context(|| format!("reading page {}", page))?;
+        let total = fetched.total_pages();
+        plays.extend(fetched.plays());
+        eprintln!("fetched page {} of {}", page, total);
+        if page >= total {
+            return Ok(plays);
+        }
+        page += 1;
+        tokio::time::sleep(MIN_INTERVAL).await;
+    }
+}
+
+/// The imported plays that aren't in `history` already, whether from an
+/// earlier import or seen here as they played, oldest first.
+fn new_plays(history: &[Play], mut imported: Vec<Play>) -> Vec<Play> {
+    let mut ended: HashMap<(String, String), Vec<u64>> = HashMap::new();
+    for play in history {
+        ended
+            .entry(same_track(play))
+            .or_default()
+            .push(play.ended_at);
+    }
+    imported.sort_by_key(|play| play.ended_at);
+    imported.dedup_by(|a, b| a.ended_at == b.ended_at && same_track(a) == same_track(b));
+    imported.retain(|play| {
+        !ended.get(&same_track(play)).is_some_and(|ends| {
+            ends.iter()
+                .any(|&end| (play.ended_at..=play.ended_at + SAME_PLAY).contains(&end))
+        })
+    });
+    imported
+}
+
+/// Tracks are matched on their tags, as an imported play has no id.
+fn same_track(play: &Play) -> (String, String) {
+    (play.artist.to_lowercase(), play.title.to_lowercase())
+}
+
+/// Runs `import lastfm`: adds scrobbles to the play history, from an export
+/// file or, given a user name, from Last.fm itself, with the API key in
+/// `LASTFM_API_KEY`.
+pub async fn import(source: &str) -> anyhow::Result<()> {
+    let path = history::path().context("no data directory for the history")?;
+    let history = history::read(&path)?;
+    let imported = match Path::new(source).is_file() {
+        true => read_export(Path::new(source))?,
+        false => {
+            let api_key = std::env::var("LASTFM_API_KEY")
+                .map_err(|_| anyhow!("set LASTFM_API_KEY to fetch from Last.fm"))?;
+            // Only what's come since the last import needs fetching.
+            let since = history
+                .iter()
+                .filter(|play| play.player == PLAYER)
+                .map(|play| play.ended_at + 1)
+                .max();
+            debug!("fetching scrobbles since {:?}", since);
+            fetch(source, &api_key, since).await?
+        }
+    };
+    let found = imported.len();
+    let plays = new_plays(&history, imported);
+    history::append_all(&path, &plays)?;
+    println!(
+        "imported {} of {} scrobbles, the rest being in the history already",
+        plays.len(),
+        found
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = r##"{"recenttracks": {
+        "track": [
+            {"artist": {"mbid": "", "#text": "Low"}, "name": "Words",
+             "album": {"mbid": "", "#text": "I Could Live in Hope"},
+             "@attr": {"nowplaying": "true"}},
+            {"artist": {"mbid": "", "#text": "Low"}, "name": "Words",
+             "album": {"mbid": "", "#text": "I Could Live in Hope"},
+             "date": {"uts": "1700000000", "#text": "14 Nov 2023, 22:13"}}
+        ],
+        "@attr": {"user": "me", "totalPages": "3", "page": "1", "perPage": "200", "total": "401"}
+    }}"##;
+
+    fn play(title: &str, ended_at: u64) -> Play {
+        Play {
+            track: TrackKey::new(None, "Low", title, None),
+            artist: "Low".to_owned(),
+            title: title.to_owned(),
+            album: String::new(),
+            player: PLAYER.to_owned(),
+            ended_at,
+            reached: 0,
+            outcome: Outcome::Completed,
+        }
+    }
+
+    #[test]
+    fn page_gives_scrobbles_not_now_playing() {
+        let page = parse_page(PAGE).unwrap();
+        assert_eq!(page.total_pages(), 3);
+        let plays: Vec<_> = page.plays().collect();
+        assert_eq!(plays.len(), 1);
+        assert_eq!(plays[0].album, "I Could Live in Hope");
+        assert_eq!(plays[0].ended_at, 1_700_000_000);
+    }
+
+    #[test]
+    fn api_errors_are_reported() {
+        let err = parse_page(r#"{"error": 10, "message": "Invalid API key"}"#).unwrap_err();
+        assert_eq!(err.to_string(), "Last.fm refused: Invalid API key");
+    }
+
+    #[test]
+    fn export_may_be_a_list_of_pages() {
+        let path = std::env::temp_dir().join(format!("dmr-lastfm-{}.json", std::process::id()));
+        std::fs::write(&path, format!("[{}, {}]", PAGE, PAGE)).unwrap();
+        assert_eq!(read_export(&path).unwrap().len(), 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn plays_already_counted_are_left_out() {
+        let seen_here = Play {
+            player: "Audacious".to_owned(),
+            title: "WORDS".to_owned(),
+            ..play("Words", 1000 + 200)
+        };
+        let history = [seen_here, play("Sunflower", 5000)];
+        let imported = vec![
+            play("Sunflower", 5000),
+            play("Words", 1000),
+            play("Words", 9000),
+            play("Words", 9000),
+        ];
+        assert_eq!(new_plays(&history, imported), [play("Words", 9000)]);
+    }
+}
@@ -0,0 +1,1918 @@
+extern crate futures;
+use album::AlbumSession;
+use anyhow::anyhow;
+use cli::{Cli, Command, Mode};
+use config::{Config, ScreenShare, Severity};
+use content::ContentType;
+use dbus::arg;
+use dbus::arg::{PropMap, RefArg};
+use dbus::message::MatchRule;
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::Message;
+use dbus_tokio::connection::IOResource;
+use discord::Connection;
+use discovery::Players;
+use duration::TrackDuration;
+use enrich::{Background, Enrichment, Pipeline};
+use events::{Bus, Event, PlayerState, Reporter};
+use futures::prelude::*;
+use history::PlayTracker;
+use last_played::LastPlayed;
+use log::{debug, error, info, warn};
+use merge::Merger;
+use metrics::{Failure, METRICS};
+use notify::{Outage, Problem, Streak};
+use overrides::Overrides;
+use percent_encoding::NON_ALPHANUMERIC;
+use position::{PositionTracker, Progress};
+use presence::{publish, timestamps};
+use serde::{Deserialize, Serialize};
+use session::Session;
+use std::env;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::os::unix::process::CommandExt;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use stream_cancel::{StreamExt, Tripwire};
+use template::{Placeholder, Template};
+use throttle::{Facet, Scheduler};
+use toggles::Toggles;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant, MissedTickBehavior};
+use track::TrackKey;
+use updates::Strategy;
+
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+const _PROPERTY_INTERFACE_NAME: &str = "org.freedesktop.DBus.Properties";
+/// Player properties whose changes are worth reading the player again for.
+// Position shouldn't be signalled, but some players do instead of Seeked.
+const REPORTED_PROPERTIES: [&str; 7] = [
+    "Metadata",
+    "PlaybackStatus",
+    "Rate",
+    "Position",
+    "CanPlay",
+    "CanPause",
+    "CanSeek",
+];
+
+/// How long to wait before each retry of a metadata read the player failed.
+/// Some players fail a call or two while switching tracks.
+const METADATA_RETRY_DELAYS: [Duration; 2] =
+    [Duration::from_millis(100), Duration::from_millis(400)];
+/// The longest a Discord client that can't get through is left before being
+/// replaced, the wait doubling with each replacement that doesn't help.
+const LONGEST_RESTART_WAIT: Duration = Duration::from_secs(600);
+/// How long the Discord sink gets to clear the presence on the way out.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+/// How long `--detach` watches the daemon it started before saying it's
+/// running.
+const DETACH_CHECK: Duration = Duration::from_millis(500);
+/// Why the daemon stops when the session bus goes away.
+const LOST_BUS: &str = "losing D-Bus";
+const CLIENT_ID: u64 = 1048886631823843368; // should be safe to leave public.
+
+mod album;
+mod cached_http;
+mod cli;
+mod color;
+mod config;
+mod content;
+mod control;
+mod crashes;
+mod diff;
+mod discord;
+mod discovery;
+mod duration;
+mod embed;
+mod enrich;
+mod events;
+mod flap;
+mod health;
+mod history;
+mod hooks;
+mod ipc;
+mod last_played;
+mod lastfm;
+mod locale;
+mod logging;
+mod merge;
+mod metrics;
+mod musicbrainz;
+mod notify;
+mod odesli;
+mod overrides;
+mod paths;
+mod player;
+mod position;
+mod presence;
+mod preview;
+mod processes;
+mod schedule;
+mod screenshare;
+mod seat;
+mod session;
+mod setup;
+mod simulate;
+mod stats;
+mod status;
+mod systemd;
+pub mod template;
+mod throttle;
+mod toggles;
+mod track;
+mod updates;
+mod validate;
+mod wakeups;
+mod webhook;
+mod wire;
+
+pub use embed::{pipe, Discord, MediaSource, Mpris, PresenceSink, Reading};
+
+mod keys {
+    pub const TITLE: &str = "xesam:title";
+    pub const ALBUM: &str = "xesam:album";
+    pub const ARTIST: &str = "xesam:artist";
+    pub const TRACK_ID: &str = "mpris:trackid";
+    pub const GENRE: &str = "xesam:genre";
+    pub const LENGTH: &str = "mpris:length";
+    pub const URL: &str = "xesam:url";
+    pub const TRACK_NUMBER: &str = "xesam:trackNumber";
+    pub const ART_URL: &str = "mpris:artUrl";
+}
+
+const DEFAULT_NOW_FORMAT: &str = "{artist} - {title}";
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MediaInfo {
+    title: String,
+    artist: String,
+    album: String,
+    track_id: Option<String>,
+    genres: Vec<String>,
+    player: String,
+    length: Option<TrackDuration>,
+    url: Option<String>,
+    content: ContentType,
+    /// The player can't seek in it, as with a live stream, so its position
+    /// isn't worth showing.
+    #[serde(default)]
+    unseekable: bool,
+    track_number: Option<u32>,
+    /// An image to show instead of any found by enrichment.
+    image: Option<String>,
+    /// The player's own cover, which may well be a local file.
+    #[serde(default)]
+    art_url: Option<String>,
+    /// A page where the track can be heard, given by the user.
+    link: Option<String>,
+    /// The bus name of the player it's playing in.
+    #[serde(default)]
+    bus_name: String,
+}
+
+impl Display for MediaInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let on = if self.album.is_empty() { "" } else { " on " };
+        write!(f, "{} - {}{}{}", self.artist, self.title, on, self.album)?;
+        match self.length {
+            Some(length) => write!(f, " ({})", length),
+            None => Ok(()),
+        }
+    }
+}
+
+impl MediaInfo {
+    /// A track with just a title and artist, for sources that know no more.
+    pub fn new(title: impl Into<String>, artist: impl Into<String>) -> Self {
+        MediaInfo {
+            title: title.into(),
+            artist: artist.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_album(mut self, album: impl Into<String>) -> Self {
+        self.album = album.into();
+        self
+    }
+
+    pub fn with_length(mut self, length: Duration) -> Self {
+        self.length = Some(length.into());
+        self
+    }
+
+    /// The name of the player it's playing in, as shown on Discord.
+    pub fn with_player(mut self, player: impl Into<String>) -> Self {
+        self.player = player.into();
+        self
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn artist(&self) -> &str {
+        &self.artist
+    }
+
+    pub fn album(&self) -> &str {
+        &self.album
+    }
+
+    pub fn player(&self) -> &str {
+        &self.player
+    }
+
+    pub fn genres(&self) -> &[String] {
+        &self.genres
+    }
+
+    pub fn length(&self) -> Option<Duration> {
+        self.length.map(TrackDuration::as_duration)
+    }
+
+    /// Where the player is reading it from, for files and streams.
+    pub fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    fn key(&self) -> TrackKey {
+        TrackKey::new(
+            self.track_id.as_deref(),
+            &self.artist,
+            &self.title,
+            self.url.as_deref(),
+        )
+    }
+
+    /// Whether `other` is a reading of the same track, perhaps with its tags
+    /// corrected. A player's trackids are believed over the tags, so that
+    /// tags refined mid-song don't make a new track. A trackid turning up or
+    /// going away, as some players' do while loading a track, doesn't
+    /// either; then it's the tags that tell.
+    fn same_track(&self, other: &MediaInfo) -> bool {
+        let (mine, theirs) = (self.key(), other.key());
+        match mine.is_id() && theirs.is_id() {
+            true => mine == theirs,
+            false => {
+                let by_tags =
+                    |mi: &MediaInfo| TrackKey::new(None, &mi.artist, &mi.title, mi.url.as_deref());
+                by_tags(self) == by_tags(other)
+            }
+        }
+    }
+
+    fn is_shorter_than(&self, min: Duration) -> bool {
+        self.length.is_some_and(|length| length.as_duration() < min)
+    }
+
+    /// A radio station's name: players tend to put it in the album, failing
+    /// that the stream's host will do.
+    fn station(&self) -> String {
+        match &self.album {
+            album if !album.is_empty() => album.clone(),
+            _ => self
+                .url
+                .as_deref()
+                .and_then(|url| url.split_once("://"))
+                .and_then(|(_, rest)| rest.split(['/', ':']).next())
+                .filter(|host| !host.is_empty())
+                .unwrap_or("the radio")
+                .to_owned(),
+        }
+    }
+
+    /// The song currently on air, for streams that announce it.
+    fn now_playing(&self) -> Option<String> {
+        match (self.artist.as_str(), self.title.as_str()) {
+            (_, "") => None,
+            ("", title) => Some(title.to_owned()),
+            (artist, title) => Some(format!("{} - {}", artist, title)),
+        }
+    }
+
+    fn placeholder(&self, placeholder: Placeholder, status: PlaybackStatus) -> String {
+        match placeholder {
+            Placeholder::Title => self.title.clone(),
+            Placeholder::Artist => self.artist.clone(),
+            Placeholder::Album => self.album.clone(),
+            Placeholder::Player => self.player.clone(),
+            Placeholder::Length => self
+                .length
+                .map(|length| locale::current().duration(length))
+                .unwrap_or_default(),
+            Placeholder::Genre => self.genres.join(", "),
+            Placeholder::Status => format!("{:?}", status),
+            // Depends on when it's rendered, so it's filled in by the caller.
+            Placeholder::Position => String::new(),
+        }
+    }
+
+    /// Fills in a URL template, percent-encoding each value.
+    fn render_url(&self, template: &Template, status: PlaybackStatus) -> String {
+        template.render(|p| {
+            percent_encoding::utf8_percent_encode(&self.placeholder(p, status), NON_ALPHANUMERIC)
+                .to_string()
+        })
+    }
+
+    /// Fills in `template`, with the position as of now.
+    fn render(
+        &self,
+        template: &Template,
+        status: PlaybackStatus,
+        progress: Option<&Progress>,
+    ) -> String {
+        let position = progress.map(|progress| {
+            locale::current().duration(
+                progress.position_at(SystemTime::now(), status == PlaybackStatus::Playing),
+            )
+        });
+        template.render(|p| match p {
+            Placeholder::Position => position.clone().unwrap_or_default(),
+            p => self.placeholder(p, status),
+        })
+    }
+}
+
+fn parse_metadata(metadata: &PropMap) -> anyhow::Result<MediaInfo> {
+    match (
+        arg::prop_cast(metadata, keys::TITLE).cloned(),
+        arg::prop_cast(metadata, keys::ALBUM).cloned(),
+        arg::prop_cast::<Vec<String>>(metadata, keys::ARTIST).cloned(),
+    ) {
+        (None, None, None) => Err(anyhow!("no track data returned")),
+        (title, album, artist) => {
+            let url = arg::prop_cast::<String>(metadata, keys::URL).cloned();
+            let length = TrackDuration::from_prop(metadata, keys::LENGTH);
+            Ok(MediaInfo {
+                title: title.unwrap_or_default(),
+                album: album.unwrap_or_default(),
+                artist: artist.unwrap_or_default().join(" & "),
+                track_id: parse_track_id(metadata),
+                genres: arg::prop_cast::<Vec<String>>(metadata, keys::GENRE)
+                    .cloned()
+                    .unwrap_or_default(),
+                player: String::new(),
+                length,
+                content: ContentType::detect(url.as_deref(), length),
+                unseekable: false,
+                url,
+                track_number: arg::prop_cast::<i32>(metadata, keys::TRACK_NUMBER)
+                    .and_then(|&n| u32::try_from(n).ok())
+                    .filter(|&n| n > 0),
+                image: None,
+                art_url: arg::prop_cast::<String>(metadata, keys::ART_URL)
+                    .cloned()
+                    .filter(|url| !url.is_empty()),
+                link: None,
+                bus_name: String::new(),
+            })
+        }
+    }
+}
+
+// The spec types trackid as an object path, but some players send a plain string.
+fn parse_track_id(metadata: &PropMap) -> Option<String> {
+    arg::prop_cast::<dbus::Path>(metadata, keys::TRACK_ID)
+        .map(|path| path.to_string())
+        .or_else(|| arg::prop_cast::<String>(metadata, keys::TRACK_ID).cloned())
+}
+
+/// Whether a PropertiesChanged signal touches anything that's reported.
+/// Players may list a property as invalidated instead of sending its new
+/// value; that counts the same, since the player is read afresh either way.
+fn worth_rereading(interface: &str, changed: &PropMap, invalidated: &[String]) -> bool {
+    interface == PLAYER_INTERFACE
+        && changed
+            .keys()
+            .chain(invalidated)
+            .any(|property| REPORTED_PROPERTIES.contains(&property.as_str()))
+}
+
+/// Any player on the bus may be followed, and not all keep to the spec, so
+/// a status that isn't one of the three is taken as stopped.
+fn parse_playback(playback: Option<String>) -> PlaybackStatus {
+    match playback {
+        None => PlaybackStatus::Closed,
+        Some(s) if s == "Paused" => PlaybackStatus::Paused,
+        Some(s) if s == "Playing" => PlaybackStatus::Playing,
+        Some(s) if s == "Stopped" => PlaybackStatus::Stopped,
+        Some(s) => {
+            warn!("taking the unknown playback status `{}` as stopped", s);
+            PlaybackStatus::Stopped
+        }
+    }
+}
+
+/// Reads and parses the player's metadata. D-Bus failures are retried, and
+/// if they persist the error is a `dbus::Error`; metadata the player gave but
+/// that's missing what we need isn't retried.
+async fn read_metadata(proxy: &Proxy<'_, Arc<SyncConnection>>) -> anyhow::Result<MediaInfo> {
+    let metadata = retrying(&METADATA_RETRY_DELAYS, || async {
+        let started = Instant::now();
+        let metadata: Result<PropMap, _> = proxy.get(PLAYER_INTERFACE, "Metadata").await;
+        METRICS.metadata_fetch.observe(started.elapsed());
+        metadata.inspect_err(|_| METRICS.fail(Failure::MetadataRead))
+    })
+    .await?;
+    parse_metadata(&metadata).inspect_err(|_| METRICS.fail(Failure::MissingTrackData))
+}
+
+/// Makes an attempt, then another after each of `delays` for as long as
+/// they fail, giving the last result.
+async fn retrying<T, E: Display, F: Future<Output = Result<T, E>>>(
+    delays: &[Duration],
+    mut attempt: impl FnMut() -> F,
+) -> Result<T, E> {
+    let mut result = attempt().await;
+    for &delay in delays {
+        match &result {
+            Ok(_) => break,
+            Err(e) => debug!("retrying in {:?} after: {}", delay, e),
+        }
+        tokio::time::sleep(delay).await;
+        result = attempt().await;
+    }
+    result
+}
+
+async fn read_player_name(config: &Config, proxy: &Proxy<'_, Arc<SyncConnection>>) -> String {
+    let identity = if config.player_names.contains_key(&*proxy.destination) {
+        None
+    } else {
+        player::read_identity(proxy).await
+    };
+    player::display_name(&config.player_names, &proxy.destination, identity)
+}
+
+/// What a player says it's doing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub enum PlaybackStatus {
+    Stopped,
+    Playing,
+    Paused,
+    Closed,
+}
+
+async fn read_playback_status(proxy: &Proxy<'_, Arc<SyncConnection>>) -> PlaybackStatus {
+    let status = proxy.get(PLAYER_INTERFACE, "PlaybackStatus").await;
+    if status.is_err() {
+        METRICS.fail(Failure::PlaybackStatusRead);
+    }
+    parse_playback(status.ok())
+}
+
+/// Reads Position and Rate, falling back to an estimate from earlier
+/// readings when the player won't say.
+async fn read_progress(
+    proxy: &Proxy<'_, Arc<SyncConnection>>,
+    tracker: &Mutex<PositionTracker>,
+    mi: &MediaInfo,
+    status: PlaybackStatus,
+) -> Progress {
+    let playing = status == PlaybackStatus::Playing;
+    let track = mi.key();
+    let rate: Option<f64> = proxy.get(PLAYER_INTERFACE, "Rate").await.ok();
+    let position = proxy
+        .get::<Box<dyn RefArg>>(PLAYER_INTERFACE, "Position")
+        .await
+        .ok()
+        .and_then(|position| TrackDuration::from_refarg(&position));
+    let now = Instant::now();
+    let mut tracker = tracker.lock().unwrap();
+    // Scaling by a nonsensical rate would overflow, so it's made sane here,
+    // before anything else sees it.
+    let rate = position::sane_rate(rate.or(tracker.rate()).unwrap_or(1.0));
+    let position = match position {
+        Some(position) => {
+            tracker.observe(&track, position, rate, playing, now);
+            position
+        }
+        None => {
+            METRICS.fail(Failure::PositionRead);
+            let estimate = tracker.estimate(&track, playing, now);
+            debug!("position unavailable, estimated {}", estimate);
+            estimate
+        }
+    };
+    Progress {
+        position,
+        rate,
+        at: SystemTime::now(),
+    }
+}
+
+/// Why the player is being read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Trigger {
+    /// Starting to follow it.
+    Attach,
+    /// It sent PropertiesChanged.
+    Signal,
+    Seeked,
+    /// Time to look again, for players that may not say when they change.
+    Poll,
+    /// A player took its bus name.
+    Appeared,
+    /// It let go of its bus name, so has exited.
+    Vanished,
+}
+
+/// Whether `next` has a different track or status from `previous`.
+fn shows_change(previous: &PlayerState, next: &PlayerState) -> bool {
+    let other_track = match (&previous.track, &next.track) {
+        (Some(before), Some(after)) => !before.same_track(after),
+        (before, after) => before.is_some() != after.is_some(),
+    };
+    other_track || previous.status != next.status
+}
+
+fn changed_facets(previous: Option<&PlayerState>, next: &PlayerState) -> Vec<Facet> {
+    let mut facets = Vec::new();
+    if previous.map(|state| &state.track) != Some(&next.track) {
+        facets.push(Facet::Metadata);
+    }
+    if previous.map(|state| state.status) != Some(next.status) {
+        facets.push(Facet::Playback);
+    }
+    let timestamps_moved = match (previous.and_then(timestamps), timestamps(next)) {
+        (Some(before), Some(after)) => before.drifted(&after),
+        (before, after) => before.is_some() != after.is_some(),
+    };
+    if timestamps_moved {
+        facets.push(Facet::Timestamps);
+    }
+    facets
+}
+
+async fn flag_changed(
+    flag: &mut Option<watch::Receiver<bool>>,
+) -> Result<(), watch::error::RecvError> {
+    match flag {
+        Some(rx) => rx.changed().await,
+        None => future::pending().await,
+    }
+}
+
+/// Reads the player's state directly, for when no daemon is running.
+async fn query_player(
+    config: &Config,
+    overrides: &Overrides,
+) -> anyhow::Result<(Option<MediaInfo>, PlaybackStatus, Option<Progress>)> {
+    let (resource, conn) = seat::connect(config.session_bus).await?;
+    let resource = tokio::spawn(resource);
+    let state = async {
+        let Some(name) = discovery::find(&conn, config).await? else {
+            return Ok(PlayerState::not_playing(PlaybackStatus::Closed));
+        };
+        let proxy = Proxy::new(
+            name,
+            "/org/mpris/MediaPlayer2",
+            Duration::from_secs(config.resilience.dbus_timeout),
+            conn.clone(),
+        );
+        let tracker = Mutex::new(PositionTracker::default());
+        read_player(config, overrides, &proxy, &tracker).await
+    }
+    .await;
+    resource.abort();
+    let state = state?;
+    Ok((state.track, state.status, state.progress))
+}
+
+/// Reads everything about the player behind `proxy` in one go.
+async fn read_player(
+    config: &Config,
+    overrides: &Overrides,
+    proxy: &Proxy<'_, Arc<SyncConnection>>,
+    tracker: &Mutex<PositionTracker>,
+) -> anyhow::Result<PlayerState> {
+    let status = match config.excludes_player(&proxy.destination) {
+        true => PlaybackStatus::Stopped,
+        false => read_playback_status(proxy).await,
+    };
+    match status {
+        PlaybackStatus::Playing | PlaybackStatus::Paused => {
+            let mi = read_track(config, overrides, proxy).await?;
+            let progress = read_progress(proxy, tracker, &mi, status).await;
+            Ok(PlayerState {
+                track: Some(mi),
+                status,
+                progress: Some(progress),
+            })
+        }
+        _ => Ok(PlayerState::not_playing(status)),
+    }
+}
+
+/// Reads the track the player behind `proxy` has, with everything that's
+/// worked out about it besides: the player's name, whether it can seek,
+/// and the user's overrides.
+async fn read_track(
+    config: &Config,
+    overrides: &Overrides,
+    proxy: &Proxy<'_, Arc<SyncConnection>>,
+) -> anyhow::Result<MediaInfo> {
+    let mut mi = read_metadata(proxy).await?;
+    mi.player = read_player_name(config, proxy).await;
+    mi.bus_name = proxy.destination.to_string();
+    mi.unseekable = !control::can_seek(proxy).await;
+    overrides.apply(&mut mi);
+    Ok(mi)
+}
+
+fn print_stats() -> Result<(), Box<dyn std::error::Error>> {
+    locale::init(&config::load()?.locale);
+    let path = history::path().ok_or("no data directory for the history")?;
+    println!("{}", stats::Stats::new(&history::read(&path)?));
+    Ok(())
+}
+
+/// Loads the config and overrides files as the daemon would, to find
+/// mistakes in them before restarting it.
+fn check_config() -> Result<(), Box<dyn std::error::Error>> {
+    config::load()?;
+    overrides::load()?;
+    println!("config ok");
+    Ok(())
+}
+
+/// Prints the running daemon's metrics in the OpenMetrics text format.
+fn dump_metrics() -> Result<(), Box<dyn std::error::Error>> {
+    let snapshot = status::read_live().ok_or("the daemon isn't running")?;
+    print!("{}", metrics::request_dump(snapshot.pid)?);
+    Ok(())
+}
+
+/// Lists what the running daemon recently showed on Discord, newest first.
+fn print_recent() -> Result<(), Box<dyn std::error::Error>> {
+    let snapshot = status::read_live().ok_or("the daemon isn't running")?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    for published in snapshot.recent.iter().rev() {
+        println!("{}", status::Entry { published, now });
+    }
+    Ok(())
+}
+
+/// Feeds a scenario file into the sinks in place of a player, then keeps
+/// them running until interrupted.
+async fn run_simulation(path: String) -> Result<(), Box<dyn std::error::Error>> {
+    let scenario = simulate::Scenario::load(std::path::Path::new(&path))?;
+    let mut config = config::load()?;
+    // Made-up plays have no place in the real history.
+    config.history.enabled = false;
+    let config = Arc::new(config);
+    locale::init(&config.locale);
+    let bus = Bus::new();
+    // The config stays as it is for a scenario.
+    let (_reload, reloads) = watch::channel(config.clone());
+    spawn_sinks(&config, &bus, reloads);
+    scenario.play(&bus).await;
+    info!("scenario finished; interrupt to exit");
+    tokio::signal::ctrl_c().await?;
+    status::remove();
+    Ok(())
+}
+
+/// The configured one-line template, or the built-in one.
+fn now_template(configured: Option<&Template>) -> Template {
+    match configured {
+        Some(template) => template.clone(),
+        None => Template::parse(DEFAULT_NOW_FORMAT).expect("default template is valid"),
+    }
+}
+
+/// Prints the current track using the given template and exits non-zero
+/// when nothing is playing.
+async fn print_now(format: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let config = config::load()?;
+    locale::init(&config.locale);
+    let template = match format {
+        Some(format) => Template::parse(&format)?,
+        None => now_template(config.templates.now()),
+    };
+    let (track, status, progress) = match status::read_live() {
+        Some(snapshot) => (
+            snapshot.track.map(MediaInfo::from),
+            snapshot.status,
+            snapshot.progress,
+        ),
+        None => query_player(&config, &overrides::load()?).await?,
+    };
+    match track {
+        Some(mi) => {
+            println!("{}", mi.render(&template, status, progress.as_ref()));
+            Ok(())
+        }
+        None => {
+            eprintln!("nothing playing");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Starts everything that follows the bus: the Discord presence, the status
+/// file, the play history, the webhook and the hooks. Returns what switches
+/// the toggles in the presence, and the Discord sink, which finishes once
+/// it's cleared the presence on `Stopping`.
+fn spawn_sinks(
+    config: &Arc<Config>,
+    bus: &Bus,
+    mut reloads: watch::Receiver<Arc<Config>>,
+) -> (watch::Sender<Toggles>, JoinHandle<()>) {
+    let mut discord_events = bus.subscribe();
+    let mut status_events = bus.subscribe();
+
+    // Records how far each track got once it stops playing.
+    if let Some(path) = history::path().filter(|_| config.history.enabled) {
+        let mut history_events = bus.subscribe();
+        let history_bus = bus.clone();
+        tokio::spawn(async move {
+            let mut tracker = PlayTracker::default();
+            let mut failing = Streak::default();
+            loop {
+                match history_events.recv().await {
+                    Ok(Event::State(state)) => {
+                        if let Some(play) = tracker.observe(&state, SystemTime::now()) {
+                            match history::append(&path, &play) {
+                                Ok(()) => failing.succeed(&history_bus, "Recording play history"),
+                                Err(e) => {
+                                    warn!("couldn't record play history: {}", e);
+                                    failing.fail(&history_bus, |failures| {
+                                        Problem::new(
+                                            Severity::Error,
+                                            format!(
+                                                "The last {} plays couldn't be recorded: {}",
+                                                failures, e
+                                            ),
+                                        )
+                                    });
+                                }
+                            }
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        debug!("history sink missed {} events", missed)
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    if config.hooks != config::Hooks::default() {
+        tokio::spawn(hooks::run(config.clone(), bus.subscribe()));
+    }
+
+    if config.webhook.url.is_some() {
+        match webhook::Webhook::new(&config.webhook, config.templates.webhook().cloned()) {
+            Ok(webhook) => {
+                tokio::spawn(webhook.run(bus.subscribe(), bus.clone()));
+            }
+            Err(e) => warn!("can't post to the webhook: {}", e),
+        }
+    }
+
+    if !config.notify.channels.is_empty() {
+        tokio::spawn(notify::run(config.clone(), bus.subscribe()));
+    }
+
+    debug!("channel created");
+
+    // Keeps the status snapshot in step with the player for `now` and widgets.
+    let status_template = now_template(config.templates.status());
+    let mut recent = status::Recent::new(config.status.recent);
+    let status_bus = bus.clone();
+    tokio::spawn(async move {
+        let mut failing = Streak::default();
+        let mut latest = PlayerState::not_playing(PlaybackStatus::Stopped);
+        let mut updates = None;
+        let mut enriched: Option<(TrackKey, Enrichment)> = None;
+        let (mut discord, mut dbus) = (false, false);
+        loop {
+            match status_events.recv().await {
+                Ok(Event::State(state)) => latest = state,
+                Ok(Event::DiscordConnected) => discord = true,
+                Ok(Event::DiscordLost) => discord = false,
+                Ok(Event::BusConnected) => dbus = true,
+                Ok(Event::BusLost) => dbus = false,
+                Ok(Event::Published(published)) => recent.push(published),
+                Ok(Event::UpdatesBy(strategy)) => updates = Some(strategy),
+                Ok(Event::Enriched(track, enrichment)) => enriched = Some((track, enrichment)),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    debug!("status sink missed {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            }
+            let snapshot = status::Snapshot {
+                version: wire::VERSION,
+                pid: std::process::id(),
+                status: latest.status,
+                track: latest.track.as_ref().map(wire::Track::from),
+                progress: latest.progress,
+                text: latest
+                    .track
+                    .as_ref()
+                    .map(|mi| mi.render(&status_template, latest.status, latest.progress.as_ref())),
+                recent: recent.to_vec(),
+                updates,
+                color: enriched
+                    .as_ref()
+                    .filter(|(track, _)| latest.track.as_ref().is_some_and(|mi| mi.key() == *track))
+                    .and_then(|(_, enrichment)| enrichment.color.clone()),
+                discord,
+                dbus,
+            };
+            match status::write(&snapshot) {
+                Ok(()) => failing.succeed(&status_bus, "Writing the status file"),
+                Err(e) => {
+                    debug!("couldn't write status snapshot: {}", e);
+                    failing.fail(&status_bus, |failures| {
+                        Problem::new(
+                            Severity::Warning,
+                            format!(
+                                "The status file couldn't be written the last {} times, so \
+                                 `now` may be out of date: {}",
+                                failures, e
+                            ),
+                        )
+                    });
+                }
+            }
+        }
+    });
+
+    let (toggles_tx, mut toggles) = watch::channel(Toggles::default());
+    let discord_config = config.clone();
+    let discord_bus = bus.clone();
+    let discord_client = tokio::spawn(async move {
+        let resilience = &discord_config.resilience;
+        let mut connection = Connection::start(
+            discord_config.client_id.unwrap_or(CLIENT_ID),
+            Duration::from_secs(resilience.discord_handshake),
+        );
+        // What's shown follows the config as it's reloaded; the rest of
+        // the sink is set up once.
+        let mut presence_config = reloads.borrow_and_update().clone();
+        debug!("discord client started");
+        // Updates are held back until Discord has answered the handshake,
+        // however long that takes, rather than being sent into the void.
+        let mut discord_ready = false;
+        let mut ready_poll = tokio::time::interval(Duration::from_secs(resilience.discord_poll));
+        // Ticks skipped while idle aren't made up for all at once.
+        ready_poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let dead_after = Duration::from_secs(resilience.discord_dead_after);
+        let mut restart_after = dead_after;
+        let mut outage = Outage::new(Duration::from_secs(discord_config.notify.discord_after));
+        let mut scheduler = Scheduler::new(&discord_config.throttle);
+        scheduler.settle(Instant::now());
+        let mut latest: Option<PlayerState> = None;
+        let mut last_played = LastPlayed::default();
+        let mut album = AlbumSession::default();
+        let mut session: Option<Session> = None;
+        let mut enricher = Background::new(
+            Pipeline::new(&discord_config),
+            discord_config.enrichment.placeholder.clone(),
+        );
+        let mut expiry = Duration::from_secs(presence_config.last_played.expiry);
+        let mut quiet_hours = presence_config.quiet_hours.schedule();
+        let pause_while_running = &discord_config.pause_while_running;
+        let mut pausing = (!pause_while_running.processes.is_empty()).then(|| {
+            processes::watch(
+                pause_while_running.processes.clone(),
+                Duration::from_secs(pause_while_running.poll),
+            )
+        });
+        let mut sharing = match discord_config.screen_share {
+            ScreenShare::Show => None,
+            _ => screenshare::watch(discord_config.session_bus)
+                .await
+                .map_err(|e| warn!("can't watch for screen sharing: {}", e))
+                .ok(),
+        };
+        loop {
+            let due = scheduler.next_due(Instant::now());
+            let quiet_change = quiet_hours
+                .as_ref()
+                .and_then(|quiet| quiet.until_next_boundary(jiff::Timestamp::now()))
+                .map(|wait| Instant::now() + wait);
+            // With nothing to send or showing, there's no call to check on
+            // Discord; it's looked at again as soon as there is.
+            let idle = due.is_none()
+                && latest
+                    .as_ref()
+                    .is_none_or(|state| state.status != PlaybackStatus::Playing)
+                && last_played.track().is_none();
+            if idle {
+                outage.idle();
+            }
+            tokio::select! {
+                event = discord_events.recv() => match event {
+                    Ok(Event::State(state)) => {
+                        if presence_config.remembers_last_played() {
+                            last_played.observe(&state, Instant::now());
+                        }
+                        if let Some(mi) = &state.track {
+                            enricher.request(mi);
+                        }
+                        album.observe(&state, timestamps(&state));
+                        for facet in changed_facets(latest.as_ref(), &state) {
+                            scheduler.mark(facet, Instant::now());
+                        }
+                        latest = Some(state);
+                    }
+                    Ok(Event::PlayerAppeared) => scheduler.settle(Instant::now()),
+                    Ok(Event::Replayed(mi)) => {
+                        info!(event = logging::event::REPLAY, player = mi.bus_name.as_str(); "replaying {}", mi);
+                        scheduler.mark(Facet::Timestamps, Instant::now());
+                    }
+                    Ok(Event::Stopping) => {
+                        presence::clear(&mut connection);
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => debug!("discord sink missed {} events", missed),
+                    Err(RecvError::Closed) => break,
+                },
+                () = enricher.finished() => {
+                    scheduler.mark(Facet::Metadata, Instant::now());
+                    if let Some(mi) = latest.as_ref().and_then(|state| state.track.as_ref()) {
+                        discord_bus.send(Event::Enriched(mi.key(), enricher.for_track(mi)));
+                    }
+                },
+                Ok(()) = flag_changed(&mut sharing) => {
+                    scheduler.mark(Facet::Metadata, Instant::now());
+                },
+                Ok(()) = flag_changed(&mut pausing) => {
+                    scheduler.mark(Facet::Playback, Instant::now());
+                },
+                Ok(()) = toggles.changed() => {
+                    scheduler.mark(Facet::Metadata, Instant::now());
+                },
+                Ok(()) = reloads.changed() => {
+                    presence_config = reloads.borrow_and_update().clone();
+                    expiry = Duration::from_secs(presence_config.last_played.expiry);
+                    quiet_hours = presence_config.quiet_hours.schedule();
+                    scheduler.mark(Facet::Metadata, Instant::now());
+                },
+                _ = sleep_until(last_played.expires_at(expiry).unwrap_or_else(Instant::now)),
+                    if last_played.expires_at(expiry).is_some() =>
+                {
+                    wakeups::trace("discord", "last played expired");
+                    last_played.forget();
+                    scheduler.mark(Facet::Playback, Instant::now());
+                },
+                _ = sleep_until(quiet_change.unwrap_or_else(Instant::now)), if quiet_change.is_some() => {
+                    wakeups::trace("discord", "quiet hours");
+                    scheduler.mark(Facet::Playback, Instant::now());
+                },
+                _ = ready_poll.tick(), if !idle => {
+                    wakeups::trace("discord", "checking the connection");
+                    if connection.is_dead(restart_after) {
+                        warn!("the Discord client has stopped getting through, starting a new one");
+                        connection.restart().await;
+                        restart_after = (restart_after * 2).min(dead_after.max(LONGEST_RESTART_WAIT));
+                    }
+                    if discord_ready && connection.is_failing() {
+                        debug!("lost discord");
+                        discord_ready = false;
+                        discord_bus.send(Event::DiscordLost);
+                    } else if !discord_ready && connection.is_ready() && !connection.is_failing() {
+                        debug!("discord ready");
+                        discord_ready = true;
+                        restart_after = dead_after;
+                        discord_bus.send(Event::DiscordConnected);
+                        // Whatever was shown may have gone with the old connection.
+                        scheduler.mark(Facet::Metadata, Instant::now());
+                        if let Some(problem) = outage.reachable() {
+                            discord_bus.send(Event::Problem(problem));
+                        }
+                    }
+                    if !discord_ready {
+                        if let Some(problem) = outage.unreachable(Instant::now()) {
+                            discord_bus.send(Event::Problem(problem));
+                        }
+                    }
+                },
+                _ = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() && discord_ready => {
+                    wakeups::trace("discord", "publishing");
+                    let quiet = quiet_hours
+                        .as_ref()
+                        .is_some_and(|quiet| quiet.contains(jiff::Timestamp::now()));
+                    let paused = pausing.as_ref().is_some_and(|rx| *rx.borrow());
+                    let hidden = !toggles.borrow().presence;
+                    if quiet || paused || hidden {
+                        presence::clear(&mut connection);
+                    } else if let Some(state) = &latest {
+                        if let Some(mi) = &state.track {
+                            let client_id = discord::application_id(
+                                &presence_config.applications,
+                                presence_config.client_id.unwrap_or(CLIENT_ID),
+                                mi,
+                            );
+                            connection.switch_to(client_id).await;
+                        }
+                        let enrichment = match &state.track {
+                            Some(mi) => enricher.for_track(mi),
+                            None => Enrichment::default(),
+                        };
+                        let sharing = sharing.as_ref().is_some_and(|rx| *rx.borrow());
+                        let toggles = *toggles.borrow();
+                        let published = publish(
+                            &mut connection,
+                            state,
+                            &presence_config,
+                            &Toggles {
+                                privacy: toggles.privacy || sharing,
+                                ..toggles
+                            },
+                            last_played
+                                .track()
+                                .filter(|_| presence_config.shows_last_played(state.status)),
+                            &enrichment,
+                            &album,
+                        );
+                        match published {
+                            Ok(Some(published)) => {
+                                discord_bus.send(Event::Published(published));
+                                let showing = Session::of(state, SystemTime::now()).filter(|showing| {
+                                    session.as_ref().is_none_or(|saved| !saved.shows_same(showing))
+                                });
+                                if let Some(showing) = showing {
+                                    if let Err(e) = session::save(&showing) {
+                                        debug!("couldn't save the session: {}", e);
+                                    }
+                                    session = Some(showing);
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                debug!("Discord didn't take the presence, trying again: {}", e);
+                                scheduler.failed(Instant::now());
+                                continue;
+                            }
+                        }
+                    }
+                    scheduler.published(Instant::now());
+                }
+            }
+        }
+    });
+
+    debug!("discord client spawned");
+    (toggles_tx, discord_client)
+}
+
+/// Runs the command named on the command line, or the daemon without one.
+pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = cli::parse(env::args()).unwrap_or_else(|e| e.exit());
+    logging::init(cli.as_service());
+    debug!("started");
+    if cli.trace_wakeups {
+        wakeups::enable();
+    }
+    let mode = match cli.command {
+        Command::Daemon(mode) => mode,
+        Command::Healthcheck => {
+            let config = config::load()?;
+            let health = health::check(config.session_bus).await;
+            println!("{}", health);
+            if !health.is_healthy() {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Command::Now { format } => return print_now(format).await,
+        Command::Stats => return print_stats(),
+        Command::Recent => return print_recent(),
+        Command::Check => return check_config(),
+        Command::Setup => return Ok(setup::run().await?),
+        Command::MetricsDump => return dump_metrics(),
+        Command::ImportLastfm { source } => return Ok(lastfm::import(&source).await?),
+        Command::Toggle { name, on } => {
+            let config = config::load()?;
+            return Ok(toggles::run(&config, name, on).await?);
+        }
+        Command::Control { action, seconds } => {
+            let config = config::load()?;
+            return Ok(control::run(&config, &action, seconds.into_iter()).await?);
+        }
+        Command::Simulate { scenario } => return run_simulation(scenario).await,
+    };
+    if mode == Mode::Detach {
+        return detach(&cli).await;
+    }
+    let mut config = config::load()?;
+    let (crash_guard, safe) = match crashes::start() {
+        Ok((guard, safe)) => (Some(guard), safe),
+        Err(e) => {
+            warn!("can't keep count of crashes: {}", e);
+            (None, false)
+        }
+    };
+    if safe {
+        warn!(
+            "SAFE MODE: the daemon crashed repeatedly in the last few minutes, so only the \
+             player and Discord are running: no enrichment, webhook, notices, hooks, history, \
+             screen share or process watching, and no extra buses"
+        );
+        config.safe_mode();
+    }
+    let config = Arc::new(config);
+    let overrides = overrides::load()?;
+    locale::init(&config.locale);
+    let (resource, conn): (IOResource<SyncConnection>, Arc<SyncConnection>) =
+        seat::connect(config.session_bus).await?;
+
+    debug!("connection created");
+    // Stops following the player, so everything winds down and the exit
+    // counts as clean, on SIGTERM or SIGINT, or a newline in console mode.
+    // Losing the bus stops it too, though the exit is then an error.
+    let (stop, mut stopping) = tokio::sync::mpsc::channel(1);
+    // The resource is a task that should be spawned onto a tokio compatible
+    // reactor ASAP. If the resource ever finishes, you lost connection to D-Bus.
+    let lost_bus = stop.clone();
+    let bus = Bus::new();
+    let bus_events = bus.clone();
+    tokio::spawn(async move {
+        let err = resource.await;
+        error!("lost connection to D-Bus: {}", err);
+        bus_events.send(Event::BusLost);
+        let _ = lost_bus.send(LOST_BUS).await;
+    });
+
+    let mut conns = vec![conn];
+    for address in &config.extra_buses {
+        match seat::connect_to(address) {
+            Ok((resource, conn)) => {
+                let address = address.clone();
+                tokio::spawn(async move {
+                    let err = resource.await;
+                    warn!("lost connection to the bus at {}: {}", address, err);
+                });
+                conns.push(conn);
+            }
+            Err(e) => warn!("can't connect to the bus at {}: {}", address, e),
+        }
+    }
+
+    debug!("connection spawned");
+    let rule = MatchRule::new_signal("org.freedesktop.DBus.Properties", "PropertiesChanged")
+        .with_path("/org/mpris/MediaPlayer2");
+    let seeked_rule =
+        MatchRule::new_signal(PLAYER_INTERFACE, "Seeked").with_path("/org/mpris/MediaPlayer2");
+    let owner_rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged")
+        .with_sender("org.freedesktop.DBus");
+
+    // Which players there are on each bus, and which is followed.
+    let players: Vec<Arc<Mutex<Players>>> = conns
+        .iter()
+        .map(|_| Arc::new(Mutex::new(Players::new(&config))))
+        .collect();
+
+    let (reload, reloads) = watch::channel(config.clone());
+    let (toggles, discord_client) = spawn_sinks(&config, &bus, reloads);
+    bus.send(Event::BusConnected);
+    if let Err(e) = toggles::serve(&conns[0], toggles).await {
+        warn!(
+            "can't take the bus name {}, so toggle won't work: {}",
+            toggles::BUS_NAME,
+            e
+        );
+    }
+
+    // SIGHUP reloads the config, though only what the presence shows
+    // changes before a restart.
+    let mut hup = signal(SignalKind::hangup())?;
+    let reload_bus = bus.clone();
+    tokio::spawn(async move {
+        while hup.recv().await.is_some() {
+            match config::load() {
+                Ok(mut reloaded) => {
+                    if safe {
+                        reloaded.safe_mode();
+                    }
+                    info!("reloaded the config");
+                    reload.send_replace(Arc::new(reloaded));
+                }
+                Err(e) => {
+                    warn!("keeping the config as it was: {:#}", e);
+                    reload_bus.send(Event::Problem(Problem::new(
+                        Severity::Warning,
+                        format!("The config couldn't be reloaded: {:#}", e),
+                    )));
+                }
+            }
+        }
+    });
+
+    // SIGUSR1 dumps the current metrics to the log, and for `metrics dump`.
+    let mut usr1 = signal(SignalKind::user_defined1())?;
+    tokio::spawn(async move {
+        while usr1.recv().await.is_some() {
+            info!(event = logging::event::METRICS; "metrics\n{}", METRICS);
+            if let Err(e) = metrics::write_dump(&metrics::dump_path()) {
+                warn!("couldn't write the metrics: {}", e);
+            }
+        }
+    });
+
+    let (trigger, tripwire) = Tripwire::new();
+    let mut matches = Vec::new();
+    let mut triggers = Vec::new();
+    let mut pollers = Vec::new();
+    for (source, conn) in conns.iter().enumerate() {
+        let (signal, changes) = conn.add_match(rule.clone()).await?.stream();
+        // A seek doesn't change any property, so comes in on its own signal.
+        let (seeked_signal, seeks) = conn.add_match(seeked_rule.clone()).await?.stream();
+        let (owner_signal, owners) = conn.add_match(owner_rule.clone()).await?.stream();
+        matches.extend([signal, seeked_signal, owner_signal].map(|m| (conn.clone(), m)));
+        // Players may start before or after us, and may restart; the one to
+        // follow is read once now and again whenever that changes.
+        let followed = &players[source];
+        let timeout = Duration::from_secs(config.resilience.dbus_timeout);
+        if let Err(e) = discovery::discover(conn, followed, timeout).await {
+            warn!("can't list the players on the bus: {}", e);
+        }
+        let owner_changes = owners.filter_map({
+            let players = followed.clone();
+            move |(_, (name, _, new_owner)): (_, (String, String, String))| {
+                future::ready(players.lock().unwrap().owner_changed(&name, &new_owner))
+            }
+        });
+        // Not every player says when it changes, so they're all polled
+        // until it's clear whether this one does.
+        let poll_every = Duration::from_secs(config.resilience.player_poll);
+        // Only while there's a player to read, though, so that nothing
+        // wakes the daemon while there isn't.
+        let (polling, switch) = watch::channel(false);
+        pollers.push(polling);
+        let polls = stream::unfold(switch, move |mut switch| async move {
+            switch.wait_for(|&on| on).await.ok()?;
+            tokio::time::sleep(poll_every).await;
+            Some((Trigger::Poll, switch))
+        });
+        // Every player's signals come in, but only the followed one's are
+        // read; another starting to play may take over from it.
+        let signals = changes.filter_map({
+            let players = followed.clone();
+            move |(message, (interface, changed, invalidated)): (
+                Message,
+                (String, PropMap, Vec<String>),
+            )| {
+                let sender = message.sender().map(|s| s.to_string()).unwrap_or_default();
+                let mut players = players.lock().unwrap();
+                let trigger = match players.follows(&sender) {
+                    true => worth_rereading(&interface, &changed, &invalidated)
+                        .then_some(Trigger::Signal),
+                    false if discovery::says_playing(&changed) => players.started_playing(&sender),
+                    false => None,
+                };
+                future::ready(trigger)
+            }
+        });
+        let seeks = seeks.filter_map({
+            let players = followed.clone();
+            move |(message, _): (Message, (i64,))| {
+                let sender = message.sender().map(|s| s.to_string()).unwrap_or_default();
+                future::ready(
+                    players
+                        .lock()
+                        .unwrap()
+                        .follows(&sender)
+                        .then_some(Trigger::Seeked),
+                )
+            }
+        });
+        let source_triggers = stream::once(future::ready(Trigger::Attach)).chain(stream::select(
+            stream::select(signals, seeks),
+            stream::select(owner_changes, polls),
+        ));
+        // A player that's back soon after going, as a browser's may be on
+        // changing tabs, is taken never to have gone.
+        let grace = {
+            let players = followed.clone();
+            move || players.lock().unwrap().grace()
+        };
+        triggers.push(
+            flap::hold_vanishing(source_triggers, grace)
+                .map(move |trigger| (source, trigger))
+                .boxed(),
+        );
+    }
+    let triggers = stream::select_all(triggers).take_until_if(tripwire);
+    let trackers: Vec<_> = conns
+        .iter()
+        .map(|_| Mutex::new(PositionTracker::default()))
+        .collect();
+    let announce = |player: &str, strategy: Option<Strategy>| {
+        if let Some(strategy) = strategy {
+            info!(player = player; "following the player by {:?}", strategy);
+            bus.send(Event::UpdatesBy(strategy));
+        }
+    };
+    let merger = Mutex::new(Merger::new(
+        conns.len(),
+        config.on_close == config::OnClose::NextPlayer,
+        config.player_priority.clone(),
+    ));
+    // What was showing before a restart, until the first reading of a
+    // playing track, which either carries on from it or doesn't.
+    let restoring = Mutex::new(session::load());
+    let reporter = Mutex::new(Reporter::default());
+    let report = |state: PlayerState| {
+        let mut reporter = reporter.lock().unwrap();
+        // Only what changed goes in the log, so it stays readable however
+        // long the daemon runs.
+        if let Some(changes) = diff::describe(reporter.previous(), &state) {
+            let event = match state.track {
+                Some(_) => logging::event::TRACK,
+                None => logging::event::NOT_PLAYING,
+            };
+            let track_id = state.track.as_ref().and_then(|mi| mi.track_id.as_deref());
+            let player = state.track.as_ref().map(|mi| mi.bus_name.as_str());
+            info!(
+                event = event,
+                player = player.unwrap_or_default(),
+                track_id = track_id.unwrap_or_default();
+                "{}", changes
+            );
+        }
+        for event in reporter.report(state) {
+            bus.send(event);
+        }
+    };
+    // Each read gets its own copies of these references.
+    let (config, overrides, restoring, merger) = (&*config, &overrides, &restoring, &merger);
+    let (bus, report, players, trackers) = (&bus, &report, &players, &trackers);
+    let (conns, timeout) = (&conns, Duration::from_secs(config.resilience.dbus_timeout));
+    let (announce, pollers) = (&announce, &pollers);
+    let stream_fut = triggers.for_each(|(source, trigger)| {
+        async move {
+            wakeups::trace("player", format_args!("{:?}", trigger));
+            let followed = players[source]
+                .lock()
+                .unwrap()
+                .followed()
+                .map(str::to_owned);
+            let name = followed.as_deref().unwrap_or_default();
+            // With no player on the bus there's nobody to ask.
+            let proxy = followed.as_deref().map(|name| {
+                Proxy::new(
+                    name,
+                    "/org/mpris/MediaPlayer2",
+                    timeout,
+                    conns[source].clone(),
+                )
+            });
+            let tracker = &trackers[source];
+            // The bus's players, which know how the one followed announces
+            // its changes.
+            let on_bus = || players[source].lock().unwrap();
+            let polled = trigger == Trigger::Poll;
+            if polled && !on_bus().detector(name).polls() {
+                return;
+            }
+            match &trigger {
+                Trigger::Signal => announce(name, on_bus().detector(name).signalled()),
+                Trigger::Appeared => {
+                    bus.send(Event::PlayerAppeared);
+                    announce(name, Some(on_bus().detector(name).strategy()))
+                }
+                Trigger::Vanished => bus.send(Event::PlayerVanished),
+                _ => {}
+            }
+            // There's no asking a player that's exited, or that there isn't;
+            // it's closed.
+            let proxy = proxy.as_ref().filter(|_| trigger != Trigger::Vanished);
+            // Readings from a poll show whether the player changed without
+            // saying so.
+            let update = |state: PlayerState| {
+                let mut merger = merger.lock().unwrap();
+                if polled {
+                    let changed = merger
+                        .reading(source)
+                        .is_some_and(|before| shows_change(before, &state));
+                    announce(name, on_bus().detector(name).polled(changed));
+                }
+                // Only while it's playing, so that nothing is asked of the
+                // bus while nothing is. A player that never signals isn't
+                // noticed starting again until it's read for another reason.
+                let polls = on_bus().detector(name).polls();
+                pollers[source].send_replace(state.status == PlaybackStatus::Playing && polls);
+                merger.update(source, state)
+            };
+            let status = match proxy {
+                None => PlaybackStatus::Closed,
+                Some(proxy) => {
+                    debug!("about to read a playback status");
+                    let status: PlaybackStatus = read_playback_status(proxy).await;
+                    debug!("read a playback status");
+                    status
+                }
+            };
+            if let (PlaybackStatus::Paused | PlaybackStatus::Playing, Some(proxy)) = (status, proxy)
+            {
+                let mi = match read_track(config, overrides, proxy).await {
+                    Ok(mi) => mi,
+                    // The player is there but not answering; the status is
+                    // still news, so it goes out with what we last knew.
+                    Err(e) if e.is::<dbus::Error>() => {
+                        let last = merger
+                            .lock()
+                            .unwrap()
+                            .reading(source)
+                            .and_then(|state| state.track.clone());
+                        match last {
+                            Some(last) => {
+                                warn!("couldn't read metadata ({}); reusing the last known", e);
+                                last
+                            }
+                            None => {
+                                warn!("couldn't read metadata: {}", e);
+                                return;
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        debug!("unusable metadata: {}", e);
+                        return;
+                    }
+                };
+                if mi.is_shorter_than(Duration::from_secs(config.min_length)) {
+                    debug!("ignoring {}, as it's shorter than min_length", mi);
+                    return;
+                }
+                let saved = restoring.lock().unwrap().take();
+                let now = SystemTime::now();
+                if let Some(position) = saved.as_ref().and_then(|s| s.position_at(&mi.key(), now)) {
+                    let playing = status == PlaybackStatus::Playing;
+                    tracker.lock().unwrap().observe(
+                        &mi.key(),
+                        position,
+                        1.0,
+                        playing,
+                        Instant::now(),
+                    );
+                }
+                let progress = read_progress(proxy, tracker, &mi, status).await;
+                debug!("read {}", mi);
+                let mut state = PlayerState {
+                    track: Some(mi),
+                    status,
+                    progress: Some(progress),
+                };
+                if let Some(progress) = saved.and_then(|saved| saved.restore(&state, now)) {
+                    debug!("carrying on with the timestamps from before restarting");
+                    state.progress = Some(progress);
+                }
+                let merged = update(state);
+                if let Some(state) = merged {
+                    report(state);
+                }
+            } else {
+                debug!("not playing");
+                let state = PlayerState::not_playing(status);
+                let merged = update(state);
+                if let Some(state) = merged {
+                    report(state);
+                }
+            }
+            tokio::task::yield_now().await
+        }
+    });
+
+    match mode == Mode::Console && std::io::stdin().is_terminal() {
+        false => debug!("running as a service"),
+        true => {
+            debug!("running in console mode");
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                let mut buffer = String::new();
+                debug!("pausing forever (until newln)");
+                let _ = std::io::stdin().read_line(&mut buffer);
+                let _ = stop.blocking_send("a newline");
+            });
+        }
+    }
+    for (kind, name) in [
+        (SignalKind::terminate(), "SIGTERM"),
+        (SignalKind::interrupt(), "SIGINT"),
+    ] {
+        let mut received = signal(kind)?;
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            received.recv().await;
+            let _ = stop.send(name).await;
+        });
+    }
+    let stopper = tokio::spawn(async move {
+        let reason = stopping.recv().await;
+        if let Some(reason) = reason {
+            debug!("stopping on {}", reason);
+        }
+        systemd::notify("STOPPING=1");
+        // There's no removing matches from a bus that's gone.
+        if reason != Some(LOST_BUS) {
+            for (conn, signal) in matches {
+                let _ = conn.remove_match(signal.token()).await;
+            }
+        }
+        drop(trigger);
+        reason
+    });
+    systemd::notify("READY=1");
+    stream_fut.await;
+    debug!("future ended");
+    bus.send(Event::Stopping);
+    if tokio::time::timeout(SHUTDOWN_GRACE, discord_client)
+        .await
+        .is_err()
+    {
+        debug!("gave up waiting for the presence to be cleared");
+    }
+    let lost_bus = stopper.now_or_never().and_then(Result::ok).flatten() == Some(LOST_BUS);
+    status::remove();
+    let _ = std::fs::remove_file(metrics::dump_path());
+    if let Some(guard) = crash_guard {
+        guard.exited();
+    }
+    info!(event = logging::event::METRICS; "metrics\n{}", METRICS);
+    if lost_bus {
+        return Err("lost the connection to D-Bus".into());
+    }
+    Ok(())
+}
+
+/// Starts the daemon again as a service, in its own process group so that
+/// it outlives the terminal, and returns once it's clearly not failed to
+/// start. Its log goes to the journal, if there is one.
+async fn detach(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = std::process::Command::new(env::current_exe()?)
+        .args(cli.service_args())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    // Anything wrong with the config or the arguments shows by then.
+    tokio::time::sleep(DETACH_CHECK).await;
+    if let Some(status) = child.try_wait()? {
+        return Err(format!(
+            "the daemon stopped straight away ({}); run it without --detach to see why",
+            status
+        )
+        .into());
+    }
+    println!("started the daemon as process {}", child.id());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn station_falls_back_to_stream_host() {
+        let media_info = MediaInfo {
+            url: Some("http://ice.example.org:8000/live".to_owned()),
+            content: ContentType::Stream,
+            ..Default::default()
+        };
+        assert_eq!(media_info.station(), "ice.example.org");
+        assert_eq!(media_info.now_playing(), None);
+    }
+
+    #[test]
+    fn only_known_short_lengths_are_too_short() {
+        let min = Duration::from_secs(10);
+        let chime = MediaInfo {
+            length: TrackDuration::from_micros(2_000_000),
+            ..Default::default()
+        };
+        assert!(chime.is_shorter_than(min));
+        assert!(!MediaInfo::default().is_shorter_than(min));
+        assert!(!chime.is_shorter_than(Duration::ZERO));
+    }
+
+    #[test]
+    fn content_type_detected_from_url() {
+        let mut metadata = PropMap::new();
+        metadata.insert(
+            keys::TITLE.to_owned(),
+            arg::Variant(Box::new("Heat".to_owned())),
+        );
+        metadata.insert(
+            keys::URL.to_owned(),
+            arg::Variant(Box::new("file:///films/heat.mkv".to_owned())),
+        );
+        let media_info = parse_metadata(&metadata).unwrap();
+        assert_eq!(media_info.url.as_deref(), Some("file:///films/heat.mkv"));
+        assert_eq!(media_info.content, ContentType::Video);
+    }
+
+    #[test]
+    fn art_url_is_read_unless_blank() {
+        let mut metadata = PropMap::new();
+        metadata.insert(
+            keys::TITLE.to_owned(),
+            arg::Variant(Box::new("River".to_owned())),
+        );
+        metadata.insert(
+            keys::ART_URL.to_owned(),
+            arg::Variant(Box::new("https://example.org/blue.jpg".to_owned())),
+        );
+        let media_info = parse_metadata(&metadata).unwrap();
+        assert_eq!(
+            media_info.art_url.as_deref(),
+            Some("https://example.org/blue.jpg")
+        );
+        metadata.insert(
+            keys::ART_URL.to_owned(),
+            arg::Variant(Box::new(String::new())),
+        );
+        assert_eq!(parse_metadata(&metadata).unwrap().art_url, None);
+    }
+
+    #[test]
+    fn track_number_parsed_when_positive() {
+        let mut metadata = PropMap::new();
+        metadata.insert(
+            keys::TITLE.to_owned(),
+            arg::Variant(Box::new("t".to_owned())),
+        );
+        metadata.insert(keys::TRACK_NUMBER.to_owned(), arg::Variant(Box::new(4i32)));
+        assert_eq!(parse_metadata(&metadata).unwrap().track_number, Some(4));
+        metadata.insert(keys::TRACK_NUMBER.to_owned(), arg::Variant(Box::new(0i32)));
+        assert_eq!(parse_metadata(&metadata).unwrap().track_number, None);
+    }
+
+    #[test]
+    fn track_id_parsed_from_object_path() {
+        let mut metadata = PropMap::new();
+        metadata.insert(
+            keys::TRACK_ID.to_owned(),
+            arg::Variant(Box::new(dbus::Path::from(
+                "/org/mpris/MediaPlayer2/Track/7",
+            ))),
+        );
+        assert_eq!(
+            parse_track_id(&metadata),
+            Some("/org/mpris/MediaPlayer2/Track/7".to_owned())
+        );
+    }
+
+    #[test]
+    fn track_id_parsed_from_string() {
+        let mut metadata = PropMap::new();
+        metadata.insert(
+            keys::TRACK_ID.to_owned(),
+            arg::Variant(Box::new("spotify:track:abc".to_owned())),
+        );
+        assert_eq!(
+            parse_track_id(&metadata),
+            Some("spotify:track:abc".to_owned())
+        );
+    }
+
+    #[test]
+    fn media_info_display_includes_known_length() {
+        let media_info = MediaInfo {
+            artist: "artist".to_owned(),
+            title: "title".to_owned(),
+            length: TrackDuration::from_micros(225_000_000),
+            ..Default::default()
+        };
+        assert_eq!(media_info.to_string(), "artist - title (3:45)");
+    }
+
+    #[test]
+    fn placeholders_read_media_info() {
+        let media_info = MediaInfo {
+            title: "title".to_owned(),
+            genres: vec!["Jazz".to_owned(), "Fusion".to_owned()],
+            length: TrackDuration::from_micros(61_000_000),
+            ..Default::default()
+        };
+        let template = Template::parse("{title} [{genre}] {length} {status}").unwrap();
+        assert_eq!(
+            template.render(|p| media_info.placeholder(p, PlaybackStatus::Paused)),
+            "title [Jazz, Fusion] 1:01 Paused"
+        );
+    }
+
+    #[test]
+    fn render_fills_position_from_progress() {
+        let media_info = MediaInfo {
+            title: "title".to_owned(),
+            ..Default::default()
+        };
+        let progress = Progress {
+            position: TrackDuration::from_micros(75_000_000).unwrap(),
+            rate: 1.0,
+            at: SystemTime::now(),
+        };
+        let template = Template::parse("{title} @ {position}").unwrap();
+        assert_eq!(
+            media_info.render(&template, PlaybackStatus::Paused, Some(&progress)),
+            "title @ 1:15"
+        );
+        assert_eq!(
+            media_info.render(&template, PlaybackStatus::Paused, None),
+            "title @ "
+        );
+    }
+
+    #[test]
+    fn position_alone_is_no_change_to_notice() {
+        let playing = |title: &str, position| PlayerState {
+            track: Some(MediaInfo::new(title, "artist")),
+            status: PlaybackStatus::Playing,
+            progress: Some(Progress {
+                position: Duration::from_secs(position).into(),
+                rate: 1.0,
+                at: SystemTime::now(),
+            }),
+        };
+        assert!(!shows_change(&playing("a", 0), &playing("a", 30)));
+        assert!(shows_change(&playing("a", 0), &playing("b", 0)));
+        assert!(shows_change(
+            &playing("a", 0),
+            &PlayerState::not_playing(PlaybackStatus::Stopped)
+        ));
+    }
+
+    #[test]
+    fn first_message_changes_every_facet() {
+        let message = PlayerState::not_playing(PlaybackStatus::Stopped);
+        assert_eq!(
+            changed_facets(None, &message),
+            vec![Facet::Metadata, Facet::Playback]
+        );
+    }
+
+    #[test]
+    fn pausing_only_changes_playback() {
+        let media_info = MediaInfo {
+            title: "title".to_owned(),
+            ..Default::default()
+        };
+        let playing = PlayerState {
+            track: Some(media_info.clone()),
+            status: PlaybackStatus::Playing,
+            progress: None,
+        };
+        let paused = PlayerState {
+            status: PlaybackStatus::Paused,
+            ..playing.clone()
+        };
+        assert_eq!(
+            changed_facets(Some(&playing), &paused),
+            vec![Facet::Playback]
+        );
+    }
+
+    #[test]
+    fn repeated_message_changes_nothing() {
+        let message = PlayerState::not_playing(PlaybackStatus::Stopped);
+        assert!(changed_facets(Some(&message), &message).is_empty());
+    }
+
+    #[test]
+    fn seeking_changes_only_timestamps() {
+        let media_info = MediaInfo {
+            length: TrackDuration::from_micros(200_000_000),
+            ..Default::default()
+        };
+        let progress = |secs: i64| Progress {
+            position: TrackDuration::from_micros(secs * 1_000_000).unwrap(),
+            rate: 1.0,
+            at: SystemTime::UNIX_EPOCH,
+        };
+        let first = PlayerState {
+            track: Some(media_info.clone()),
+            status: PlaybackStatus::Playing,
+            progress: Some(progress(0)),
+        };
+        let second = PlayerState {
+            track: Some(media_info),
+            status: PlaybackStatus::Playing,
+            progress: Some(progress(60)),
+        };
+        assert_eq!(
+            changed_facets(Some(&first), &second),
+            vec![Facet::Timestamps]
+        );
+    }
+
+    #[test]
+    fn parsing_playback_status_closed_when_no_value_present() {
+        parse_playback(None);
+    }
+
+    #[test]
+    fn parsing_playback_paused() {
+        assert_eq!(
+            parse_playback(Some("Paused".to_string())),
+            PlaybackStatus::Paused
+        );
+    }
+
+    #[test]
+    fn parsing_playback_playing() {
+        assert_eq!(
+            parse_playback(Some("Playing".to_string())),
+            PlaybackStatus::Playing
+        );
+    }
+
+    #[test]
+    fn parsing_playback_stopped() {
+        assert_eq!(
+            parse_playback(Some("Stopped".to_string())),
+            PlaybackStatus::Stopped
+        );
+    }
+
+    #[test]
+    fn unknown_playback_status_is_stopped() {
+        assert_eq!(
+            parse_playback(Some("Fish".to_owned())),
+            PlaybackStatus::Stopped
+        );
+    }
+
+    #[test]
+    fn invalidated_properties_are_reread() {
+        let invalidated = ["Metadata".to_owned()];
+        assert!(worth_rereading(
+            PLAYER_INTERFACE,
+            &PropMap::new(),
+            &invalidated
+        ));
+    }
+
+    #[test]
+    fn changed_properties_are_reread() {
+        let mut changed = PropMap::new();
+        changed.insert(
+            "PlaybackStatus".to_owned(),
+            arg::Variant(Box::new("Paused".to_owned())),
+        );
+        assert!(worth_rereading(PLAYER_INTERFACE, &changed, &[]));
+    }
+
+    #[test]
+    fn unreported_properties_are_not_reread() {
+        let mut changed = PropMap::new();
+        changed.insert("Volume".to_owned(), arg::Variant(Box::new(0.5)));
+        assert!(!worth_rereading(PLAYER_INTERFACE, &changed, &[]));
+        let invalidated = ["Metadata".to_owned()];
+        assert!(!worth_rereading(
+            "org.mpris.MediaPlayer2.TrackList",
+            &PropMap::new(),
+            &invalidated
+        ));
+    }
+
+    #[tokio::test]
+    async fn read_is_retried_until_it_succeeds() {
+        let attempts = std::cell::Cell::new(0);
+        let result = retrying(&[Duration::ZERO; 2], || {
+            attempts.set(attempts.get() + 1);
+            future::ready(match attempts.get() {
+                3 => Ok(attempts.get()),
+                _ => Err("busy"),
+            })
+        })
+        .await;
+        assert_eq!(result, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn retries_are_bounded() {
+        let attempts = std::cell::Cell::new(0);
+        let result: Result<(), _> = retrying(&[Duration::ZERO; 2], || {
+            attempts.set(attempts.get() + 1);
+            future::ready(Err("busy"))
+        })
+        .await;
+        assert_eq!(result, Err("busy"));
+        assert_eq!(attempts.get(), 3);
+    }
+}
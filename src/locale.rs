@@ -0,0 +1,167 @@
+use crate::config;
+use crate::duration::TrackDuration;
+use std::env;
+use std::sync::OnceLock;
+
+static CURRENT: OnceLock<Locale> = OnceLock::new();
+
+/// How durations and counts are written for the user's locale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Locale {
+    time_separator: char,
+    group_separator: char,
+    hours: config::Hours,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale {
+            time_separator: ':',
+            group_separator: ',',
+            hours: config::Hours::Auto,
+        }
+    }
+}
+
+impl Locale {
+    /// Separators for a locale tag like `de_DE.UTF-8` or `fr-CA`; anything
+    /// unrecognised is written the English way.
+    pub fn from_tag(tag: &str, hours: config::Hours) -> Self {
+        let tag = tag.split(['.', '@']).next().unwrap_or_default();
+        let (language, region) = tag.split_once(['_', '-']).unwrap_or((tag, ""));
+        let time_separator = match language {
+            "fi" | "da" => '.',
+            _ => ':',
+        };
+        let group_separator = match (language, region) {
+            ("de", "CH") | ("it", "CH") => '\u{2019}',
+            ("fr", _) => '\u{202f}',
+            ("de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el", _) => '.',
+            ("ru" | "uk" | "pl" | "cs" | "sk" | "sv" | "nb" | "no" | "fi" | "hu", _) => '\u{a0}',
+            _ => ',',
+        };
+        Locale {
+            time_separator,
+            group_separator,
+            hours,
+        }
+    }
+
+    /// The configured locale, or the environment's (`LC_ALL`, `LC_NUMERIC`,
+    /// then `LANG`).
+    fn new(config: &config::Locale) -> Self {
+        let tag = config.tag.clone().or_else(|| {
+            ["LC_ALL", "LC_NUMERIC", "LANG"]
+                .iter()
+                .filter_map(|name| env::var(name).ok())
+                .find(|value| !value.is_empty())
+        });
+        match tag {
+            Some(tag) => Locale::from_tag(&tag, config.hours),
+            None => Locale {
+                hours: config.hours,
+                ..Locale::default()
+            },
+        }
+    }
+
+    /// Like `m:ss`, or `h:mm:ss` from an hour up unless hours are turned off.
+    pub fn duration(&self, duration: TrackDuration) -> String {
+        let secs = duration.as_duration().as_secs();
+        let sep = self.time_separator;
+        match (secs / 3600, self.hours) {
+            (0, _) | (_, config::Hours::Never) => {
+                format!("{}{}{:02}", secs / 60, sep, secs % 60)
+            }
+            (hours, config::Hours::Auto) => {
+                format!(
+                    "{}{}{:02}{}{:02}",
+                    hours,
+                    sep,
+                    secs / 60 % 60,
+                    sep,
+                    secs % 60
+                )
+            }
+        }
+    }
+
+    /// A whole number with its digits grouped in threes.
+    pub fn number(&self, n: u64) -> String {
+        let digits = n.to_string();
+        let mut grouped = String::new();
+        for (i, digit) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                grouped.push(self.group_separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+}
+
+/// Sets the locale for the rest of the run, from the config or else the
+/// environment; only the first call counts.
+pub fn init(config: &config::Locale) {
+    let _ = CURRENT.set(Locale::new(config));
+}
+
+/// The locale set by `init`, or English if it hasn't been called.
+pub fn current() -> Locale {
+    CURRENT.get().copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn secs(secs: u64) -> TrackDuration {
+        TrackDuration::from(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn english_durations_use_colons() {
+        let locale = Locale::from_tag("en_US.UTF-8", config::Hours::Auto);
+        assert_eq!(locale.duration(secs(65)), "1:05");
+        assert_eq!(locale.duration(secs(3930)), "1:05:30");
+    }
+
+    #[test]
+    fn finnish_durations_use_dots() {
+        let locale = Locale::from_tag("fi_FI", config::Hours::Auto);
+        assert_eq!(locale.duration(secs(3930)), "1.05.30");
+    }
+
+    #[test]
+    fn hours_can_be_turned_off() {
+        let locale = Locale::from_tag("en", config::Hours::Never);
+        assert_eq!(locale.duration(secs(3930)), "65:30");
+    }
+
+    #[test]
+    fn numbers_are_grouped_per_locale() {
+        assert_eq!(
+            Locale::from_tag("en_GB", config::Hours::Auto).number(1234567),
+            "1,234,567"
+        );
+        assert_eq!(
+            Locale::from_tag("de-DE", config::Hours::Auto).number(1234),
+            "1.234"
+        );
+        assert_eq!(
+            Locale::from_tag("fr_FR", config::Hours::Auto).number(1234),
+            "1\u{202f}234"
+        );
+        assert_eq!(Locale::default().number(999), "999");
+    }
+
+    #[test]
+    fn configured_tag_wins_over_environment() {
+        let config = config::Locale {
+            tag: Some("de_DE".to_owned()),
+            hours: config::Hours::Auto,
+        };
+        assert_eq!(Locale::new(&config).group_separator, '.');
+    }
+}
@@ -0,0 +1,62 @@
+use log::LevelFilter;
+use std::env;
+use std::path::Path;
+use std::str::FromStr;
+use systemd_journal_logger::JournalLog;
+
+/// Structured `EVENT` field values attached to journal entries.
+pub mod event {
+    pub const TRACK: &str = "track";
+    pub const NOT_PLAYING: &str = "not_playing";
+    pub const REPLAY: &str = "replay";
+    pub const METRICS: &str = "metrics";
+    pub const PREVIEW: &str = "preview";
+    pub const WAKEUP: &str = "wakeup";
+    pub const PROBLEM: &str = "problem";
+}
+
+/// Where journald listens for log entries sent straight to it.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Logs straight to journald (keeping key-value pairs as journal fields) when
+/// stderr is connected to the journal, or when running as a `service` and
+/// there's a journal to reach; otherwise falls back to env_logger.
+pub fn init(service: bool) {
+    let journal = systemd_journal_logger::connected_to_journal()
+        || (service && Path::new(JOURNAL_SOCKET).exists());
+    if journal {
+        match JournalLog::new().map(JournalLog::install) {
+            Ok(Ok(())) => {
+                log::set_max_level(journal_level(env::var("RUST_LOG").ok().as_deref()));
+                return;
+            }
+            Ok(Err(e)) => eprintln!("failed to install journal logger: {}", e),
+            Err(e) => eprintln!("failed to connect to journal: {}", e),
+        }
+    }
+    env_logger::init();
+}
+
+// The journal does its own filtering, so only a plain level is honoured here
+// rather than env_logger's per-module directives.
+fn journal_level(rust_log: Option<&str>) -> LevelFilter {
+    rust_log
+        .and_then(|level| LevelFilter::from_str(level).ok())
+        .unwrap_or(LevelFilter::Info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal_level_defaults_to_info() {
+        assert_eq!(journal_level(None), LevelFilter::Info);
+        assert_eq!(journal_level(Some("mymod=trace")), LevelFilter::Info);
+    }
+
+    #[test]
+    fn journal_level_uses_plain_rust_log_level() {
+        assert_eq!(journal_level(Some("debug")), LevelFilter::Debug);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::discovery;
+use crate::events::PlayerState;
+use crate::PlaybackStatus;
+
+/// Combines readings of the player from several buses into the one to
+/// report: a playing one over a paused one over the rest, and then, as on
+/// any one bus, the player higher in `player_priority`. Among equals the
+/// one already reported stays, so that two playing at once don't take turns.
+#[derive(Debug)]
+pub struct Merger {
+    readings: Vec<Option<PlayerState>>,
+    current: Option<usize>,
+    /// Whether a player that's exited gives way even to a stopped one.
+    closed_yields: bool,
+    priority: Vec<String>,
+}
+
+impl Merger {
+    pub fn new(sources: usize, closed_yields: bool, priority: Vec<String>) -> Self {
+        Merger {
+            readings: vec![None; sources],
+            current: None,
+            closed_yields,
+            priority,
+        }
+    }
+
+    fn rank(&self, state: &PlayerState) -> impl Ord {
+        let status = match state.status {
+            PlaybackStatus::Playing => 3,
+            PlaybackStatus::Paused => 2,
+            PlaybackStatus::Closed if self.closed_yields => 0,
+            PlaybackStatus::Stopped | PlaybackStatus::Closed => 1,
+        };
+        let player = state.track.as_ref().map_or("", |mi| mi.bus_name.as_str());
+        (status, discovery::listed(&self.priority, player))
+    }
+
+    /// The last reading from `source`.
+    pub fn reading(&self, source: usize) -> Option<&PlayerState> {
+        self.readings.get(source)?.as_ref()
+    }
+
+    /// Takes a reading from `source`, giving what to report if it changes
+    /// what's reported: nothing when another source still wins.
+    pub fn update(&mut self, source: usize, state: PlayerState) -> Option<PlayerState> {
+        self.readings[source] = Some(state);
+        let ranked = |i: usize| self.readings[i].as_ref().map(|state| self.rank(state));
+        let best = (0..self.readings.len()).filter_map(ranked).max();
+        let winner = self
+            .current
+            .into_iter()
+            .chain([source])
+            .chain(0..self.readings.len())
+            .find(|&i| ranked(i) == best)
+            .unwrap_or(source);
+        let switched = self.current.replace(winner) != Some(winner);
+        match switched || winner == source {
+            true => self.readings[winner].clone(),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MediaInfo;
+
+    fn state(title: &str, status: PlaybackStatus) -> PlayerState {
+        PlayerState {
+            track: Some(MediaInfo {
+                title: title.to_owned(),
+                ..Default::default()
+            }),
+            status,
+            progress: None,
+        }
+    }
+
+    fn on(bus_name: &str, state: PlayerState) -> PlayerState {
+        PlayerState {
+            track: state.track.map(|mi| MediaInfo {
+                bus_name: bus_name.to_owned(),
+                ..mi
+            }),
+            ..state
+        }
+    }
+
+    fn title(state: Option<PlayerState>) -> Option<String> {
+        Some(state?.track?.title)
+    }
+
+    #[test]
+    fn playing_bus_wins_over_paused_one() {
+        let mut merger = Merger::new(2, false, Vec::new());
+        merger.update(0, state("River", PlaybackStatus::Paused));
+        let reported = merger.update(1, state("Blue", PlaybackStatus::Playing));
+        assert_eq!(title(reported).as_deref(), Some("Blue"));
+        assert_eq!(
+            merger.update(0, state("River", PlaybackStatus::Paused)),
+            None
+        );
+    }
+
+    #[test]
+    fn reported_bus_keeps_its_place_among_equals() {
+        let mut merger = Merger::new(2, false, Vec::new());
+        merger.update(0, state("River", PlaybackStatus::Playing));
+        assert_eq!(
+            merger.update(1, state("Blue", PlaybackStatus::Playing)),
+            None
+        );
+        let reported = merger.update(0, state("Case of You", PlaybackStatus::Playing));
+        assert_eq!(title(reported).as_deref(), Some("Case of You"));
+    }
+
+    #[test]
+    fn priority_decides_between_buses_playing_at_once() {
+        let priority = vec!["org.mpris.MediaPlayer2.mpd".to_owned()];
+        let mut merger = Merger::new(2, false, priority);
+        merger.update(
+            0,
+            on(
+                "org.mpris.MediaPlayer2.vlc",
+                state("River", PlaybackStatus::Playing),
+            ),
+        );
+        let reported = merger.update(
+            1,
+            on(
+                "org.mpris.MediaPlayer2.mpd",
+                state("Blue", PlaybackStatus::Playing),
+            ),
+        );
+        assert_eq!(title(reported).as_deref(), Some("Blue"));
+        // Playing still beats a higher priority that's only paused.
+        let reported = merger.update(
+            1,
+            on(
+                "org.mpris.MediaPlayer2.mpd",
+                state("Blue", PlaybackStatus::Paused),
+            ),
+        );
+        assert_eq!(title(reported).as_deref(), Some("River"));
+    }
+
+    #[test]
+    fn stopping_hands_over_to_the_other_bus() {
+        let mut merger = Merger::new(2, false, Vec::new());
+        merger.update(0, state("River", PlaybackStatus::Playing));
+        merger.update(1, state("Blue", PlaybackStatus::Paused));
+        let reported = merger.update(0, state("River", PlaybackStatus::Stopped));
+        assert_eq!(title(reported).as_deref(), Some("Blue"));
+        assert_eq!(merger.reading(0).unwrap().status, PlaybackStatus::Stopped);
+    }
+
+    #[test]
+    fn exited_player_yields_to_a_stopped_one_only_if_asked() {
+        let closed = PlayerState::not_playing(PlaybackStatus::Closed);
+        for (closed_yields, reported) in [
+            (false, PlaybackStatus::Closed),
+            (true, PlaybackStatus::Stopped),
+        ] {
+            let mut merger = Merger::new(2, closed_yields, Vec::new());
+            merger.update(0, state("River", PlaybackStatus::Playing));
+            merger.update(1, state("Blue", PlaybackStatus::Stopped));
+            let merged = merger.update(0, closed.clone()).unwrap();
+            assert_eq!(merged.status, reported);
+        }
+    }
+}
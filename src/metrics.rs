@@ -0,0 +1,333 @@
+use crate::paths;
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use std::fmt::{Display, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Prefixed to every name in the OpenMetrics text.
+const NAMESPACE: &str = "discord_mediaplayer_rpc";
+/// How long `metrics dump` waits for the daemon to write its metrics.
+const DUMP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Upper bounds (in milliseconds) of the latency histogram buckets.
+const BUCKETS_MS: [u64; 11] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    MetadataRead,
+    MissingTrackData,
+    PlaybackStatusRead,
+    PositionRead,
+    ChannelSend,
+    DiscordSetActivity,
+    DiscordClearActivity,
+    MusicBrainzRequest,
+    OdesliRequest,
+    CoverArtRequest,
+}
+
+impl Failure {
+    const ALL: [Failure; 10] = [
+        Failure::MetadataRead,
+        Failure::MissingTrackData,
+        Failure::PlaybackStatusRead,
+        Failure::PositionRead,
+        Failure::ChannelSend,
+        Failure::DiscordSetActivity,
+        Failure::DiscordClearActivity,
+        Failure::MusicBrainzRequest,
+        Failure::OdesliRequest,
+        Failure::CoverArtRequest,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Failure::MetadataRead => "metadata_read",
+            Failure::MissingTrackData => "missing_track_data",
+            Failure::PlaybackStatusRead => "playback_status_read",
+            Failure::PositionRead => "position_read",
+            Failure::ChannelSend => "channel_send",
+            Failure::DiscordSetActivity => "discord_set_activity",
+            Failure::DiscordClearActivity => "discord_clear_activity",
+            Failure::MusicBrainzRequest => "musicbrainz_request",
+            Failure::OdesliRequest => "odesli_request",
+            Failure::CoverArtRequest => "cover_art_request",
+        }
+    }
+}
+
+pub struct Histogram {
+    // one slot per bucket plus the overflow slot
+    buckets: [AtomicU64; BUCKETS_MS.len() + 1],
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS_MS.len() + 1],
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let ms = elapsed.as_millis();
+        let slot = BUCKETS_MS
+            .iter()
+            .position(|&bound| ms <= u128::from(bound))
+            .unwrap_or(BUCKETS_MS.len());
+        self.buckets[slot].fetch_add(1, Ordering::Relaxed);
+        let us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            n => Some(Duration::from_micros(
+                self.sum_us.load(Ordering::Relaxed) / n,
+            )),
+        }
+    }
+}
+
+impl Histogram {
+    /// Writes the histogram as an OpenMetrics family named `name`, in
+    /// seconds with cumulative buckets.
+    fn write_open_metrics(&self, out: &mut String, name: &str, help: &str) -> std::fmt::Result {
+        writeln!(out, "# TYPE {}_{}_seconds histogram", NAMESPACE, name)?;
+        writeln!(out, "# HELP {}_{}_seconds {}", NAMESPACE, name, help)?;
+        let mut cumulative = 0;
+        for (bound, bucket) in BUCKETS_MS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            writeln!(
+                out,
+                "{}_{}_seconds_bucket{{le=\"{}\"}} {}",
+                NAMESPACE,
+                name,
+                Duration::from_millis(*bound).as_secs_f64(),
+                cumulative
+            )?;
+        }
+        let count = self.count();
+        writeln!(
+            out,
+            "{}_{}_seconds_bucket{{le=\"+Inf\"}} {}",
+            NAMESPACE, name, count
+        )?;
+        let sum = Duration::from_micros(self.sum_us.load(Ordering::Relaxed)).as_secs_f64();
+        writeln!(out, "{}_{}_seconds_sum {}", NAMESPACE, name, sum)?;
+        writeln!(out, "{}_{}_seconds_count {}", NAMESPACE, name, count)
+    }
+}
+
+impl Display for Histogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "count={}", self.count())?;
+        if let Some(mean) = self.mean() {
+            write!(f, " mean={:?}", mean)?;
+        }
+        for (bound, bucket) in BUCKETS_MS.iter().zip(&self.buckets) {
+            match bucket.load(Ordering::Relaxed) {
+                0 => {}
+                n => write!(f, " <={}ms:{}", bound, n)?,
+            }
+        }
+        match self.buckets[BUCKETS_MS.len()].load(Ordering::Relaxed) {
+            0 => Ok(()),
+            n => write!(f, " >{}ms:{}", BUCKETS_MS[BUCKETS_MS.len() - 1], n),
+        }
+    }
+}
+
+pub struct Metrics {
+    pub metadata_fetch: Histogram,
+    pub set_activity: Histogram,
+    failures: [AtomicU64; Failure::ALL.len()],
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            metadata_fetch: Histogram::new(),
+            set_activity: Histogram::new(),
+            failures: [const { AtomicU64::new(0) }; Failure::ALL.len()],
+        }
+    }
+
+    pub fn fail(&self, failure: Failure) {
+        self.failures[failure as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failures(&self, failure: Failure) -> u64 {
+        self.failures[failure as usize].load(Ordering::Relaxed)
+    }
+
+    /// The metrics in the OpenMetrics text format, for `metrics dump`.
+    pub fn open_metrics(&self) -> String {
+        let mut out = String::new();
+        let _ = self.write_open_metrics(&mut out);
+        out
+    }
+
+    fn write_open_metrics(&self, out: &mut String) -> std::fmt::Result {
+        self.metadata_fetch.write_open_metrics(
+            out,
+            "metadata_fetch",
+            "Time taken to read a track's metadata from the player.",
+        )?;
+        self.set_activity.write_open_metrics(
+            out,
+            "set_activity",
+            "Time taken for Discord to accept a presence.",
+        )?;
+        writeln!(out, "# TYPE {}_failures counter", NAMESPACE)?;
+        writeln!(out, "# HELP {}_failures Failures, by kind.", NAMESPACE)?;
+        for failure in Failure::ALL {
+            writeln!(
+                out,
+                "{}_failures_total{{kind=\"{}\"}} {}",
+                NAMESPACE,
+                failure.name(),
+                self.failures(failure)
+            )?;
+        }
+        writeln!(out, "# EOF")
+    }
+}
+
+/// Where the daemon writes its metrics when asked.
+pub fn dump_path() -> PathBuf {
+    paths::runtime_dir().join("metrics.txt")
+}
+
+/// Writes the current metrics for `metrics dump` to pick up.
+pub fn write_dump(path: &Path) -> anyhow::Result<()> {
+    paths::write_atomic(path, METRICS.open_metrics())
+}
+
+/// Asks the daemon running as `pid` for its metrics, and waits for them.
+pub fn request_dump(pid: u32) -> anyhow::Result<String> {
+    let path = dump_path();
+    let _ = std::fs::remove_file(&path);
+    // SIGUSR1 has the daemon log its metrics and write them out.
+    let pid = Pid::from_raw(i32::try_from(pid)?);
+    match signal::kill(pid, Signal::SIGUSR1) {
+        Ok(()) => {}
+        Err(Errno::ESRCH) => anyhow::bail!("the daemon isn't running"),
+        Err(e) => anyhow::bail!("couldn't signal the daemon: {}", e),
+    }
+    let deadline = Instant::now() + DUMP_TIMEOUT;
+    loop {
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            return Ok(text);
+        }
+        anyhow::ensure!(
+            Instant::now() < deadline,
+            "the daemon didn't write its metrics"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+impl Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "metadata_fetch: {}", self.metadata_fetch)?;
+        writeln!(f, "set_activity: {}", self.set_activity)?;
+        write!(f, "failures:")?;
+        for failure in Failure::ALL {
+            write!(f, " {}={}", failure.name(), self.failures(failure))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_from_no_such_process_says_so() {
+        // Above any pid_max, so never a process.
+        let err = request_dump(i32::MAX as u32).unwrap_err();
+        assert_eq!(err.to_string(), "the daemon isn't running");
+    }
+
+    #[test]
+    fn histogram_places_observations_in_smallest_fitting_bucket() {
+        let histogram = Histogram::new();
+        histogram.observe(Duration::from_micros(300));
+        histogram.observe(Duration::from_millis(5));
+        histogram.observe(Duration::from_millis(6));
+        histogram.observe(Duration::from_secs(60));
+
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(
+            histogram.to_string(),
+            "count=4 mean=15.002825s <=1ms:1 <=5ms:1 <=10ms:1 >5000ms:1"
+        );
+    }
+
+    #[test]
+    fn empty_histogram_has_no_mean() {
+        assert_eq!(Histogram::new().to_string(), "count=0");
+    }
+
+    #[test]
+    fn open_metrics_buckets_are_cumulative() {
+        let metrics = Metrics::new();
+        metrics.metadata_fetch.observe(Duration::from_micros(300));
+        metrics.metadata_fetch.observe(Duration::from_millis(6));
+        metrics.fail(Failure::OdesliRequest);
+        let text = metrics.open_metrics();
+        let line = |prefix: &str| {
+            text.lines()
+                .find(|line| line.starts_with(prefix))
+                .unwrap()
+                .to_owned()
+        };
+        let fetch = "discord_mediaplayer_rpc_metadata_fetch_seconds";
+        assert_eq!(
+            line(&format!("{}_bucket{{le=\"0.001\"}}", fetch)),
+            format!("{}_bucket{{le=\"0.001\"}} 1", fetch)
+        );
+        assert_eq!(
+            line(&format!("{}_bucket{{le=\"0.01\"}}", fetch)),
+            format!("{}_bucket{{le=\"0.01\"}} 2", fetch)
+        );
+        assert_eq!(
+            line(&format!("{}_bucket{{le=\"+Inf\"}}", fetch)),
+            format!("{}_bucket{{le=\"+Inf\"}} 2", fetch)
+        );
+        assert_eq!(
+            line(&format!("{}_sum", fetch)),
+            format!("{}_sum 0.0063", fetch)
+        );
+        assert_eq!(
+            line("discord_mediaplayer_rpc_failures_total{kind=\"odesli_request\"}"),
+            "discord_mediaplayer_rpc_failures_total{kind=\"odesli_request\"} 1"
+        );
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn failures_are_counted_per_kind() {
+        let metrics = Metrics::new();
+        metrics.fail(Failure::MetadataRead);
+        metrics.fail(Failure::MetadataRead);
+        metrics.fail(Failure::DiscordSetActivity);
+
+        assert_eq!(metrics.failures(Failure::MetadataRead), 2);
+        assert_eq!(metrics.failures(Failure::DiscordSetActivity), 1);
+        assert_eq!(metrics.failures(Failure::ChannelSend), 0);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::cached_http::CachedHttp;
+use crate::metrics::Failure;
+use log::warn;
+use reqwest::Url;
+use serde::Deserialize;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+const BASE_URL: &str = "https://musicbrainz.org/ws/2/";
+/// MusicBrainz asks for no more than one request a second per client.
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Relationship types whose URL plays the release on some service.
+const STREAMING_RELATIONS: [&str; 2] = ["streaming", "free streaming"];
+
+/// A release found by searching, with MusicBrainz's 0-100 match score.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Release {
+    pub id: String,
+    pub title: String,
+    pub score: u8,
+    /// How many tracks it has across all its media.
+    #[serde(rename = "track-count", default)]
+    pub track_count: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseSearch {
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize)]
+struct ReleaseLookup {
+    #[serde(default)]
+    relations: Vec<Relation>,
+}
+
+#[derive(Deserialize)]
+struct Relation {
+    #[serde(rename = "type")]
+    kind: String,
+    url: Option<RelationUrl>,
+}
+
+#[derive(Deserialize)]
+struct RelationUrl {
+    resource: String,
+}
+
+/// The one way anything here talks to MusicBrainz, so that together they
+/// stay within its rate limit.
+pub struct Client {
+    http: CachedHttp,
+}
+
+impl Client {
+    /// The client every feature shares.
+    pub fn shared() -> Option<Arc<Client>> {
+        static SHARED: OnceLock<Option<Arc<Client>>> = OnceLock::new();
+        SHARED
+            .get_or_init(|| {
+                CachedHttp::new("musicbrainz", MIN_INTERVAL, Failure::MusicBrainzRequest)
+                    .map_err(|e| warn!("can't create MusicBrainz client: {}", e))
+                    .ok()
+                    .map(|http| Arc::new(Client { http }))
+            })
+            .clone()
+    }
+
+    /// Releases matching `album` by `artist`, best match first.
+    pub async fn search_release(&self, artist: &str, album: &str) -> anyhow::Result<Vec<Release>> {
+        let query = format!(
+            "release:\"{}\" AND artist:\"{}\"",
+            escape(album),
+            escape(artist)
+        );
+        let url = Url::parse_with_params(
+            &format!("{}release/", BASE_URL),
+            &[("query", query.as_str()), ("fmt", "json"), ("limit", "5")],
+        )?;
+        let body = self.http.get(&url).await?;
+        let search: ReleaseSearch = serde_json::from_str(&body)?;
+        Ok(search.releases)
+    }
+
+    /// Pages where the release can be streamed, on whichever services
+    /// MusicBrainz knows of.
+    pub async fn streaming_links(&self, release_id: &str) -> anyhow::Result<Vec<String>> {
+        let url = Url::parse_with_params(
+            &format!("{}release/{}", BASE_URL, release_id),
+            &[("inc", "url-rels"), ("fmt", "json")],
+        )?;
+        let body = self.http.get(&url).await?;
+        Ok(streaming_relations(serde_json::from_str(&body)?))
+    }
+}
+
+fn streaming_relations(lookup: ReleaseLookup) -> Vec<String> {
+    lookup
+        .relations
+        .into_iter()
+        .filter(|relation| STREAMING_RELATIONS.contains(&relation.kind.as_str()))
+        .filter_map(|relation| relation.url)
+        .map(|url| url.resource)
+        .collect()
+}
+
+// Lucene query syntax, within a quoted phrase.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_are_escaped_in_queries() {
+        assert_eq!(escape(r#"12" \ Single"#), r#"12\" \\ Single"#);
+    }
+
+    #[test]
+    fn search_response_parses() {
+        let body = r#"{"created":"x","count":1,"offset":0,"releases":[
+            {"id":"b84ee12a-09ef-421b-82de-0441a926375b","score":100,"title":"Blue",
+             "status":"Official","track-count":10}]}"#;
+        let search: ReleaseSearch = serde_json::from_str(body).unwrap();
+        assert_eq!(search.releases[0].score, 100);
+        assert_eq!(search.releases[0].title, "Blue");
+        assert_eq!(search.releases[0].track_count, Some(10));
+    }
+
+    #[test]
+    fn only_streaming_relations_are_links() {
+        let body = r#"{"id":"x","relations":[
+            {"type":"free streaming","url":{"resource":"https://open.spotify.com/album/1"}},
+            {"type":"purchase for download","url":{"resource":"https://shop.example/1"}},
+            {"type":"streaming","url":{"resource":"https://tidal.com/album/1"}}]}"#;
+        assert_eq!(
+            streaming_relations(serde_json::from_str(body).unwrap()),
+            [
+                "https://open.spotify.com/album/1",
+                "https://tidal.com/album/1"
+            ]
+        );
+    }
+}
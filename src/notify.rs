@@ -0,0 +1,300 @@
+use crate::config::{Config, NotifyChannel, Severity};
+use crate::events::{Bus, Event};
+use crate::{logging, seat};
+use dbus::arg::{PropMap, Variant};
+use dbus::nonblock::{Proxy, SyncConnection};
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Instant;
+
+/// Failures in a row before a sink's trouble is worth telling anyone about.
+const FAILURES: u32 = 3;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something going wrong, or righting itself, that the user may want to
+/// hear about as well as find in the log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Problem {
+    pub severity: Severity,
+    pub text: String,
+}
+
+impl Problem {
+    pub fn new(severity: Severity, text: impl Into<String>) -> Self {
+        Problem {
+            severity,
+            text: text.into(),
+        }
+    }
+}
+
+/// Keeps count of a sink's failures in a row, so that it's the ones that
+/// keep failing that are reported, once each time, and not every blip.
+#[derive(Debug, Default)]
+pub struct Streak {
+    failures: u32,
+}
+
+impl Streak {
+    /// Counts a failure; true once there have been enough in a row to tell.
+    pub fn failed(&mut self) -> bool {
+        self.failures += 1;
+        self.failures == FAILURES
+    }
+
+    /// Ends the streak; true if it had been told of, so its end should be.
+    pub fn succeeded(&mut self) -> bool {
+        std::mem::take(&mut self.failures) >= FAILURES
+    }
+
+    /// Sends `problem` on `bus` if this failure makes the streak worth
+    /// telling of.
+    pub fn fail(&mut self, bus: &Bus, problem: impl FnOnce(u32) -> Problem) {
+        if self.failed() {
+            bus.send(Event::Problem(problem(FAILURES)));
+        }
+    }
+
+    /// Says on `bus` that what was failing works again, if its failing was
+    /// told of.
+    pub fn succeed(&mut self, bus: &Bus, what: &str) {
+        if self.succeeded() {
+            bus.send(Event::Problem(Problem::new(
+                Severity::Info,
+                format!("{} is working again", what),
+            )));
+        }
+    }
+}
+
+/// Watches for Discord staying out of reach, while there's something to
+/// show, for long enough to be worth a notice.
+#[derive(Debug)]
+pub struct Outage {
+    after: Duration,
+    since: Option<Instant>,
+    told: bool,
+}
+
+impl Outage {
+    pub fn new(after: Duration) -> Self {
+        Outage {
+            after,
+            since: None,
+            told: false,
+        }
+    }
+
+    /// There's nothing to show, so it doesn't matter whether Discord can
+    /// be reached; only the time there is counts.
+    pub fn idle(&mut self) {
+        if !self.told {
+            self.since = None;
+        }
+    }
+
+    /// Discord couldn't be reached at `now`; the problem, the first time
+    /// it's been so for long enough.
+    pub fn unreachable(&mut self, now: Instant) -> Option<Problem> {
+        let since = *self.since.get_or_insert(now);
+        if self.told || now.duration_since(since) < self.after {
+            return None;
+        }
+        self.told = true;
+        Some(Problem::new(
+            Severity::Warning,
+            format!(
+                "Discord has been out of reach for {} seconds, so nothing is being shown",
+                self.after.as_secs()
+            ),
+        ))
+    }
+
+    /// Discord answered; says so if its being out of reach was told of.
+    pub fn reachable(&mut self) -> Option<Problem> {
+        self.since = None;
+        std::mem::take(&mut self.told)
+            .then(|| Problem::new(Severity::Info, "Discord can be reached again"))
+    }
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    content: &'a str,
+}
+
+/// Where notices go: the channels configured, some of which need setting
+/// up first.
+struct Channels {
+    log: bool,
+    desktop: Option<Arc<SyncConnection>>,
+    webhook: Option<(reqwest::Client, String)>,
+}
+
+impl Channels {
+    async fn open(config: &Config) -> Self {
+        let channels = &config.notify.channels;
+        let desktop = match channels.contains(&NotifyChannel::Desktop) {
+            true => seat::connect(config.session_bus)
+                .await
+                .map_err(|e| warn!("can't send desktop notifications: {}", e))
+                .ok()
+                .map(|(resource, conn)| {
+                    tokio::spawn(resource);
+                    conn
+                }),
+            false => None,
+        };
+        let webhook = channels
+            .contains(&NotifyChannel::Webhook)
+            .then(|| config.notify.webhook_url.clone())
+            .flatten()
+            .and_then(|url| {
+                reqwest::Client::builder()
+                    .timeout(TIMEOUT)
+                    .build()
+                    .map_err(|e| warn!("can't post notices to the webhook: {}", e))
+                    .ok()
+                    .map(|http| (http, url))
+            });
+        Channels {
+            log: channels.contains(&NotifyChannel::Log),
+            desktop,
+            webhook,
+        }
+    }
+
+    async fn send(&self, problem: &Problem) {
+        if self.log {
+            log_marker(problem);
+        }
+        if let Some(conn) = &self.desktop {
+            if let Err(e) = notify_desktop(conn, problem).await {
+                debug!("couldn't send a desktop notification: {}", e);
+            }
+        }
+        if let Some((http, url)) = &self.webhook {
+            let message = Message {
+                content: &format!("{} {}", symbol(problem.severity), problem.text),
+            };
+            let posted = http.post(url).json(&message).send().await;
+            if let Err(e) = posted.and_then(|response| response.error_for_status()) {
+                debug!("couldn't post a notice to the webhook: {}", e);
+            }
+        }
+    }
+}
+
+fn symbol(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Info => "ℹ",
+        Severity::Warning => "⚠",
+        Severity::Error => "❌",
+    }
+}
+
+/// Logs the problem at its own level, marked so it can be picked out of
+/// the journal with `EVENT=problem`.
+fn log_marker(problem: &Problem) {
+    let event = logging::event::PROBLEM;
+    match problem.severity {
+        Severity::Info => info!(event = event; "{}", problem.text),
+        Severity::Warning => warn!(event = event; "{}", problem.text),
+        Severity::Error => error!(event = event; "{}", problem.text),
+    }
+}
+
+/// Shows the problem through the desktop's notification server.
+async fn notify_desktop(conn: &Arc<SyncConnection>, problem: &Problem) -> Result<(), dbus::Error> {
+    let proxy = Proxy::new(
+        "org.freedesktop.Notifications",
+        "/org/freedesktop/Notifications",
+        TIMEOUT,
+        conn.clone(),
+    );
+    let urgency: u8 = match problem.severity {
+        Severity::Info => 0,
+        Severity::Warning => 1,
+        Severity::Error => 2,
+    };
+    let hints = PropMap::from([("urgency".to_owned(), Variant(Box::new(urgency) as _))]);
+    let _: (u32,) = proxy
+        .method_call(
+            "org.freedesktop.Notifications",
+            "Notify",
+            (
+                "discord-mediaplayer-rpc",
+                0u32,
+                "",
+                "Discord media player presence",
+                problem.text.as_str(),
+                Vec::<String>::new(),
+                hints,
+                -1i32,
+            ),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Follows the bus until it closes, passing each problem serious enough on
+/// to every channel configured.
+pub async fn run(config: Arc<Config>, mut events: broadcast::Receiver<Event>) {
+    let channels = Channels::open(&config).await;
+    loop {
+        match events.recv().await {
+            Ok(Event::Problem(problem)) if problem.severity >= config.notify.min_severity => {
+                channels.send(&problem).await
+            }
+            Ok(_) => {}
+            Err(RecvError::Lagged(missed)) => debug!("notify sink missed {} events", missed),
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streak_is_told_once_and_its_end_only_if_told() {
+        let mut streak = Streak::default();
+        assert!(!streak.failed());
+        assert!(!streak.succeeded());
+        let told: Vec<bool> = (0..5).map(|_| streak.failed()).collect();
+        assert_eq!(told, [false, false, true, false, false]);
+        assert!(streak.succeeded());
+        assert!(!streak.succeeded());
+    }
+
+    #[test]
+    fn outage_counts_only_time_with_something_to_show() {
+        let after = Duration::from_secs(300);
+        let mut outage = Outage::new(after);
+        let start = Instant::now();
+        assert_eq!(outage.unreachable(start), None);
+        outage.idle();
+        let later = start + Duration::from_secs(200);
+        assert_eq!(outage.unreachable(later), None);
+        let notice = outage.unreachable(later + after).unwrap();
+        assert_eq!(notice.severity, Severity::Warning);
+        assert_eq!(outage.unreachable(later + after * 2), None);
+        assert_eq!(outage.reachable().unwrap().severity, Severity::Info);
+        assert_eq!(outage.reachable(), None);
+    }
+
+    #[test]
+    fn webhook_notice_is_plain_content() {
+        let message = Message {
+            content: "⚠ oops"
+        };
+        assert_eq!(
+            serde_json::to_string(&message).unwrap(),
+            r#"{"content":"⚠ oops"}"#
+        );
+    }
+}
@@ -0,0 +1,179 @@
+use crate::cached_http::CachedHttp;
+use crate::metrics::Failure;
+use log::warn;
+use reqwest::Url;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+const BASE_URL: &str = "https://api.song.link/v1-alpha.1/links";
+/// Odesli allows ten requests a minute without an API key.
+const MIN_INTERVAL: Duration = Duration::from_secs(6);
+/// Hosts whose pages Odesli can start from.
+const SERVICE_HOSTS: [&str; 9] = [
+    "open.spotify.com",
+    "music.apple.com",
+    "tidal.com",
+    "listen.tidal.com",
+    "www.deezer.com",
+    "music.youtube.com",
+    "www.youtube.com",
+    "music.amazon.com",
+    "soundcloud.com",
+];
+
+/// A streaming service a track can be linked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Service {
+    Spotify,
+    AppleMusic,
+    Tidal,
+    Deezer,
+    YoutubeMusic,
+    AmazonMusic,
+    Soundcloud,
+}
+
+impl Service {
+    fn from_odesli(platform: &str) -> Option<Self> {
+        Some(match platform {
+            "spotify" => Service::Spotify,
+            "appleMusic" => Service::AppleMusic,
+            "tidal" => Service::Tidal,
+            "deezer" => Service::Deezer,
+            "youtubeMusic" => Service::YoutubeMusic,
+            "amazonMusic" => Service::AmazonMusic,
+            "soundcloud" => Service::Soundcloud,
+            _ => return None,
+        })
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::Spotify => "Spotify",
+            Service::AppleMusic => "Apple Music",
+            Service::Tidal => "TIDAL",
+            Service::Deezer => "Deezer",
+            Service::YoutubeMusic => "YouTube Music",
+            Service::AmazonMusic => "Amazon Music",
+            Service::Soundcloud => "SoundCloud",
+        }
+    }
+}
+
+/// Where a track can be played: Odesli's own page, which lets whoever opens
+/// it pick their service, and direct links for each service found.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Links {
+    pub page: Option<String>,
+    pub services: BTreeMap<Service, String>,
+}
+
+impl Links {
+    pub fn is_empty(&self) -> bool {
+        self.page.is_none() && self.services.is_empty()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Response {
+    page_url: Option<String>,
+    #[serde(default)]
+    links_by_platform: HashMap<String, PlatformLink>,
+}
+
+#[derive(Deserialize)]
+struct PlatformLink {
+    url: String,
+}
+
+impl From<Response> for Links {
+    fn from(response: Response) -> Self {
+        Links {
+            page: response.page_url,
+            services: response
+                .links_by_platform
+                .into_iter()
+                .filter_map(|(platform, link)| {
+                    Service::from_odesli(&platform).map(|service| (service, link.url))
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Maps a track's page on one streaming service to its pages on the others,
+/// through Odesli (song.link).
+pub struct Client {
+    http: CachedHttp,
+}
+
+impl Client {
+    pub fn shared() -> Option<Arc<Client>> {
+        static SHARED: OnceLock<Option<Arc<Client>>> = OnceLock::new();
+        SHARED
+            .get_or_init(|| {
+                CachedHttp::new("odesli", MIN_INTERVAL, Failure::OdesliRequest)
+                    .map_err(|e| warn!("can't create Odesli client: {}", e))
+                    .ok()
+                    .map(|http| Arc::new(Client { http }))
+            })
+            .clone()
+    }
+
+    /// Links for the track or album at `page`, a service page such as
+    /// `on_service` gives.
+    pub async fn links(&self, page: &str) -> anyhow::Result<Links> {
+        let url = Url::parse_with_params(BASE_URL, &[("url", page)])?;
+        let body = self.http.get(&url).await?;
+        let response: Response = serde_json::from_str(&body)?;
+        Ok(response.into())
+    }
+}
+
+/// `url` as a page Odesli can look up, if it's on a streaming service.
+/// Spotify's player reports `spotify:track:…` URIs rather than pages.
+pub fn on_service(url: &str) -> Option<String> {
+    if let Some(id) = url.strip_prefix("spotify:track:") {
+        return Some(format!("https://open.spotify.com/track/{}", id));
+    }
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?;
+    (matches!(parsed.scheme(), "http" | "https") && SERVICE_HOSTS.contains(&host))
+        .then(|| url.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_keeps_known_services() {
+        let body = r#"{"entityUniqueId":"SPOTIFY_SONG::1","userCountry":"US",
+            "pageUrl":"https://song.link/s/1",
+            "linksByPlatform":{
+                "spotify":{"url":"https://open.spotify.com/track/1","entityUniqueId":"a"},
+                "appleMusic":{"url":"https://music.apple.com/us/album/x?i=1","entityUniqueId":"b"},
+                "napster":{"url":"https://napster.example/1","entityUniqueId":"c"}}}"#;
+        let links: Links = serde_json::from_str::<Response>(body).unwrap().into();
+        assert_eq!(links.page.as_deref(), Some("https://song.link/s/1"));
+        assert_eq!(
+            links.services.keys().copied().collect::<Vec<_>>(),
+            [Service::Spotify, Service::AppleMusic]
+        );
+    }
+
+    #[test]
+    fn only_service_pages_are_looked_up() {
+        assert_eq!(
+            on_service("spotify:track:4uLU6hMCjMI75M1A2tKUQC").as_deref(),
+            Some("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC")
+        );
+        assert!(on_service("https://tidal.com/browse/track/1").is_some());
+        assert_eq!(on_service("file:///music/River.flac"), None);
+        assert_eq!(on_service("https://radio.example/live"), None);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::paths;
+use crate::player;
+use crate::track::TrackKey;
+use crate::MediaInfo;
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// Corrections for particular tracks, kept in `overrides.toml` beside the
+/// config file, so badly tagged files can be fixed without retagging them.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overrides {
+    #[serde(default, rename = "track")]
+    rules: Vec<Rule>,
+}
+
+/// Replacement metadata for the tracks matching either `key` or `url`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    /// A track key, as recorded in the play history.
+    key: Option<TrackKey>,
+    /// A URL pattern in which `*` stands for any run of characters.
+    url: Option<String>,
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    genres: Option<Vec<String>>,
+    image: Option<String>,
+    /// The track's page on a streaming service, or anywhere else it can be
+    /// heard.
+    link: Option<String>,
+}
+
+impl Rule {
+    fn matches(&self, key: &TrackKey, url: Option<&str>) -> bool {
+        self.key.as_ref() == Some(key)
+            || self.url.as_deref().zip(url).is_some_and(|(pattern, url)| {
+                player::glob_match(pattern.as_bytes(), url.as_bytes())
+            })
+    }
+}
+
+impl Overrides {
+    /// Applies the first rule matching `mi`, if any.
+    pub fn apply(&self, mi: &mut MediaInfo) {
+        let key = mi.key();
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(&key, mi.url.as_deref()))
+        else {
+            return;
+        };
+        let replace = |field: &mut String, with: &Option<String>| {
+            if let Some(with) = with {
+                field.clone_from(with);
+            }
+        };
+        replace(&mut mi.title, &rule.title);
+        replace(&mut mi.artist, &rule.artist);
+        replace(&mut mi.album, &rule.album);
+        if let Some(genres) = &rule.genres {
+            mi.genres.clone_from(genres);
+        }
+        if rule.image.is_some() {
+            mi.image.clone_from(&rule.image);
+        }
+        if rule.link.is_some() {
+            mi.link.clone_from(&rule.link);
+        }
+    }
+}
+
+pub fn path() -> Option<PathBuf> {
+    paths::config_dir().map(|dir| dir.join("overrides.toml"))
+}
+
+/// Loads the overrides file; there being none is the same as it being empty.
+pub fn load() -> anyhow::Result<Overrides> {
+    match path() {
+        Some(path) if path.exists() => {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("reading {}", path.display()))?;
+            parse(&text).with_context(|| format!("parsing {}", path.display()))
+        }
+        _ => Ok(Overrides::default()),
+    }
+}
+
+pub fn parse(text: &str) -> anyhow::Result<Overrides> {
+    let overrides: Overrides = toml::from_str(text)?;
+    for (i, rule) in overrides.rules.iter().enumerate() {
+        if rule.key.is_some() == rule.url.is_some() {
+            bail!("override {} needs exactly one of `key` or `url`", i + 1);
+        }
+    }
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> MediaInfo {
+        MediaInfo {
+            title: "Track 01".to_owned(),
+            artist: "Unknown Artist".to_owned(),
+            url: Some("file:///music/bootlegs/1994/01.flac".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn key_rule_replaces_given_fields() {
+        let key = track().key();
+        let overrides = parse(&format!(
+            "[[track]]\nkey = \"{}\"\ntitle = \"Live Intro\"\nimage = \"intro\"\n",
+            key
+        ))
+        .unwrap();
+        let mut mi = track();
+        overrides.apply(&mut mi);
+        assert_eq!(mi.title, "Live Intro");
+        assert_eq!(mi.artist, "Unknown Artist");
+        assert_eq!(mi.image.as_deref(), Some("intro"));
+    }
+
+    #[test]
+    fn first_matching_url_rule_wins() {
+        let overrides = parse(
+            r#"
+            [[track]]
+            url = "file:///music/bootlegs/*"
+            artist = "The Band"
+
+            [[track]]
+            url = "*"
+            artist = "Anyone"
+            "#,
+        )
+        .unwrap();
+        let mut mi = track();
+        overrides.apply(&mut mi);
+        assert_eq!(mi.artist, "The Band");
+    }
+
+    #[test]
+    fn unmatched_track_is_untouched() {
+        let overrides = parse("[[track]]\nurl = \"https://*\"\nartist = \"x\"\n").unwrap();
+        let mut mi = track();
+        overrides.apply(&mut mi);
+        assert_eq!(mi, track());
+    }
+
+    #[test]
+    fn rule_needs_exactly_one_matcher() {
+        assert!(parse("[[track]]\ntitle = \"x\"\n").is_err());
+        assert!(parse("[[track]]\nkey = \"id:/a\"\nurl = \"*\"\n").is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// The directory of ours within each of the user's directories.
+const APP_DIR: &str = "discord-mediaplayer-rpc";
+
+/// Where the config and overrides files are.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join(APP_DIR))
+}
+
+/// Where answers from web services are kept for a while.
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join(APP_DIR))
+}
+
+/// Where the play history is kept.
+pub fn data_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join(APP_DIR))
+}
+
+/// Where what should outlive a restart, but isn't worth keeping for long,
+/// is kept: the session, crash record and webhook message ids.
+pub fn state_dir() -> Option<PathBuf> {
+    dirs::state_dir()
+        .or_else(dirs::data_local_dir)
+        .map(|dir| dir.join(APP_DIR))
+}
+
+/// Where what only matters while the daemon runs goes, such as its status
+/// and metrics, for other commands to read.
+pub fn runtime_dir() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(APP_DIR)
+}
+
+/// Writes `contents` to `path`, making its directory if need be. It's
+/// written to a temporary file and renamed, so readers never see a partial
+/// write.
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    let dir = path
+        .parent()
+        .with_context(|| format!("{} has no parent", path.display()))?;
+    std::fs::create_dir_all(dir)?;
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+    std::fs::write(&partial, contents)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomic_replaces_whole_file() {
+        let dir = std::env::temp_dir().join(format!("dmr-paths-{}", std::process::id()));
+        let path = dir.join("nested").join("now.json");
+        write_atomic(&path, "first, and longer").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        let left: Vec<_> = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["now.json"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use dbus::nonblock::stdintf::org_freedesktop_dbus::Properties;
+use dbus::nonblock::{Proxy, SyncConnection};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+const ROOT_INTERFACE: &str = "org.mpris.MediaPlayer2";
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+/// Picks the name shown for a player: a configured override wins, then the
+/// player's own MPRIS `Identity`, then the tail of its bus name.
+pub fn display_name(
+    overrides: &HashMap<String, String>,
+    bus_name: &str,
+    identity: Option<String>,
+) -> String {
+    overrides
+        .get(bus_name)
+        .cloned()
+        .or(identity)
+        .unwrap_or_else(|| {
+            bus_name
+                .strip_prefix(BUS_NAME_PREFIX)
+                .unwrap_or(bus_name)
+                .to_owned()
+        })
+}
+
+/// Whether `bus_name` is an MPRIS player's.
+pub fn is_player(bus_name: &str) -> bool {
+    bus_name.starts_with(BUS_NAME_PREFIX)
+}
+
+/// Whether the player at `bus_name` is kept out of the presence: it matches
+/// `ignore`, or there's an `only` list (strict mode) and it doesn't match
+/// that, even if it's the only player about.
+pub fn is_excluded(ignore: &[String], only: Option<&[String]>, bus_name: &str) -> bool {
+    matches_any(ignore, bus_name) || only.is_some_and(|only| !matches_any(only, bus_name))
+}
+
+/// Whether `bus_name` matches any of the patterns, in which `*` stands for
+/// any run of characters (e.g. `org.mpris.MediaPlayer2.chromium.*`).
+fn matches_any(patterns: &[String], bus_name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| glob_match(pattern.as_bytes(), bus_name.as_bytes()))
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of bytes.
+/// On a mismatch only the latest `*` is retried, taking in one more byte,
+/// so the work is bounded by the text's length times the pattern's rather
+/// than growing with the number of `*`s.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Just after the latest `*`, and where in the text it stopped.
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                p += 1;
+                star = Some((p, t));
+            }
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((after, from)) => {
+                    p = after;
+                    t = from + 1;
+                    star = Some((after, t));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+pub async fn read_identity(proxy: &Proxy<'_, Arc<SyncConnection>>) -> Option<String> {
+    proxy.get(ROOT_INTERFACE, "Identity").await.ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUDACIOUS: &str = "org.mpris.MediaPlayer2.audacious";
+
+    #[test]
+    fn override_beats_identity() {
+        let overrides = HashMap::from([(AUDACIOUS.to_owned(), "Aud".to_owned())]);
+        assert_eq!(
+            display_name(&overrides, AUDACIOUS, Some("Audacious".to_owned())),
+            "Aud"
+        );
+    }
+
+    #[test]
+    fn identity_used_without_override() {
+        assert_eq!(
+            display_name(&HashMap::new(), AUDACIOUS, Some("Audacious".to_owned())),
+            "Audacious"
+        );
+    }
+
+    #[test]
+    fn bus_name_tail_is_last_resort() {
+        assert_eq!(display_name(&HashMap::new(), AUDACIOUS, None), "audacious");
+    }
+
+    #[test]
+    fn ignore_patterns_match_exactly_or_by_wildcard() {
+        let patterns = [
+            "org.mpris.MediaPlayer2.spotify".to_owned(),
+            "org.mpris.MediaPlayer2.chromium.*".to_owned(),
+        ];
+        assert!(matches_any(&patterns, "org.mpris.MediaPlayer2.spotify"));
+        assert!(matches_any(
+            &patterns,
+            "org.mpris.MediaPlayer2.chromium.instance1234"
+        ));
+        assert!(!matches_any(&patterns, "org.mpris.MediaPlayer2.spotifyd"));
+        assert!(!matches_any(&patterns, AUDACIOUS));
+    }
+
+    #[test]
+    fn wildcards_match_anywhere() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"a*c*", b"abcbc"));
+        assert!(glob_match(b"*b*b", b"abab"));
+        assert!(!glob_match(b"a*c", b"abcb"));
+        assert!(!glob_match(b"", b"a"));
+    }
+
+    #[test]
+    fn many_wildcards_fail_quickly() {
+        let text = vec![b'a'; 10_000];
+        assert!(!glob_match(b"*a*a*a*a*a*a*a*b", &text));
+        assert!(glob_match(b"*a*a*a*a*a*a*a*", &text));
+    }
+
+    #[test]
+    fn only_mpris_names_are_players() {
+        assert!(is_player(AUDACIOUS));
+        assert!(!is_player("org.mpris.MediaPlayer2"));
+        assert!(!is_player("org.freedesktop.Notifications"));
+    }
+
+    #[test]
+    fn strict_mode_excludes_unlisted_players() {
+        let only = ["org.mpris.MediaPlayer2.audacious".to_owned()];
+        assert!(!is_excluded(&[], Some(&only), AUDACIOUS));
+        assert!(is_excluded(
+            &[],
+            Some(&only),
+            "org.mpris.MediaPlayer2.firefox"
+        ));
+        assert!(is_excluded(&only, Some(&only), AUDACIOUS));
+        assert!(is_excluded(&[], Some(&[]), AUDACIOUS));
+        assert!(!is_excluded(&[], None, AUDACIOUS));
+    }
+}
@@ -0,0 +1,350 @@
+use crate::duration::TrackDuration;
+use crate::track::TrackKey;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::Instant;
+
+/// How far apart two sets of timestamps may be before they count as changed.
+const DRIFT_TOLERANCE_SECS: u64 = 2;
+/// A position this far past the end of the track is put down to the player
+/// not having noticed the end yet, and shown as the end.
+const OVERRUN_TOLERANCE: Duration = Duration::from_secs(3);
+/// Lengths and positions beyond this are nonsense, such as a player's
+/// idea of "unknown".
+const MAX_LENGTH: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+struct Sample {
+    track: TrackKey,
+    position: Duration,
+    rate: f64,
+    playing: bool,
+    at: Instant,
+}
+
+/// Remembers the last position a player reported so it can be estimated
+/// when a later read fails, which several players do intermittently.
+#[derive(Default)]
+pub struct PositionTracker {
+    last: Option<Sample>,
+}
+
+impl PositionTracker {
+    pub fn observe(
+        &mut self,
+        track: &TrackKey,
+        position: TrackDuration,
+        rate: f64,
+        playing: bool,
+        at: Instant,
+    ) {
+        self.last = Some(Sample {
+            track: track.to_owned(),
+            position: position.as_duration(),
+            rate,
+            playing,
+            at,
+        });
+    }
+
+    /// Extrapolates from the last sample using wall-clock time and Rate. A
+    /// track we have no sample for is assumed to have just started.
+    pub fn estimate(&mut self, track: &TrackKey, playing: bool, at: Instant) -> TrackDuration {
+        let (position, rate) = match &self.last {
+            Some(last) if last.track == *track => {
+                let advanced = match last.playing {
+                    true => at.saturating_duration_since(last.at).mul_f64(last.rate),
+                    false => Duration::ZERO,
+                };
+                (last.position + advanced, last.rate)
+            }
+            _ => (Duration::ZERO, 1.0),
+        };
+        let position = TrackDuration::from(position);
+        self.observe(track, position, rate, playing, at);
+        position
+    }
+
+    pub fn rate(&self) -> Option<f64> {
+        self.last.as_ref().map(|last| last.rate)
+    }
+}
+
+/// The slowest and fastest a player is taken to be going; anything beyond
+/// would overflow the durations scaled by it.
+const RATES: std::ops::RangeInclusive<f64> = 0.01..=100.0;
+
+/// A rate that's safe to scale durations by. Rates players report as zero,
+/// negative or NaN are treated as normal speed, and others kept within
+/// `RATES`.
+pub fn sane_rate(rate: f64) -> f64 {
+    if rate.is_finite() && rate > 0.0 {
+        rate.clamp(*RATES.start(), *RATES.end())
+    } else {
+        1.0
+    }
+}
+
+/// A position reading: where the track was, how fast it was moving, and when.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Progress {
+    pub position: TrackDuration,
+    pub rate: f64,
+    pub at: SystemTime,
+}
+
+impl Progress {
+    /// Where the track sits on Discord's timeline, unless the player's
+    /// figures don't add up. A length of zero or one that's absurd is left
+    /// out, so only the time elapsed is shown; a position well past the end
+    /// leaves no telling which is wrong, so nothing is.
+    pub fn timestamps(&self, length: Option<TrackDuration>) -> Option<Timestamps> {
+        if self.position.as_duration() > MAX_LENGTH {
+            debug!("no timestamps, as the position {} is absurd", self.position);
+            return None;
+        }
+        let length = length.filter(|length| {
+            let sane = !length.as_duration().is_zero() && length.as_duration() <= MAX_LENGTH;
+            if !sane {
+                debug!("leaving out the end, as the length {} is absurd", length);
+            }
+            sane
+        });
+        let position = match length {
+            Some(length)
+                if self.position.as_duration() > length.as_duration() + OVERRUN_TOLERANCE =>
+            {
+                debug!(
+                    "no timestamps, as the position {} is past the length {}",
+                    self.position, length
+                );
+                return None;
+            }
+            Some(length) => self.position.min(length),
+            None => self.position,
+        };
+        Some(Timestamps::new(self.at, position, length, self.rate))
+    }
+
+    /// Where playback is at `now`, assuming it carried on at `rate` if playing.
+    pub fn position_at(&self, now: SystemTime, playing: bool) -> TrackDuration {
+        let advanced = match playing {
+            true => now
+                .duration_since(self.at)
+                .unwrap_or_default()
+                .mul_f64(sane_rate(self.rate)),
+            false => Duration::ZERO,
+        };
+        TrackDuration::from(self.position.as_duration() + advanced)
+    }
+}
+
+/// Activity timestamps, in unix seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Timestamps {
+    pub start: u64,
+    pub end: Option<u64>,
+}
+
+impl Timestamps {
+    pub fn new(
+        now: SystemTime,
+        position: TrackDuration,
+        length: Option<TrackDuration>,
+        rate: f64,
+    ) -> Self {
+        let rate = sane_rate(rate);
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let position = position.as_duration();
+        let start = now.saturating_sub(position.div_f64(rate));
+        let end =
+            length.map(|length| now + length.as_duration().saturating_sub(position).div_f64(rate));
+        Timestamps {
+            start: start.as_secs(),
+            end: end.map(|end| end.as_secs()),
+        }
+    }
+
+    /// Whether the two differ by more than reading jitter.
+    pub fn drifted(&self, other: &Timestamps) -> bool {
+        let apart = |a: u64, b: u64| a.abs_diff(b) > DRIFT_TOLERANCE_SECS;
+        apart(self.start, other.start)
+            || match (self.end, other.end) {
+                (Some(a), Some(b)) => apart(a, b),
+                (a, b) => a.is_some() != b.is_some(),
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(id: &str) -> TrackKey {
+        TrackKey::new(Some(id), "", "", None)
+    }
+
+    fn secs(secs: u64) -> TrackDuration {
+        TrackDuration::from(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn estimate_advances_from_last_sample_while_playing() {
+        let now = Instant::now();
+        let mut tracker = PositionTracker::default();
+        tracker.observe(&key("a"), secs(10), 1.0, true, now);
+        assert_eq!(
+            tracker.estimate(&key("a"), true, now + Duration::from_secs(5)),
+            secs(15)
+        );
+    }
+
+    #[test]
+    fn estimate_scales_with_rate() {
+        let now = Instant::now();
+        let mut tracker = PositionTracker::default();
+        tracker.observe(&key("a"), secs(10), 2.0, true, now);
+        assert_eq!(
+            tracker.estimate(&key("a"), true, now + Duration::from_secs(5)),
+            secs(20)
+        );
+    }
+
+    #[test]
+    fn estimate_holds_while_paused() {
+        let now = Instant::now();
+        let mut tracker = PositionTracker::default();
+        tracker.observe(&key("a"), secs(10), 1.0, false, now);
+        assert_eq!(
+            tracker.estimate(&key("a"), false, now + Duration::from_secs(5)),
+            secs(10)
+        );
+    }
+
+    #[test]
+    fn estimate_restarts_on_new_track() {
+        let now = Instant::now();
+        let mut tracker = PositionTracker::default();
+        tracker.observe(&key("a"), secs(100), 1.0, true, now);
+        let later = now + Duration::from_secs(5);
+        assert_eq!(tracker.estimate(&key("b"), true, later), secs(0));
+        assert_eq!(
+            tracker.estimate(&key("b"), true, later + Duration::from_secs(3)),
+            secs(3)
+        );
+    }
+
+    #[test]
+    fn timestamps_place_start_behind_and_end_ahead() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let timestamps = Timestamps::new(now, secs(30), Some(secs(200)), 1.0);
+        assert_eq!(
+            timestamps,
+            Timestamps {
+                start: 970,
+                end: Some(1170)
+            }
+        );
+    }
+
+    #[test]
+    fn timestamps_without_length_have_no_end() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        assert_eq!(Timestamps::new(now, secs(30), None, 1.0).end, None);
+    }
+
+    #[test]
+    fn timestamps_ignore_nonsensical_rate() {
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        assert_eq!(
+            Timestamps::new(now, secs(30), None, 0.0),
+            Timestamps::new(now, secs(30), None, 1.0)
+        );
+    }
+
+    #[test]
+    fn nonsensical_rates_are_normal_speed() {
+        assert_eq!(sane_rate(-2.0), 1.0);
+        assert_eq!(sane_rate(f64::NAN), 1.0);
+        assert_eq!(sane_rate(f64::INFINITY), 1.0);
+        assert_eq!(sane_rate(1.5), 1.5);
+    }
+
+    #[test]
+    fn extreme_rates_are_clamped() {
+        assert_eq!(sane_rate(1e-300), 0.01);
+        assert_eq!(sane_rate(1e300), 100.0);
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let timestamps = Timestamps::new(now, secs(30), Some(secs(200)), 1e-300);
+        assert_eq!(timestamps.start, 0);
+        let start = Instant::now();
+        let mut tracker = PositionTracker::default();
+        tracker.observe(&key("a"), secs(10), sane_rate(1e300), true, start);
+        assert_eq!(
+            tracker.estimate(&key("a"), true, start + Duration::from_secs(1)),
+            secs(110)
+        );
+    }
+
+    fn progress_at(position: u64) -> Progress {
+        Progress {
+            position: secs(position),
+            rate: 1.0,
+            at: UNIX_EPOCH + Duration::from_secs(1000),
+        }
+    }
+
+    #[test]
+    fn zero_or_absurd_length_leaves_out_the_end() {
+        let timestamps = progress_at(30).timestamps(Some(secs(0))).unwrap();
+        assert_eq!(timestamps.start, 970);
+        assert_eq!(timestamps.end, None);
+        let forever = TrackDuration::from_micros(i64::MAX);
+        assert_eq!(progress_at(30).timestamps(forever).unwrap().end, None);
+    }
+
+    #[test]
+    fn slight_overrun_is_shown_as_the_end() {
+        let timestamps = progress_at(202).timestamps(Some(secs(200))).unwrap();
+        assert_eq!(
+            timestamps,
+            Timestamps {
+                start: 800,
+                end: Some(1000)
+            }
+        );
+    }
+
+    #[test]
+    fn position_well_past_the_end_shows_nothing() {
+        assert_eq!(progress_at(500).timestamps(Some(secs(200))), None);
+        assert_eq!(progress_at(30 * 24 * 60 * 60).timestamps(None), None);
+    }
+
+    #[test]
+    fn progress_advances_only_while_playing() {
+        let progress = Progress {
+            position: secs(30),
+            rate: 1.0,
+            at: UNIX_EPOCH + Duration::from_secs(1000),
+        };
+        let later = UNIX_EPOCH + Duration::from_secs(1010);
+        assert_eq!(progress.position_at(later, true), secs(40));
+        assert_eq!(progress.position_at(later, false), secs(30));
+    }
+
+    #[test]
+    fn small_differences_are_not_drift() {
+        let a = Timestamps {
+            start: 100,
+            end: Some(300),
+        };
+        let b = Timestamps {
+            start: 101,
+            end: Some(301),
+        };
+        assert!(!a.drifted(&b));
+        assert!(a.drifted(&Timestamps { start: 110, ..a }));
+        assert!(a.drifted(&Timestamps { end: None, ..a }));
+    }
+}
@@ -0,0 +1,980 @@
+use crate::album::AlbumSession;
+use crate::config::{self, Config, Presence, ScreenShare, StatusDisplay, StatusImages, TimeShown};
+use crate::content::ContentType;
+use crate::enrich::Enrichment;
+use crate::events::{PlayerState, Published};
+use crate::ipc;
+use crate::locale;
+use crate::logging;
+use crate::metrics::{Failure, METRICS};
+use crate::odesli::{Links, Service};
+use crate::position::Timestamps;
+use crate::preview;
+use crate::toggles::Toggles;
+use crate::track::stable_hash;
+use crate::validate;
+use crate::{MediaInfo, PlaybackStatus};
+use log::info;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Where presences are sent: Discord itself, or a stand-in that records
+/// them in tests.
+pub trait DiscordClient {
+    fn set_activity(&mut self, activity: Activity) -> anyhow::Result<()>;
+    fn clear_activity(&mut self) -> anyhow::Result<()>;
+}
+
+/// A presence to show, before it's turned into Discord's own type.
+pub struct Activity {
+    pub kind: ContentType,
+    pub state: Option<String>,
+    pub details: String,
+    pub large_image: Option<String>,
+    pub large_text: Option<String>,
+    pub small_image: Option<String>,
+    pub small_text: Option<String>,
+    pub timestamps: Option<Timestamps>,
+    pub buttons: Vec<Button>,
+    pub party: Option<Party>,
+    pub status_display: StatusDisplay,
+}
+
+/// A group of friends listening to the same thing, shown together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Party {
+    pub id: String,
+    /// How many are in it, out of how many it can take.
+    pub size: Option<(u32, u32)>,
+}
+
+/// A link shown under the presence, for whoever's looking at it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Button {
+    pub label: String,
+    pub url: String,
+}
+
+impl From<Activity> for ipc::Activity {
+    fn from(activity: Activity) -> Self {
+        let assets = ipc::Assets {
+            large_image: activity.large_image,
+            large_text: activity.large_text,
+            small_image: activity.small_image,
+            small_text: activity.small_text,
+        };
+        ipc::Activity {
+            state: activity.state,
+            details: activity.details,
+            kind: activity.kind.activity_type(),
+            timestamps: activity
+                .timestamps
+                .map(|Timestamps { start, end }| ipc::Timestamps { start, end }),
+            assets: (assets != ipc::Assets::default()).then_some(assets),
+            party: activity.party.map(|party| ipc::Party {
+                id: party.id,
+                size: party.size,
+            }),
+            buttons: activity
+                .buttons
+                .into_iter()
+                .map(|button| ipc::Button {
+                    label: button.label,
+                    url: button.url,
+                })
+                .collect(),
+            // Discord's `status_display_type`: 0 for the name, 1 the state,
+            // 2 the details. The name is what it shows when given none.
+            status_display_type: match activity.status_display {
+                StatusDisplay::App => None,
+                StatusDisplay::Artist => Some(1),
+                StatusDisplay::Track => Some(2),
+            },
+        }
+    }
+}
+
+impl Activity {
+    fn last_played(mi: &MediaInfo, config: &config::LastPlayed) -> Self {
+        Activity {
+            kind: mi.content,
+            state: None,
+            details: format!("Last played: {} – {}", mi.artist, mi.title),
+            large_image: config.image.clone(),
+            large_text: None,
+            small_image: None,
+            small_text: None,
+            timestamps: None,
+            buttons: Vec::new(),
+            party: None,
+            status_display: StatusDisplay::App,
+        }
+    }
+
+    /// Names the album rather than the track, counting up from when it
+    /// started so moving on to its next track changes only the number,
+    /// which is out of the album's `tracks` when those are known.
+    fn album(mi: &MediaInfo, started: Option<u64>, tracks: Option<u32>) -> Self {
+        let number = |n: u32| locale::current().number(n.into());
+        let large_text = (!mi.player.is_empty()).then(|| format!("via {}", mi.player));
+        let details = match mi.artist.as_str() {
+            "" => format!("Listening to {}", mi.album),
+            artist => format!("Listening to {} by {}", mi.album, artist),
+        };
+        Activity {
+            kind: mi.content,
+            state: mi.track_number.map(|n| match tracks {
+                Some(tracks) if tracks >= n => format!("Track {} of {}", number(n), number(tracks)),
+                _ => format!("Track {}", number(n)),
+            }),
+            details,
+            large_image: None,
+            large_text,
+            small_image: None,
+            small_text: None,
+            timestamps: started.map(|start| Timestamps { start, end: None }),
+            buttons: Vec::new(),
+            party: None,
+            status_display: StatusDisplay::App,
+        }
+    }
+
+    /// Says something is playing without saying what.
+    fn generic() -> Self {
+        Activity {
+            kind: ContentType::Audio,
+            state: None,
+            details: "Listening to music".to_owned(),
+            large_image: None,
+            large_text: None,
+            small_image: None,
+            small_text: None,
+            timestamps: None,
+            buttons: Vec::new(),
+            party: None,
+            status_display: StatusDisplay::App,
+        }
+    }
+}
+
+impl From<MediaInfo> for Activity {
+    fn from(mi: MediaInfo) -> Self {
+        let large_text = match &mi.player {
+            p if p.is_empty() => None,
+            player => Some(format!("via {}", player)),
+        };
+        let from_album = (!mi.album.is_empty()).then(|| format!("From {}", mi.album));
+        let (details, state) = match mi.content {
+            ContentType::Stream => (format!("Listening to {}", mi.station()), mi.now_playing()),
+            ContentType::Video if mi.artist.is_empty() => {
+                (format!("Watching {}", mi.title), from_album)
+            }
+            kind => (
+                format!("{} {} - {}", kind.verb(), mi.artist, mi.title),
+                from_album,
+            ),
+        };
+        Activity {
+            kind: mi.content,
+            state,
+            details,
+            large_image: None,
+            large_text,
+            small_image: None,
+            small_text: None,
+            timestamps: None,
+            buttons: Vec::new(),
+            party: None,
+            status_display: StatusDisplay::App,
+        }
+    }
+}
+
+/// Where the playing track sits on Discord's timeline; nothing when paused.
+pub fn timestamps(state: &PlayerState) -> Option<Timestamps> {
+    match (&state.track, state.status, &state.progress) {
+        // A live stream's position is just time since tuning in.
+        (Some(mi), _, _) if mi.content == ContentType::Stream || mi.unseekable => None,
+        (Some(mi), PlaybackStatus::Playing, Some(progress)) => progress.timestamps(mi.length),
+        _ => None,
+    }
+}
+
+/// Drops the end when only the elapsed time is wanted, which is what
+/// Discord shows given a start alone.
+fn shown(timestamps: Timestamps, time_shown: TimeShown) -> Timestamps {
+    match time_shown {
+        TimeShown::Remaining => timestamps,
+        TimeShown::Elapsed => Timestamps {
+            end: None,
+            ..timestamps
+        },
+    }
+}
+
+/// Puts the artist in the state line for the friends list to show, when
+/// that's what it's to show and there is one to name.
+fn show_artist(activity: &mut Activity, mi: &MediaInfo, display: StatusDisplay) {
+    match display {
+        StatusDisplay::Artist if mi.artist.is_empty() => {}
+        StatusDisplay::Artist => {
+            activity.state = Some(mi.artist.clone());
+            activity.status_display = display;
+        }
+        display => activity.status_display = display,
+    }
+}
+
+/// The party of everyone with the same key playing the same album. The id
+/// is worked out here from the album and artist, so friends' copies agree
+/// on it without any server between them.
+fn party(mi: &MediaInfo, config: &config::Party) -> Option<Party> {
+    let key = config.key.as_deref()?;
+    if mi.album.is_empty() {
+        return None;
+    }
+    Some(Party {
+        id: format!("{:016x}", stable_hash(&[key, &mi.album, &mi.artist])),
+        size: config.size.map(|size| (1, size)),
+    })
+}
+
+/// The image given for the track, then the player's own cover if Discord
+/// can fetch it, then whatever enrichment found, such as the cover of a
+/// local file's album.
+pub fn large_image(mi: &MediaInfo, enrichment: &Enrichment) -> Option<String> {
+    let art = mi
+        .art_url
+        .as_deref()
+        .filter(|url| validate::is_web_url(url));
+    mi.image
+        .clone()
+        .or_else(|| art.map(str::to_owned))
+        .or_else(|| enrichment.large_image.clone())
+}
+
+/// The small image saying whether the track is playing or paused.
+fn status_image(status: PlaybackStatus, images: &StatusImages) -> Option<(String, String)> {
+    let (image, text) = match status {
+        PlaybackStatus::Playing => (&images.playing, "Playing"),
+        PlaybackStatus::Paused => (&images.paused, "Paused"),
+        _ => return None,
+    };
+    image.clone().map(|image| (image, text.to_owned()))
+}
+
+/// Links to the track on `link_to` if it was found there, otherwise to the
+/// page that has it on every service.
+fn listen_button(links: &Links, link_to: Option<Service>) -> Option<Button> {
+    let direct = link_to.and_then(|service| Some((service, links.services.get(&service)?)));
+    match direct {
+        Some((service, url)) => Some(Button {
+            label: format!("Listen on {}", service.name()),
+            url: url.clone(),
+        }),
+        None => links.page.clone().map(|url| Button {
+            label: "Listen".to_owned(),
+            url,
+        }),
+    }
+}
+
+/// The configured buttons for the player, then the listen button, which
+/// only shows if there's room left for it once they're validated.
+fn buttons(mi: &MediaInfo, state: &PlayerState, config: &Config, links: &Links) -> Vec<Button> {
+    let mut buttons: Vec<Button> = config
+        .buttons_for(&mi.bus_name)
+        .iter()
+        .map(|button| Button {
+            label: button.label.clone(),
+            url: mi.render_url(&button.url, state.status),
+        })
+        .collect();
+    buttons.extend(listen_button(links, config.enrichment.link_to));
+    buttons
+}
+
+/// Shows `state` on Discord, or clears the presence when there's nothing to
+/// show. Gives what was shown, or the error if Discord didn't take it, so
+/// that it can be sent again.
+pub fn publish(
+    client: &mut dyn DiscordClient,
+    state: &PlayerState,
+    config: &Config,
+    toggles: &Toggles,
+    last_played: Option<&MediaInfo>,
+    enrichment: &Enrichment,
+    album: &AlbumSession,
+) -> anyhow::Result<Option<Published>> {
+    let private = toggles.privacy;
+    let showing = match state.status {
+        PlaybackStatus::Playing => true,
+        // A paused track stays up when there's an image saying it's paused.
+        PlaybackStatus::Paused => config.status_images.paused.is_some(),
+        PlaybackStatus::Stopped | PlaybackStatus::Closed => false,
+    };
+    let activity = match (&state.track, showing) {
+        _ if private && config.screen_share == ScreenShare::Hide => None,
+        (Some(_), true) if private => Some(Activity::generic()),
+        (Some(mi), true)
+            if config.presence == Presence::Album
+                && mi.content == ContentType::Audio
+                && !mi.album.is_empty() =>
+        {
+            let mut activity = Activity::album(mi, album.started(mi), enrichment.album_tracks);
+            activity.large_image = large_image(mi, enrichment);
+            activity.buttons = buttons(mi, state, config, &enrichment.links);
+            activity.party = party(mi, &config.party);
+            Some(activity)
+        }
+        (Some(mi), true) => {
+            let mut activity: Activity = mi.clone().into();
+            let templates = &config.templates;
+            if let Some(template) = templates.discord_details() {
+                activity.details = mi.render(template, state.status, state.progress.as_ref());
+            }
+            if let Some(template) = &templates.discord.state {
+                activity.state = Some(mi.render(template, state.status, state.progress.as_ref()))
+                    .filter(|state| !state.is_empty());
+            }
+            if templates.discord.state.is_none() {
+                show_artist(&mut activity, mi, config.status_display);
+            } else {
+                activity.status_display = config.status_display;
+            }
+            activity.large_image = large_image(mi, enrichment);
+            activity.buttons = buttons(mi, state, config, &enrichment.links);
+            activity.timestamps = timestamps(state).map(|t| shown(t, config.time_shown));
+            activity.party = party(mi, &config.party);
+            Some(activity)
+        }
+        _ if private => None,
+        _ => last_played.map(|mi| Activity::last_played(mi, &config.last_played)),
+    };
+    let activity = activity.map(|mut activity| {
+        if !toggles.art {
+            activity.large_image = None;
+        }
+        if !toggles.buttons {
+            activity.buttons.clear();
+        }
+        if !toggles.timestamps {
+            activity.timestamps = None;
+        }
+        if let Some((image, text)) = status_image(state.status, &config.status_images) {
+            activity.small_image = Some(image);
+            activity.small_text = Some(text);
+        }
+        activity
+    });
+    match activity.map(validate::activity) {
+        Some(activity) => {
+            if config.debug.preview {
+                info!(
+                    event = logging::event::PREVIEW;
+                    "{}", preview::render(&activity, SystemTime::now())
+                );
+            }
+            let published = Published {
+                at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                details: activity.details.clone(),
+                state: activity.state.clone(),
+            };
+            let started = Instant::now();
+            let result = client.set_activity(activity);
+            METRICS.set_activity.observe(started.elapsed());
+            match result {
+                Ok(()) => Ok(Some(published)),
+                Err(e) => {
+                    METRICS.fail(Failure::DiscordSetActivity);
+                    Err(e)
+                }
+            }
+        }
+        None => {
+            clear(client);
+            Ok(None)
+        }
+    }
+}
+
+pub fn clear(client: &mut dyn DiscordClient) {
+    if client.clear_activity().is_err() {
+        METRICS.fail(Failure::DiscordClearActivity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duration::TrackDuration;
+    use crate::position::Progress;
+
+    /// Records what would have been sent to Discord.
+    #[derive(Default)]
+    struct Recording {
+        sent: Vec<Option<String>>,
+    }
+
+    impl DiscordClient for Recording {
+        fn set_activity(&mut self, activity: Activity) -> anyhow::Result<()> {
+            self.sent.push(Some(activity.details));
+            Ok(())
+        }
+
+        fn clear_activity(&mut self) -> anyhow::Result<()> {
+            self.sent.push(None);
+            Ok(())
+        }
+    }
+
+    fn playing() -> PlayerState {
+        PlayerState {
+            track: Some(MediaInfo {
+                artist: "A".to_owned(),
+                title: "T".to_owned(),
+                ..Default::default()
+            }),
+            status: PlaybackStatus::Playing,
+            progress: None,
+        }
+    }
+
+    fn send(
+        state: &PlayerState,
+        config: &Config,
+        sharing: bool,
+        last_played: Option<&MediaInfo>,
+    ) -> (Vec<Option<String>>, Option<Published>) {
+        let mut client = Recording::default();
+        let published = publish(
+            &mut client,
+            state,
+            config,
+            &Toggles {
+                privacy: sharing,
+                ..Default::default()
+            },
+            last_played,
+            &Enrichment::default(),
+            &AlbumSession::default(),
+        )
+        .unwrap();
+        (client.sent, published)
+    }
+
+    #[test]
+    fn playing_track_is_published() {
+        let (sent, published) = send(&playing(), &Config::default(), false, None);
+        assert_eq!(sent, [Some("Playing A - T".to_owned())]);
+        assert_eq!(published.unwrap().details, "Playing A - T");
+    }
+
+    /// Refuses everything, as a client that's lost Discord does.
+    struct Refusing;
+
+    impl DiscordClient for Refusing {
+        fn set_activity(&mut self, _: Activity) -> anyhow::Result<()> {
+            anyhow::bail!("not connected")
+        }
+
+        fn clear_activity(&mut self) -> anyhow::Result<()> {
+            anyhow::bail!("not connected")
+        }
+    }
+
+    #[test]
+    fn refused_activity_is_an_error() {
+        let publish = |state: &PlayerState| {
+            publish(
+                &mut Refusing,
+                state,
+                &Config::default(),
+                &Toggles::default(),
+                None,
+                &Enrichment::default(),
+                &AlbumSession::default(),
+            )
+        };
+        assert!(publish(&playing()).is_err());
+        // Clearing is left for next time, there being nothing to lose.
+        let stopped = PlayerState::not_playing(PlaybackStatus::Stopped);
+        assert_eq!(publish(&stopped).unwrap(), None);
+    }
+
+    #[test]
+    fn stopping_clears_presence() {
+        let stopped = PlayerState::not_playing(PlaybackStatus::Stopped);
+        let (sent, published) = send(&stopped, &Config::default(), false, None);
+        assert_eq!(sent, [None]);
+        assert_eq!(published, None);
+    }
+
+    #[test]
+    fn stopping_shows_last_played_when_remembered() {
+        let stopped = PlayerState::not_playing(PlaybackStatus::Stopped);
+        let last = playing().track;
+        let (sent, _) = send(&stopped, &Config::default(), false, last.as_ref());
+        assert_eq!(sent, [Some("Last played: A – T".to_owned())]);
+    }
+
+    #[test]
+    fn screen_share_hides_or_generalises() {
+        let mut config = Config {
+            screen_share: ScreenShare::Generic,
+            ..Default::default()
+        };
+        let (sent, _) = send(&playing(), &config, true, None);
+        assert_eq!(sent, [Some("Listening to music".to_owned())]);
+        config.screen_share = ScreenShare::Hide;
+        let (sent, _) = send(&playing(), &config, true, None);
+        assert_eq!(sent, [None]);
+    }
+
+    #[test]
+    fn activity_has_album_as_state_when_present() {
+        let media_info = MediaInfo {
+            album: "album".to_owned(),
+            artist: "artist".to_owned(),
+            title: "title".to_owned(),
+            ..Default::default()
+        };
+
+        let result: Activity = media_info.into();
+        assert_eq!(result.state, Some("From album".to_owned()));
+    }
+
+    #[test]
+    fn activity_has_no_state_when_album_empty() {
+        let media_info = MediaInfo {
+            album: "".to_owned(),
+            artist: "artist".to_owned(),
+            title: "title".to_owned(),
+            ..Default::default()
+        };
+
+        let result: Activity = media_info.into();
+        assert!(result.state.is_none());
+    }
+
+    #[test]
+    fn video_activity_is_watched() {
+        let media_info = MediaInfo {
+            title: "Heat".to_owned(),
+            content: ContentType::Video,
+            ..Default::default()
+        };
+
+        let result: Activity = media_info.into();
+        assert_eq!(result.details, "Watching Heat");
+        assert_eq!(result.kind, ContentType::Video);
+    }
+
+    #[test]
+    fn stream_shows_station_and_current_song() {
+        let media_info = MediaInfo {
+            title: "Song".to_owned(),
+            artist: "Band".to_owned(),
+            album: "Radio Paradise".to_owned(),
+            content: ContentType::Stream,
+            ..Default::default()
+        };
+
+        let result: Activity = media_info.into();
+        assert_eq!(result.details, "Listening to Radio Paradise");
+        assert_eq!(result.state.as_deref(), Some("Band - Song"));
+    }
+
+    #[test]
+    fn streams_have_no_timestamps() {
+        let media_info = MediaInfo {
+            content: ContentType::Stream,
+            ..Default::default()
+        };
+        let progress = Progress {
+            position: TrackDuration::from_micros(60_000_000).unwrap(),
+            rate: 1.0,
+            at: SystemTime::UNIX_EPOCH,
+        };
+        let state = PlayerState {
+            track: Some(media_info),
+            status: PlaybackStatus::Playing,
+            progress: Some(progress),
+        };
+        assert_eq!(timestamps(&state), None);
+    }
+
+    #[test]
+    fn unseekable_tracks_have_no_timestamps() {
+        let progress = Progress {
+            position: TrackDuration::from_micros(60_000_000).unwrap(),
+            rate: 1.0,
+            at: SystemTime::UNIX_EPOCH,
+        };
+        let mut state = PlayerState {
+            track: Some(MediaInfo::default()),
+            status: PlaybackStatus::Playing,
+            progress: Some(progress),
+        };
+        assert!(timestamps(&state).is_some());
+        state.track.as_mut().unwrap().unseekable = true;
+        assert_eq!(timestamps(&state), None);
+    }
+
+    #[test]
+    fn elapsed_time_has_no_end() {
+        let timestamps = Timestamps {
+            start: 100,
+            end: Some(300),
+        };
+        assert_eq!(shown(timestamps, TimeShown::Remaining), timestamps);
+        assert_eq!(shown(timestamps, TimeShown::Elapsed).end, None);
+    }
+
+    #[test]
+    fn album_activity_names_album_and_counts_tracks() {
+        let media_info = MediaInfo {
+            album: "Blue".to_owned(),
+            artist: "Joni Mitchell".to_owned(),
+            title: "River".to_owned(),
+            track_number: Some(7),
+            ..Default::default()
+        };
+        let activity = Activity::album(&media_info, Some(100), None);
+        assert_eq!(activity.details, "Listening to Blue by Joni Mitchell");
+        assert_eq!(activity.state.as_deref(), Some("Track 7"));
+        assert_eq!(
+            activity.timestamps,
+            Some(Timestamps {
+                start: 100,
+                end: None
+            })
+        );
+    }
+
+    #[test]
+    fn album_activity_counts_out_of_the_album_when_known() {
+        let media_info = MediaInfo {
+            album: "Blue".to_owned(),
+            track_number: Some(7),
+            ..Default::default()
+        };
+        let state = |tracks| Activity::album(&media_info, None, tracks).state;
+        assert_eq!(state(Some(10)).as_deref(), Some("Track 7 of 10"));
+        // A count the track is past belongs to some other edition.
+        assert_eq!(state(Some(5)).as_deref(), Some("Track 7"));
+    }
+
+    #[test]
+    fn activity_mentions_player_in_large_text() {
+        let media_info = MediaInfo {
+            player: "JRiver".to_owned(),
+            ..Default::default()
+        };
+
+        let result: Activity = media_info.into();
+        assert_eq!(result.large_text, Some("via JRiver".to_owned()));
+    }
+
+    /// Keeps the whole payload Discord would have been sent.
+    #[derive(Default)]
+    struct Payload(Option<ipc::Activity>);
+
+    impl DiscordClient for Payload {
+        fn set_activity(&mut self, activity: Activity) -> anyhow::Result<()> {
+            self.0 = Some(activity.into());
+            Ok(())
+        }
+
+        fn clear_activity(&mut self) -> anyhow::Result<()> {
+            self.0 = None;
+            Ok(())
+        }
+    }
+
+    fn payload(state: &PlayerState, config: &Config) -> Option<ipc::Activity> {
+        let enrichment = Enrichment {
+            large_image: state
+                .track
+                .as_ref()
+                .filter(|mi| mi.genres.iter().any(|genre| genre == "Jazz"))
+                .map(|_| "jazz".to_owned()),
+            ..Default::default()
+        };
+        payload_enriched(state, config, &enrichment)
+    }
+
+    fn payload_enriched(
+        state: &PlayerState,
+        config: &Config,
+        enrichment: &Enrichment,
+    ) -> Option<ipc::Activity> {
+        let mut client = Payload::default();
+        let mut album = AlbumSession::default();
+        album.observe(state, timestamps(state));
+        publish(
+            &mut client,
+            state,
+            config,
+            &Toggles::default(),
+            None,
+            enrichment,
+            &album,
+        )
+        .unwrap();
+        client.0
+    }
+
+    fn at_minute(mi: MediaInfo) -> PlayerState {
+        PlayerState {
+            track: Some(mi),
+            status: PlaybackStatus::Playing,
+            progress: Some(Progress {
+                position: TrackDuration::from_micros(60_000_000).unwrap(),
+                rate: 1.0,
+                at: SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000),
+            }),
+        }
+    }
+
+    fn river() -> MediaInfo {
+        MediaInfo {
+            title: "River".to_owned(),
+            artist: "Joni Mitchell".to_owned(),
+            album: "Blue".to_owned(),
+            player: "Lollypop".to_owned(),
+            length: TrackDuration::from_micros(240_000_000),
+            track_number: Some(7),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn payload_for_track() {
+        insta::assert_json_snapshot!(payload(&at_minute(river()), &Config::default()));
+    }
+
+    #[test]
+    fn payload_showing_artist_as_status() {
+        let config = Config {
+            status_display: StatusDisplay::Artist,
+            ..Default::default()
+        };
+        insta::assert_json_snapshot!(payload(&at_minute(river()), &config));
+    }
+
+    #[test]
+    fn status_display_falls_back_to_app_without_artist() {
+        let config = Config {
+            status_display: StatusDisplay::Artist,
+            ..Default::default()
+        };
+        let mi = MediaInfo {
+            artist: String::new(),
+            ..river()
+        };
+        let sent = payload(&at_minute(mi), &config).unwrap();
+        assert_eq!(sent.status_display_type, None);
+        assert_eq!(sent.state.as_deref(), Some("From Blue"));
+        let config = Config {
+            status_display: StatusDisplay::Track,
+            ..Default::default()
+        };
+        let sent = payload(&at_minute(river()), &config).unwrap();
+        assert_eq!(sent.status_display_type, Some(2));
+    }
+
+    #[test]
+    fn payload_for_track_without_album_or_length() {
+        let mi = MediaInfo {
+            album: String::new(),
+            length: None,
+            ..river()
+        };
+        insta::assert_json_snapshot!(payload(&at_minute(mi), &Config::default()));
+    }
+
+    #[test]
+    fn payload_for_video() {
+        let mi = MediaInfo {
+            title: "Heat".to_owned(),
+            content: ContentType::Video,
+            player: "mpv".to_owned(),
+            length: TrackDuration::from_micros(10_200_000_000),
+            ..Default::default()
+        };
+        insta::assert_json_snapshot!(payload(&at_minute(mi), &Config::default()));
+    }
+
+    #[test]
+    fn payload_for_stream() {
+        let mi = MediaInfo {
+            title: "Song".to_owned(),
+            artist: "Band".to_owned(),
+            album: "Radio Paradise".to_owned(),
+            content: ContentType::Stream,
+            ..Default::default()
+        };
+        insta::assert_json_snapshot!(payload(&at_minute(mi), &Config::default()));
+    }
+
+    #[test]
+    fn payload_for_album() {
+        let config = Config {
+            presence: Presence::Album,
+            ..Default::default()
+        };
+        insta::assert_json_snapshot!(payload(&at_minute(river()), &config));
+    }
+
+    #[test]
+    fn payload_with_templates_and_enrichment() {
+        let config = config::parse(
+            r#"
+            [templates.discord]
+            details = "{artist} – {title}"
+            state = "{album} · {length} · {genre}"
+            "#,
+        )
+        .unwrap();
+        let mi = MediaInfo {
+            genres: vec!["Jazz".to_owned()],
+            ..river()
+        };
+        insta::assert_json_snapshot!(payload(&at_minute(mi), &config));
+    }
+
+    fn links() -> Links {
+        Links {
+            page: Some("https://song.link/s/1".to_owned()),
+            services: [(Service::Tidal, "https://tidal.com/track/1".to_owned())].into(),
+        }
+    }
+
+    #[test]
+    fn payload_with_listen_button() {
+        let enrichment = Enrichment {
+            links: links(),
+            ..Default::default()
+        };
+        insta::assert_json_snapshot!(payload_enriched(
+            &at_minute(river()),
+            &Config::default(),
+            &enrichment
+        ));
+    }
+
+    #[test]
+    fn payload_with_configured_buttons() {
+        let config = config::parse(
+            r#"
+            [[buttons]]
+            label = "Last.fm"
+            url = "https://www.last.fm/music/{artist}/_/{title}"
+
+            [[buttons]]
+            label = "Lyrics"
+            url = "https://lyrics.example/?q={artist}+{title}"
+            "#,
+        )
+        .unwrap();
+        let enrichment = Enrichment {
+            links: links(),
+            ..Default::default()
+        };
+        // The listen button is left out, there being no room for it.
+        insta::assert_json_snapshot!(payload_enriched(&at_minute(river()), &config, &enrichment));
+    }
+
+    #[test]
+    fn payload_with_player_art_and_status_image() {
+        let config = config::parse(
+            r#"
+            [status_images]
+            playing = "play"
+            paused = "pause"
+            "#,
+        )
+        .unwrap();
+        let mi = MediaInfo {
+            art_url: Some("https://i.scdn.co/image/ab67616d0000b273".to_owned()),
+            ..river()
+        };
+        insta::assert_json_snapshot!(payload(&at_minute(mi), &config));
+    }
+
+    #[test]
+    fn local_art_makes_way_for_enrichment() {
+        let enrichment = Enrichment {
+            large_image: Some("https://coverartarchive.org/release/1/front-250".to_owned()),
+            ..Default::default()
+        };
+        let local = MediaInfo {
+            art_url: Some("file:///home/me/.cache/art/blue.jpg".to_owned()),
+            ..river()
+        };
+        assert_eq!(large_image(&local, &enrichment), enrichment.large_image);
+        let chosen = MediaInfo {
+            image: Some("joni".to_owned()),
+            art_url: Some("https://example.org/blue.jpg".to_owned()),
+            ..river()
+        };
+        assert_eq!(large_image(&chosen, &enrichment).as_deref(), Some("joni"));
+    }
+
+    #[test]
+    fn listen_button_prefers_chosen_service() {
+        let button = listen_button(&links(), Some(Service::Tidal)).unwrap();
+        assert_eq!(button.label, "Listen on TIDAL");
+        assert_eq!(button.url, "https://tidal.com/track/1");
+        let button = listen_button(&links(), Some(Service::Spotify)).unwrap();
+        assert_eq!(button.url, "https://song.link/s/1");
+        assert_eq!(listen_button(&Links::default(), None), None);
+    }
+
+    #[test]
+    fn party_matches_album_and_key() {
+        let party_config = |key: &str| config::Party {
+            key: Some(key.to_owned()),
+            size: Some(4),
+        };
+        let mi = river();
+        let ours = party(&mi, &party_config("friends")).unwrap();
+        assert_eq!(ours.size, Some((1, 4)));
+        assert_eq!(party(&mi, &party_config("friends")), Some(ours.clone()));
+        assert_ne!(party(&mi, &party_config("others")).unwrap().id, ours.id);
+        let single = MediaInfo {
+            album: String::new(),
+            ..mi.clone()
+        };
+        assert_eq!(party(&single, &party_config("friends")), None);
+        assert_eq!(party(&mi, &config::Party::default()), None);
+    }
+
+    #[test]
+    fn payload_has_party_when_keyed() {
+        let config = config::parse("[party]\nkey = 'friends'\nsize = 4").unwrap();
+        insta::assert_json_snapshot!(payload(&at_minute(river()), &config));
+    }
+
+    #[test]
+    fn payload_when_paused_is_cleared() {
+        let state = PlayerState {
+            status: PlaybackStatus::Paused,
+            ..at_minute(river())
+        };
+        insta::assert_json_snapshot!(payload(&state, &Config::default()));
+    }
+
+    #[test]
+    fn payload_when_paused_shows_the_paused_image() {
+        let config = config::parse("[status_images]\npaused = \"pause\"\n").unwrap();
+        let state = PlayerState {
+            status: PlaybackStatus::Paused,
+            ..at_minute(river())
+        };
+        insta::assert_json_snapshot!(payload(&state, &config));
+    }
+}
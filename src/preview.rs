@@ -0,0 +1,142 @@
+use crate::duration::TrackDuration;
+use crate::position::Timestamps;
+use crate::presence::Activity;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const BAR_WIDTH: u64 = 12;
+
+/// Renders a rough mock-up of how the Discord client will show `activity`,
+/// so templates can be tuned from the log instead of the client.
+pub fn render(activity: &Activity, now: SystemTime) -> String {
+    let mut lines = vec![activity.details.clone()];
+    lines.extend(activity.state.clone());
+    if let Some(timestamps) = activity.timestamps {
+        lines.push(progress(timestamps, now));
+    }
+    lines.extend(image("image", &activity.large_image, &activity.large_text));
+    lines.extend(image(
+        "small image",
+        &activity.small_image,
+        &activity.small_text,
+    ));
+    for button in &activity.buttons {
+        lines.push(format!("[{}]", button.label));
+    }
+    let mut rendered = String::from("╭─ presence preview");
+    for line in lines {
+        rendered.push_str("\n│ ");
+        rendered.push_str(&line);
+    }
+    rendered.push_str("\n╰─");
+    rendered
+}
+
+fn image(which: &str, image: &Option<String>, text: &Option<String>) -> Option<String> {
+    match (image, text) {
+        (None, None) => None,
+        (image, text) => Some(format!(
+            "[{}: {}]{}",
+            which,
+            image.as_deref().unwrap_or("default"),
+            text.as_deref()
+                .map(|text| format!(" \"{}\"", text))
+                .unwrap_or_default()
+        )),
+    }
+}
+
+fn progress(timestamps: Timestamps, now: SystemTime) -> String {
+    let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let elapsed = now.saturating_sub(timestamps.start);
+    let clock = |secs: u64| TrackDuration::from(Duration::from_secs(secs)).to_string();
+    match timestamps.end {
+        Some(end) => {
+            let total = end.saturating_sub(timestamps.start);
+            let filled = (elapsed.min(total) * BAR_WIDTH)
+                .checked_div(total)
+                .unwrap_or(0);
+            format!(
+                "{} {}{} {}",
+                clock(elapsed),
+                "━".repeat(filled as usize),
+                "─".repeat((BAR_WIDTH - filled) as usize),
+                clock(total)
+            )
+        }
+        None => format!("{} elapsed", clock(elapsed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn activity() -> Activity {
+        Activity {
+            kind: Default::default(),
+            state: Some("From album".to_owned()),
+            details: "Playing artist - title".to_owned(),
+            large_image: None,
+            large_text: None,
+            small_image: None,
+            small_text: None,
+            timestamps: None,
+            buttons: Vec::new(),
+            party: None,
+            status_display: Default::default(),
+        }
+    }
+
+    #[test]
+    fn renders_text_lines_in_a_box() {
+        assert_eq!(
+            render(&activity(), at(0)),
+            "╭─ presence preview\n│ Playing artist - title\n│ From album\n╰─"
+        );
+    }
+
+    #[test]
+    fn renders_progress_bar_when_end_known() {
+        let activity = Activity {
+            timestamps: Some(Timestamps {
+                start: 1000,
+                end: Some(1240),
+            }),
+            ..activity()
+        };
+        let rendered = render(&activity, at(1060));
+        assert!(
+            rendered.contains("│ 1:00 ━━━───────── 4:00"),
+            "{}",
+            rendered
+        );
+    }
+
+    #[test]
+    fn renders_elapsed_without_end() {
+        let activity = Activity {
+            timestamps: Some(Timestamps {
+                start: 1000,
+                end: None,
+            }),
+            ..activity()
+        };
+        assert!(render(&activity, at(1075)).contains("│ 1:15 elapsed"));
+    }
+
+    #[test]
+    fn renders_image_and_hover_text() {
+        let activity = Activity {
+            large_image: Some("metal".to_owned()),
+            large_text: Some("via Audacious".to_owned()),
+            small_image: None,
+            small_text: None,
+            ..activity()
+        };
+        assert!(render(&activity, at(0)).contains("│ [image: metal] \"via Audacious\""));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::wakeups;
+use log::debug;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// The kernel cuts process names down to this many bytes.
+const COMM_LENGTH: usize = 15;
+
+/// Whether `comm`, a name as the kernel keeps it, is one of `names`.
+fn is_named(comm: &str, names: &[String]) -> bool {
+    names.iter().any(|name| {
+        let name = name.as_bytes();
+        comm.as_bytes() == &name[..name.len().min(COMM_LENGTH)]
+    })
+}
+
+/// Whether a process under `proc_dir` (normally `/proc`) is named any of
+/// `names`.
+fn any_running(proc_dir: &Path, names: &[String]) -> bool {
+    let Ok(entries) = std::fs::read_dir(proc_dir) else {
+        return false;
+    };
+    entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .bytes()
+                .all(|b| b.is_ascii_digit())
+        })
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("comm")).ok())
+        .any(|comm| is_named(comm.trim_end(), names))
+}
+
+/// Checks every `interval` whether any of the processes named is running,
+/// yielding whether one is. Reading `/proc` is cheap enough that polling
+/// beats keeping a netlink connector open, which needs privileges.
+pub fn watch(names: Vec<String>, interval: Duration) -> watch::Receiver<bool> {
+    let proc_dir = Path::new("/proc");
+    let (tx, rx) = watch::channel(any_running(proc_dir, &names));
+    tokio::spawn(async move {
+        let mut poll = tokio::time::interval(interval);
+        loop {
+            poll.tick().await;
+            wakeups::trace("processes", "polling");
+            let running = any_running(proc_dir, &names);
+            tx.send_if_modified(|was| {
+                let changed = *was != running;
+                if changed {
+                    debug!("a process that pauses publishing is running: {}", running);
+                }
+                *was = running;
+                changed
+            });
+            if tx.is_closed() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn long_names_match_as_the_kernel_cuts_them() {
+        assert!(is_named("gnome-screencas", &names(&["gnome-screencast"])));
+        assert!(is_named("obs", &names(&["zoom", "obs"])));
+        assert!(!is_named("obs-ffmpeg-mux", &names(&["obs"])));
+    }
+
+    #[test]
+    fn only_process_directories_are_read() {
+        let dir = std::env::temp_dir().join(format!("dmr-proc-{}", std::process::id()));
+        for (entry, comm) in [("1", "systemd\n"), ("42", "obs\n"), ("self", "zoom\n")] {
+            std::fs::create_dir_all(dir.join(entry)).unwrap();
+            std::fs::write(dir.join(entry).join("comm"), comm).unwrap();
+        }
+        assert!(any_running(&dir, &names(&["obs"])));
+        assert!(!any_running(&dir, &names(&["zoom"])));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
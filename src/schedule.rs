@@ -0,0 +1,270 @@
+use anyhow::{anyhow, bail};
+use jiff::civil::{Date, Time, Weekday};
+use jiff::tz::TimeZone;
+use jiff::Timestamp;
+use serde::Deserialize;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Some days of the week, as bits from Monday (bit 0) to Sunday (bit 6).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Days(u8);
+
+impl Days {
+    const ALL: Days = Days(0b111_1111);
+
+    fn contains(self, day: Weekday) -> bool {
+        self.0 & (1 << day.to_monday_zero_offset()) != 0
+    }
+}
+
+impl FromStr for Days {
+    type Err = anyhow::Error;
+
+    /// Parses lists of days and ranges of them, like `Mon,Wed-Fri`. A range
+    /// may wrap around the weekend, as in `Fri-Mon`.
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let mut days = 0;
+        for part in text.split(',') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (weekday(first)?, weekday(last)?),
+                None => (weekday(part)?, weekday(part)?),
+            };
+            let mut day = first;
+            loop {
+                days |= 1 << day.to_monday_zero_offset();
+                if day == last {
+                    break;
+                }
+                day = day.next();
+            }
+        }
+        Ok(Days(days))
+    }
+}
+
+fn weekday(name: &str) -> anyhow::Result<Weekday> {
+    Ok(match name.trim().to_ascii_lowercase().as_str() {
+        "mon" => Weekday::Monday,
+        "tue" => Weekday::Tuesday,
+        "wed" => Weekday::Wednesday,
+        "thu" => Weekday::Thursday,
+        "fri" => Weekday::Friday,
+        "sat" => Weekday::Saturday,
+        "sun" => Weekday::Sunday,
+        _ => bail!("unknown day `{}`; use Mon, Tue, … Sun", name),
+    })
+}
+
+fn time_of_day(text: &str) -> anyhow::Result<Time> {
+    let (hour, minute) = text
+        .split_once(':')
+        .ok_or_else(|| anyhow!("`{}` isn't a time like 22:30", text))?;
+    Ok(Time::new(hour.parse()?, minute.parse()?, 0, 0)?)
+}
+
+/// A stretch of wall-clock time on certain days, like `Mon-Fri 09:00-17:00`
+/// or, for every day, `23:00-07:00`. A window that runs past midnight
+/// belongs to the day it starts on, and one that ends when it starts lasts
+/// all day.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Window {
+    days: Days,
+    start: Time,
+    end: Time,
+}
+
+impl FromStr for Window {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let (days, times) = match text.trim().split_once(char::is_whitespace) {
+            Some((days, times)) => (days.parse()?, times.trim()),
+            None => (Days::ALL, text.trim()),
+        };
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| anyhow!("`{}` needs a start and end, like 09:00-17:00", text))?;
+        Ok(Window {
+            days,
+            start: time_of_day(start)?,
+            end: time_of_day(end)?,
+        })
+    }
+}
+
+impl TryFrom<String> for Window {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> anyhow::Result<Self> {
+        text.parse()
+    }
+}
+
+impl Window {
+    fn crosses_midnight(&self) -> bool {
+        self.end <= self.start
+    }
+
+    /// Where the window starts and ends if it starts on `date`, as instants.
+    /// Times skipped by a clock change count as just after it.
+    fn on(&self, date: Date, time_zone: &TimeZone) -> Option<(Timestamp, Timestamp)> {
+        if !self.days.contains(date.weekday()) {
+            return None;
+        }
+        let end_date = match self.crosses_midnight() {
+            true => date.tomorrow().ok()?,
+            false => date,
+        };
+        let start = time_zone.to_timestamp(date.to_datetime(self.start)).ok()?;
+        let end = time_zone
+            .to_timestamp(end_date.to_datetime(self.end))
+            .ok()?;
+        Some((start, end))
+    }
+}
+
+/// Windows of wall-clock time in a time zone, so they follow its clock
+/// changes.
+pub struct Schedule {
+    windows: Vec<Window>,
+    time_zone: TimeZone,
+}
+
+impl Schedule {
+    pub fn new(windows: Vec<Window>, time_zone: TimeZone) -> Self {
+        Schedule { windows, time_zone }
+    }
+
+    /// Whether `at` falls within any of the windows.
+    pub fn contains(&self, at: Timestamp) -> bool {
+        let now = self.time_zone.to_datetime(at);
+        let yesterday = now.date().yesterday().ok();
+        self.windows.iter().any(|window| {
+            let today = window.days.contains(now.weekday());
+            match window.crosses_midnight() {
+                false => today && window.start <= now.time() && now.time() < window.end,
+                true => {
+                    (today && now.time() >= window.start)
+                        || (yesterday.is_some_and(|date| window.days.contains(date.weekday()))
+                            && now.time() < window.end)
+                }
+            }
+        })
+    }
+
+    /// The first time after `at` that a window starts or ends.
+    pub fn next_boundary(&self, at: Timestamp) -> Option<Timestamp> {
+        let today = self.time_zone.to_datetime(at).date();
+        // A week and a day either side covers every window's next edge.
+        (-1..=8)
+            .filter_map(|offset| today.checked_add(jiff::Span::new().days(offset)).ok())
+            .flat_map(|date| self.windows.iter().map(move |window| (window, date)))
+            .filter_map(|(window, date)| window.on(date, &self.time_zone))
+            .flat_map(|(start, end)| [start, end])
+            .filter(|&edge| edge > at)
+            .min()
+    }
+
+    /// How long from `at` until the next boundary.
+    pub fn until_next_boundary(&self, at: Timestamp) -> Option<Duration> {
+        self.next_boundary(at)
+            .and_then(|edge| Duration::try_from(edge.duration_since(at)).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jiff::civil::date;
+
+    fn new_york() -> TimeZone {
+        TimeZone::posix("EST5EDT,M3.2.0,M11.1.0").unwrap()
+    }
+
+    fn schedule(windows: &[&str]) -> Schedule {
+        let windows = windows.iter().map(|w| w.parse().unwrap()).collect();
+        Schedule::new(windows, new_york())
+    }
+
+    fn at(year: i16, month: i8, day: i8, hour: i8, minute: i8) -> Timestamp {
+        new_york()
+            .to_timestamp(date(year, month, day).at(hour, minute, 0, 0))
+            .unwrap()
+    }
+
+    #[test]
+    fn days_parse_as_lists_and_ranges() {
+        let days: Days = "Mon,Wed-Fri".parse().unwrap();
+        assert_eq!(days, Days(0b001_1101));
+        assert_eq!("Sat-Mon".parse::<Days>().unwrap(), Days(0b110_0001));
+        assert!("Someday".parse::<Days>().is_err());
+    }
+
+    #[test]
+    fn windows_need_a_valid_span() {
+        assert!("Mon-Fri 09:00".parse::<Window>().is_err());
+        assert!("25:00-26:00".parse::<Window>().is_err());
+        assert_eq!("09:00-17:00".parse::<Window>().unwrap().days, Days::ALL);
+    }
+
+    #[test]
+    fn window_only_covers_its_days() {
+        // 2024-06-07 is a Friday.
+        let office = schedule(&["Mon-Fri 09:00-17:00"]);
+        assert!(office.contains(at(2024, 6, 7, 9, 0)));
+        assert!(!office.contains(at(2024, 6, 7, 17, 0)));
+        assert!(!office.contains(at(2024, 6, 8, 12, 0)));
+    }
+
+    #[test]
+    fn overnight_window_belongs_to_its_starting_day() {
+        let nights = schedule(&["Fri 23:00-07:00"]);
+        assert!(nights.contains(at(2024, 6, 7, 23, 30)));
+        assert!(nights.contains(at(2024, 6, 8, 6, 59)));
+        assert!(!nights.contains(at(2024, 6, 7, 6, 0)));
+    }
+
+    #[test]
+    fn next_boundary_is_the_nearest_edge() {
+        let office = schedule(&["Mon-Fri 09:00-17:00"]);
+        assert_eq!(
+            office.next_boundary(at(2024, 6, 7, 12, 0)),
+            Some(at(2024, 6, 7, 17, 0))
+        );
+        // Friday evening waits out the weekend.
+        assert_eq!(
+            office.next_boundary(at(2024, 6, 7, 18, 0)),
+            Some(at(2024, 6, 10, 9, 0))
+        );
+        assert_eq!(schedule(&[]).next_boundary(at(2024, 6, 7, 18, 0)), None);
+    }
+
+    #[test]
+    fn night_is_an_hour_shorter_when_clocks_go_forward() {
+        // Clocks went forward at 02:00 on 2024-03-10.
+        let nights = schedule(&["22:00-07:00"]);
+        let wait = nights.until_next_boundary(at(2024, 3, 9, 22, 0)).unwrap();
+        assert_eq!(wait, Duration::from_secs(8 * 3600));
+    }
+
+    #[test]
+    fn night_is_an_hour_longer_when_clocks_go_back() {
+        // Clocks went back at 02:00 on 2024-11-03, so 01:30 happened twice.
+        let nights = schedule(&["22:00-07:00"]);
+        let wait = nights.until_next_boundary(at(2024, 11, 2, 22, 0)).unwrap();
+        assert_eq!(wait, Duration::from_secs(10 * 3600));
+        let first = at(2024, 11, 3, 1, 30);
+        assert!(nights.contains(first));
+        assert!(nights.contains(first + jiff::SignedDuration::from_hours(1)));
+    }
+
+    #[test]
+    fn skipped_times_fall_just_after_the_change() {
+        let skipped = schedule(&["Sun 02:15-02:45"]);
+        let start = skipped.next_boundary(at(2024, 3, 10, 1, 0)).unwrap();
+        assert_eq!(start, at(2024, 3, 10, 3, 15));
+        assert!(!skipped.contains(start));
+    }
+}
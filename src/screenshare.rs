@@ -0,0 +1,202 @@
+use crate::config::SessionBus;
+use crate::seat;
+use dbus::channel::MatchingReceiver;
+use dbus::message::{MatchRule, MessageType};
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::{Message, Path};
+use log::debug;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+const SCREENCAST_INTERFACE: &str = "org.freedesktop.portal.ScreenCast";
+const SESSION_INTERFACE: &str = "org.freedesktop.portal.Session";
+
+// Portal calls and session signals are addressed to the sharing application,
+// not broadcast, so the only way to see them is as a bus monitor.
+const MONITOR_RULES: [&str; 4] = [
+    "type='method_call',interface='org.freedesktop.portal.ScreenCast',member='Start'",
+    "type='method_call',interface='org.freedesktop.portal.Session',member='Close'",
+    "type='signal',interface='org.freedesktop.portal.Session',member='Closed'",
+    "type='signal',interface='org.freedesktop.DBus',member='NameOwnerChanged'",
+];
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    /// `sender` asked the portal to start streaming `session`.
+    Started {
+        session: String,
+        sender: String,
+    },
+    Ended {
+        session: String,
+    },
+    /// A bus client went away, taking any sessions it owned with it.
+    Vanished {
+        sender: String,
+    },
+}
+
+fn classify(msg: &Message) -> Option<Event> {
+    let interface = msg.interface()?;
+    let member = msg.member()?;
+    match (msg.msg_type(), &*interface, &*member) {
+        (MessageType::MethodCall, SCREENCAST_INTERFACE, "Start") => Some(Event::Started {
+            session: msg.get1::<Path>()?.to_string(),
+            sender: msg.sender()?.to_string(),
+        }),
+        (MessageType::MethodCall, SESSION_INTERFACE, "Close")
+        | (MessageType::Signal, SESSION_INTERFACE, "Closed") => Some(Event::Ended {
+            session: msg.path()?.to_string(),
+        }),
+        (MessageType::Signal, "org.freedesktop.DBus", "NameOwnerChanged") => {
+            let (name, _, new_owner) = msg.read3::<String, String, String>().ok()?;
+            new_owner
+                .is_empty()
+                .then_some(Event::Vanished { sender: name })
+        }
+        _ => None,
+    }
+}
+
+/// Screen-cast sessions believed to be streaming, keyed by session handle.
+#[derive(Debug, Default)]
+struct Sessions(HashMap<String, String>);
+
+impl Sessions {
+    fn apply(&mut self, event: Event) {
+        match event {
+            Event::Started { session, sender } => {
+                self.0.insert(session, sender);
+            }
+            Event::Ended { session } => {
+                self.0.remove(&session);
+            }
+            Event::Vanished { sender } => self.0.retain(|_, owner| *owner != sender),
+        }
+    }
+
+    fn active(&self) -> bool {
+        !self.0.is_empty()
+    }
+}
+
+/// Watches the session bus for xdg-desktop-portal screen casts, yielding
+/// whether one is running. A share the user cancels in the portal dialog
+/// counts until the application closes the session.
+pub async fn watch(session_bus: SessionBus) -> anyhow::Result<watch::Receiver<bool>> {
+    let (resource, conn) = seat::connect(session_bus).await?;
+    tokio::spawn(async {
+        let err = resource.await;
+        debug!("screen share monitor connection lost: {}", err);
+    });
+    become_monitor(&conn).await?;
+    let (tx, rx) = watch::channel(false);
+    let sessions = Mutex::new(Sessions::default());
+    conn.start_receive(
+        MatchRule::new(),
+        Box::new(move |msg, _| {
+            if let Some(event) = classify(&msg) {
+                let mut sessions = sessions.lock().unwrap();
+                sessions.apply(event);
+                tx.send_if_modified(|sharing| {
+                    let changed = *sharing != sessions.active();
+                    if changed {
+                        debug!("screen sharing: {}", sessions.active());
+                    }
+                    *sharing = sessions.active();
+                    changed
+                });
+            }
+            true
+        }),
+    );
+    Ok(rx)
+}
+
+async fn become_monitor(conn: &Arc<SyncConnection>) -> anyhow::Result<()> {
+    let bus = Proxy::new(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        Duration::from_secs(5),
+        conn.clone(),
+    );
+    bus.method_call::<(), _, _, _>(
+        "org.freedesktop.DBus.Monitoring",
+        "BecomeMonitor",
+        (MONITOR_RULES.to_vec(), 0u32),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: &str = "/org/freedesktop/portal/desktop/session/1_42/s1";
+
+    fn started(session: &str, sender: &str) -> Event {
+        Event::Started {
+            session: session.to_owned(),
+            sender: sender.to_owned(),
+        }
+    }
+
+    #[test]
+    fn closed_signal_ends_its_session() {
+        let msg = Message::new_signal(SESSION, SESSION_INTERFACE, "Closed").unwrap();
+        assert_eq!(
+            classify(&msg),
+            Some(Event::Ended {
+                session: SESSION.to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn only_lost_names_count_as_vanished() {
+        let owner_changed = |new_owner: &str| {
+            Message::new_signal(
+                "/org/freedesktop/DBus",
+                "org.freedesktop.DBus",
+                "NameOwnerChanged",
+            )
+            .unwrap()
+            .append3(":1.42", ":1.42", new_owner)
+        };
+        assert_eq!(
+            classify(&owner_changed("")),
+            Some(Event::Vanished {
+                sender: ":1.42".to_owned()
+            })
+        );
+        assert_eq!(classify(&owner_changed(":1.42")), None);
+    }
+
+    #[test]
+    fn sharing_lasts_until_every_session_ends() {
+        let mut sessions = Sessions::default();
+        sessions.apply(started("a", ":1.1"));
+        sessions.apply(started("b", ":1.2"));
+        sessions.apply(Event::Ended {
+            session: "a".to_owned(),
+        });
+        assert!(sessions.active());
+        sessions.apply(Event::Ended {
+            session: "b".to_owned(),
+        });
+        assert!(!sessions.active());
+    }
+
+    #[test]
+    fn vanished_sender_ends_its_sessions() {
+        let mut sessions = Sessions::default();
+        sessions.apply(started("a", ":1.1"));
+        sessions.apply(Event::Vanished {
+            sender: ":1.1".to_owned(),
+        });
+        assert!(!sessions.active());
+    }
+}
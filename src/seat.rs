@@ -0,0 +1,238 @@
+use crate::config::SessionBus;
+use dbus::blocking;
+use dbus::channel::Channel;
+use dbus::nonblock::SyncConnection;
+use dbus_tokio::connection::{self, IOResource};
+use log::debug;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+const LOGIND: &str = "org.freedesktop.login1";
+const GRAPHICAL_TYPES: [&str; 3] = ["x11", "wayland", "mir"];
+
+/// An entry from `ListSessions`: id, uid, user name, seat and object path.
+type ListedSession = (String, u32, String, String, dbus::Path<'static>);
+
+/// One of the user's login sessions, as logind sees it.
+#[derive(Debug, Clone, PartialEq)]
+struct Session {
+    id: String,
+    kind: String,
+    active: bool,
+}
+
+/// Connects to the session bus `policy` calls for: that of the user's active
+/// graphical session, or whichever the environment names. Falls back to
+/// the environment when there's no logind or its answer doesn't help.
+pub async fn connect(
+    policy: SessionBus,
+) -> Result<(IOResource<SyncConnection>, Arc<SyncConnection>), dbus::Error> {
+    let address = match policy {
+        // logind is asked over a blocking connection, and /proc walked.
+        SessionBus::Active => tokio::task::spawn_blocking(active_session_bus)
+            .await
+            .ok()
+            .flatten(),
+        SessionBus::Environment => None,
+    };
+    match address {
+        Some(address) => {
+            debug!("using the active session's bus at {}", address);
+            let mut channel = Channel::open_private(&address)?;
+            channel.register()?;
+            connection::from_channel(channel)
+        }
+        None => connection::new_session_sync(),
+    }
+}
+
+/// Connects to the bus at `address`, or to the system bus for `system`.
+pub fn connect_to(
+    address: &str,
+) -> Result<(IOResource<SyncConnection>, Arc<SyncConnection>), dbus::Error> {
+    if address == "system" {
+        return connection::new_system_sync();
+    }
+    let mut channel = Channel::open_private(address)?;
+    channel.register()?;
+    connection::from_channel(channel)
+}
+
+fn active_session_bus() -> Option<String> {
+    let uid = std::fs::metadata("/proc/self").ok()?.uid();
+    let sessions = sessions(uid)
+        .map_err(|e| debug!("can't list login sessions: {}", e))
+        .ok()?;
+    let session = pick(&sessions)?;
+    debug!("active graphical session is {}", session.id);
+    session_bus(&session.id, uid, Path::new("/proc"), Path::new("/run/user"))
+}
+
+/// The bus named in the environment of one of the user's own processes in
+/// the session, the leader often being root's; failing that, the user's
+/// bus under `run_user`, which is the session bus wherever systemd starts
+/// one per user.
+fn session_bus(session: &str, uid: u32, proc: &Path, run_user: &Path) -> Option<String> {
+    let scope = format!("/session-{}.scope", session);
+    let found = std::fs::read_dir(proc)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name().to_string_lossy().parse::<u32>().is_ok())
+        .filter(|entry| entry.metadata().is_ok_and(|meta| meta.uid() == uid))
+        .filter(|entry| {
+            std::fs::read_to_string(entry.path().join("cgroup"))
+                .is_ok_and(|cgroup| cgroup.lines().any(|line| line.ends_with(&scope)))
+        })
+        .find_map(|entry| bus_address(&std::fs::read(entry.path().join("environ")).ok()?));
+    found.or_else(|| {
+        let bus = run_user.join(uid.to_string()).join("bus");
+        bus.exists().then(|| format!("unix:path={}", bus.display()))
+    })
+}
+
+fn sessions(uid: u32) -> Result<Vec<Session>, dbus::Error> {
+    let conn = blocking::Connection::new_system()?;
+    let timeout = Duration::from_secs(2);
+    let manager = conn.with_proxy(LOGIND, "/org/freedesktop/login1", timeout);
+    let (listed,): (Vec<ListedSession>,) =
+        manager.method_call("org.freedesktop.login1.Manager", "ListSessions", ())?;
+    listed
+        .into_iter()
+        .filter(|(_, owner, _, _, _)| *owner == uid)
+        .map(|(id, _, _, _, path)| {
+            use blocking::stdintf::org_freedesktop_dbus::Properties;
+            let session = conn.with_proxy(LOGIND, path, timeout);
+            let interface = "org.freedesktop.login1.Session";
+            Ok(Session {
+                id,
+                kind: session.get(interface, "Type")?,
+                active: session.get(interface, "Active")?,
+            })
+        })
+        .collect()
+}
+
+/// The user's active graphical session. logind allows one active session
+/// per seat, so this is the one on the first seat in use.
+fn pick(sessions: &[Session]) -> Option<&Session> {
+    sessions
+        .iter()
+        .find(|session| session.active && GRAPHICAL_TYPES.contains(&session.kind.as_str()))
+}
+
+/// `DBUS_SESSION_BUS_ADDRESS` from a process's NUL-separated environment.
+fn bus_address(environ: &[u8]) -> Option<String> {
+    environ
+        .split(|&byte| byte == 0)
+        .filter_map(|entry| std::str::from_utf8(entry).ok())
+        .find_map(|entry| entry.strip_prefix("DBUS_SESSION_BUS_ADDRESS="))
+        .filter(|address| !address.is_empty())
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(id: &str, kind: &str, active: bool) -> Session {
+        Session {
+            id: id.to_owned(),
+            kind: kind.to_owned(),
+            active,
+        }
+    }
+
+    #[test]
+    fn active_graphical_session_is_picked() {
+        let sessions = [
+            session("1", "x11", false),
+            session("2", "tty", true),
+            session("3", "wayland", true),
+        ];
+        assert_eq!(pick(&sessions).map(|s| s.id.as_str()), Some("3"));
+    }
+
+    #[test]
+    fn no_pick_without_active_graphical_session() {
+        assert_eq!(
+            pick(&[session("1", "x11", false), session("2", "tty", true)]),
+            None
+        );
+    }
+
+    /// A made-up `/proc` and `/run/user`, every file in them ours.
+    struct Tree(std::path::PathBuf);
+
+    impl Tree {
+        fn new(name: &str) -> Self {
+            let root =
+                std::env::temp_dir().join(format!("dmr-seat-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(root.join("proc")).unwrap();
+            std::fs::create_dir_all(root.join("run")).unwrap();
+            Tree(root)
+        }
+
+        fn process(&self, pid: u32, cgroup: &str, environ: &[u8]) {
+            let dir = self.0.join("proc").join(pid.to_string());
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("cgroup"), cgroup).unwrap();
+            std::fs::write(dir.join("environ"), environ).unwrap();
+        }
+
+        fn lookup(&self, session: &str) -> Option<String> {
+            let uid = std::fs::metadata(&self.0).unwrap().uid();
+            session_bus(session, uid, &self.0.join("proc"), &self.0.join("run"))
+        }
+    }
+
+    impl Drop for Tree {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn session_bus_found_in_process_of_the_session() {
+        let tree = Tree::new("process");
+        tree.process(
+            10,
+            "0::/user.slice/user-1000.slice/session-2.scope\n",
+            b"DBUS_SESSION_BUS_ADDRESS=unix:path=/tmp/other\0",
+        );
+        tree.process(
+            11,
+            "0::/user.slice/user-1000.slice/session-3.scope\n",
+            b"DBUS_SESSION_BUS_ADDRESS=unix:path=/tmp/nested\0",
+        );
+        std::fs::create_dir_all(tree.0.join("proc").join("self")).unwrap();
+        assert_eq!(tree.lookup("3").as_deref(), Some("unix:path=/tmp/nested"));
+        assert_eq!(tree.lookup("4"), None);
+    }
+
+    #[test]
+    fn session_bus_falls_back_to_user_bus() {
+        let tree = Tree::new("fallback");
+        // The session's only process of ours never had a bus address.
+        tree.process(10, "0::/user.slice/session-3.scope\n", b"HOME=/home/me\0");
+        let uid = std::fs::metadata(&tree.0).unwrap().uid();
+        let dir = tree.0.join("run").join(uid.to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("bus"), "").unwrap();
+        assert_eq!(
+            tree.lookup("3"),
+            Some(format!("unix:path={}", dir.join("bus").display()))
+        );
+    }
+
+    #[test]
+    fn bus_address_read_from_environ() {
+        let environ = b"HOME=/home/me\0DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/1000/bus\0";
+        assert_eq!(
+            bus_address(environ).as_deref(),
+            Some("unix:path=/run/user/1000/bus")
+        );
+        assert_eq!(bus_address(b"HOME=/home/me\0"), None);
+    }
+}
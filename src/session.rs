@@ -0,0 +1,186 @@
+use crate::duration::TrackDuration;
+use crate::events::PlayerState;
+use crate::paths;
+use crate::position::{Progress, Timestamps};
+use crate::presence::timestamps;
+use crate::track::TrackKey;
+use crate::PlaybackStatus;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A session for a track of unknown length is only trusted for this long
+/// after it was saved, as there's no telling when the track would have ended.
+const UNBOUNDED_FOR: Duration = Duration::from_secs(10 * 60);
+
+/// The timestamps last published for a track, kept on disk so a restarted
+/// daemon shows the same ones rather than counting up from zero again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub track: TrackKey,
+    pub timestamps: Timestamps,
+    /// When it was saved, in unix seconds.
+    pub saved_at: u64,
+}
+
+impl Session {
+    /// The session to save after publishing `state`, if it has timestamps.
+    pub fn of(state: &PlayerState, now: SystemTime) -> Option<Self> {
+        Some(Session {
+            track: state.track.as_ref()?.key(),
+            timestamps: timestamps(state)?,
+            saved_at: unix_secs(now),
+        })
+    }
+
+    /// Whether saving `other` over this would change anything but the time.
+    pub fn shows_same(&self, other: &Session) -> bool {
+        self.track == other.track && self.timestamps == other.timestamps
+    }
+
+    /// Whether the track could still be playing from where it was.
+    fn current(&self, now: SystemTime) -> bool {
+        let now = unix_secs(now);
+        match self.timestamps.end {
+            Some(end) => self.timestamps.start <= now && now < end,
+            None => now.saturating_sub(self.saved_at) < UNBOUNDED_FOR.as_secs(),
+        }
+    }
+
+    /// Where the track would be now had it carried on playing, for players
+    /// that can't say themselves.
+    pub fn position_at(&self, track: &TrackKey, now: SystemTime) -> Option<TrackDuration> {
+        (self.track == *track && self.current(now)).then(|| {
+            TrackDuration::from(Duration::from_secs(
+                unix_secs(now).saturating_sub(self.timestamps.start),
+            ))
+        })
+    }
+
+    /// Progress giving exactly the saved timestamps, if `state` is the same
+    /// track still playing and its own timestamps are within jitter of them.
+    /// A track that's been restarted or seeked since is left alone.
+    pub fn restore(&self, state: &PlayerState, now: SystemTime) -> Option<Progress> {
+        let track = state.track.as_ref()?;
+        let progress = state.progress?;
+        let fresh = timestamps(state)?;
+        let same = state.status == PlaybackStatus::Playing
+            && self.track == track.key()
+            && self.current(now)
+            && !self.timestamps.drifted(&fresh);
+        same.then(|| Progress {
+            position: TrackDuration::from(Duration::ZERO),
+            rate: progress.rate,
+            at: UNIX_EPOCH + Duration::from_secs(self.timestamps.start),
+        })
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub fn path() -> Option<PathBuf> {
+    paths::state_dir().map(|dir| dir.join("session.json"))
+}
+
+pub fn load() -> Option<Session> {
+    read_from(&path()?)
+}
+
+pub fn save(session: &Session) -> anyhow::Result<()> {
+    write_to(&path().context("no state directory")?, session)
+}
+
+fn read_from(path: &Path) -> Option<Session> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+fn write_to(path: &Path, session: &Session) -> anyhow::Result<()> {
+    paths::write_atomic(path, serde_json::to_vec(session)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MediaInfo;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    fn river() -> MediaInfo {
+        MediaInfo {
+            title: "River".to_owned(),
+            length: TrackDuration::from_micros(240_000_000),
+            ..Default::default()
+        }
+    }
+
+    fn playing(mi: MediaInfo, position_secs: u64) -> PlayerState {
+        PlayerState {
+            track: Some(mi),
+            status: PlaybackStatus::Playing,
+            progress: Some(Progress {
+                position: TrackDuration::from(Duration::from_secs(position_secs)),
+                rate: 1.0,
+                at: at(NOW),
+            }),
+        }
+    }
+
+    fn saved() -> Session {
+        Session {
+            track: river().key(),
+            timestamps: Timestamps {
+                start: NOW - 61,
+                end: Some(NOW + 179),
+            },
+            saved_at: NOW - 30,
+        }
+    }
+
+    #[test]
+    fn same_track_gets_identical_timestamps() {
+        let progress = saved().restore(&playing(river(), 60), at(NOW)).unwrap();
+        let restored = PlayerState {
+            progress: Some(progress),
+            ..playing(river(), 60)
+        };
+        assert_eq!(timestamps(&restored), Some(saved().timestamps));
+    }
+
+    #[test]
+    fn restarted_or_other_track_is_left_alone() {
+        assert_eq!(saved().restore(&playing(river(), 5), at(NOW)), None);
+        let other = MediaInfo {
+            title: "Blue".to_owned(),
+            ..river()
+        };
+        assert_eq!(saved().restore(&playing(other, 60), at(NOW)), None);
+    }
+
+    #[test]
+    fn ended_session_is_stale() {
+        let late = NOW + 200;
+        assert_eq!(saved().position_at(&river().key(), at(late)), None);
+        assert_eq!(
+            saved().position_at(&river().key(), at(NOW)),
+            Some(TrackDuration::from(Duration::from_secs(61)))
+        );
+    }
+
+    #[test]
+    fn session_round_trips_through_file() {
+        let dir = std::env::temp_dir().join(format!("dmr-session-{}", std::process::id()));
+        let path = dir.join("session.json");
+        write_to(&path, &saved()).unwrap();
+        assert_eq!(read_from(&path), Some(saved()));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::config::{self, SessionBus};
+use crate::{health, paths, seat};
+use anyhow::Context;
+use dbus::nonblock::{Proxy, SyncConnection};
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+/// What's about on this machine for the daemon to work with.
+#[derive(Debug, Default)]
+struct Found {
+    /// Bus names of the MPRIS players running just now.
+    players: Vec<String>,
+    discord: bool,
+}
+
+async fn detect() -> Found {
+    Found {
+        players: players().await.unwrap_or_default(),
+        discord: health::discord_alive(),
+    }
+}
+
+async fn players() -> Result<Vec<String>, dbus::Error> {
+    let (resource, conn) = seat::connect(SessionBus::default()).await?;
+    let resource = tokio::spawn(resource);
+    let proxy: Proxy<Arc<SyncConnection>> = Proxy::new(
+        "org.freedesktop.DBus",
+        "/org/freedesktop/DBus",
+        Duration::from_secs(2),
+        conn,
+    );
+    let names: Result<(Vec<String>,), _> = proxy
+        .method_call("org.freedesktop.DBus", "ListNames", ())
+        .await;
+    resource.abort();
+    let mut players: Vec<String> = names?
+        .0
+        .into_iter()
+        .filter(|name| name.starts_with(BUS_NAME_PREFIX))
+        .collect();
+    players.sort();
+    Ok(players)
+}
+
+/// What was asked for, to be written out as a config file.
+#[derive(Debug, PartialEq)]
+struct Answers {
+    /// Players to show, and no others; any player when `None`.
+    only_players: Option<Vec<String>>,
+    cover_art: bool,
+    listen_button: bool,
+    history: bool,
+    last_played: bool,
+}
+
+impl Answers {
+    /// The config file, leaving out whatever the defaults already cover.
+    fn to_toml(&self) -> String {
+        let mut text = String::from("# Written by `discord-mediaplayer-rpc setup`.\n");
+        if let Some(players) = &self.only_players {
+            let players: Vec<String> = players
+                .iter()
+                .map(|player| toml::Value::from(player.as_str()).to_string())
+                .collect();
+            text += &format!("only_players = [{}]\n", players.join(", "));
+        }
+        if !self.history {
+            text += "\n[history]\nenabled = false\n";
+        }
+        if self.last_played {
+            text += "\n[last_played]\nenabled = true\n";
+        }
+        if self.cover_art || self.listen_button {
+            let mut stages = vec!["\"genre_images\""];
+            if self.cover_art {
+                stages.push("\"cover_art\"");
+            }
+            if self.listen_button {
+                stages.push("\"streaming_links\"");
+            }
+            text += &format!("\n[enrichment]\nstages = [{}]\n", stages.join(", "));
+        }
+        text
+    }
+}
+
+/// Asks what's wanted, telling what was found along the way.
+fn interview(
+    found: &Found,
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+) -> io::Result<Answers> {
+    match found.discord {
+        true => writeln!(output, "Discord is running.")?,
+        false => writeln!(
+            output,
+            "Discord isn't running, or can't be reached; start it before the daemon."
+        )?,
+    }
+    let only_players = match found.players.as_slice() {
+        [] => {
+            writeln!(
+                output,
+                "No players are running, so whichever plays will be shown."
+            )?;
+            None
+        }
+        players => {
+            writeln!(output, "Players running:")?;
+            for (number, player) in players.iter().enumerate() {
+                let name = player.strip_prefix(BUS_NAME_PREFIX).unwrap_or(player);
+                writeln!(output, "  {}. {}", number + 1, name)?;
+            }
+            let chosen = ask(
+                input,
+                output,
+                "Show which? Numbers separated by spaces, or none for any player",
+            )?;
+            let chosen: Vec<String> = chosen
+                .split_whitespace()
+                .filter_map(|number| number.parse::<usize>().ok())
+                .filter_map(|number| players.get(number.checked_sub(1)?).cloned())
+                .collect();
+            (!chosen.is_empty()).then_some(chosen)
+        }
+    };
+    Ok(Answers {
+        only_players,
+        cover_art: confirm(
+            input,
+            output,
+            "Show album covers, looked up on MusicBrainz?",
+            false,
+        )?,
+        listen_button: confirm(
+            input,
+            output,
+            "Add a button linking to the track on streaming services?",
+            false,
+        )?,
+        history: confirm(
+            input,
+            output,
+            "Keep a history of what's played, for `stats`?",
+            true,
+        )?,
+        last_played: confirm(
+            input,
+            output,
+            "Keep showing the last track for a while after stopping?",
+            false,
+        )?,
+    })
+}
+
+fn ask(input: &mut impl BufRead, output: &mut impl Write, question: &str) -> io::Result<String> {
+    write!(output, "{}: ", question)?;
+    output.flush()?;
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim().to_owned())
+}
+
+/// Asks a yes or no question, taking an empty or unclear answer as `default`.
+fn confirm(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    question: &str,
+    default: bool,
+) -> io::Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    let answer = ask(input, output, &format!("{} [{}]", question, hint))?;
+    Ok(match answer.to_lowercase().as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
+
+/// Walks through setting up on this machine and writes the config file,
+/// asking first before replacing one.
+pub async fn run() -> anyhow::Result<()> {
+    let path = config::path().context("no config directory to write to")?;
+    let found = detect().await;
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut output = io::stdout();
+    if path.exists()
+        && !confirm(
+            &mut input,
+            &mut output,
+            &format!("{} already exists. Replace it?", path.display()),
+            false,
+        )?
+    {
+        return Ok(());
+    }
+    let text = interview(&found, &mut input, &mut output)?.to_toml();
+    // Whatever's written has to load, or the daemon won't start.
+    config::parse(&text).context("the config worked out isn't valid")?;
+    paths::write_atomic(&path, &text)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Stage;
+
+    fn interviewed(found: &Found, answers: &str) -> (Answers, String) {
+        let mut output = Vec::new();
+        let answers = interview(found, &mut answers.as_bytes(), &mut output).unwrap();
+        (answers, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn chosen_players_are_the_only_ones() {
+        let found = Found {
+            players: vec![
+                "org.mpris.MediaPlayer2.mpv".to_owned(),
+                "org.mpris.MediaPlayer2.spotify".to_owned(),
+            ],
+            discord: true,
+        };
+        let (answers, output) = interviewed(&found, "2 9\ny\n\n\nyes\n");
+        assert!(output.contains("  2. spotify"));
+        assert_eq!(
+            answers,
+            Answers {
+                only_players: Some(vec!["org.mpris.MediaPlayer2.spotify".to_owned()]),
+                cover_art: true,
+                listen_button: false,
+                history: true,
+                last_played: true,
+            }
+        );
+    }
+
+    #[test]
+    fn nothing_found_asks_only_about_features() {
+        let (answers, output) = interviewed(&Found::default(), "");
+        assert!(output.contains("Discord isn't running"));
+        assert_eq!(answers.only_players, None);
+        assert!(answers.history);
+        assert!(!answers.cover_art);
+    }
+
+    #[test]
+    fn written_config_loads_as_answered() {
+        let answers = Answers {
+            only_players: Some(vec!["org.mpris.MediaPlayer2.\"odd\"".to_owned()]),
+            cover_art: false,
+            listen_button: true,
+            history: false,
+            last_played: true,
+        };
+        let config = config::parse(&answers.to_toml()).unwrap();
+        assert_eq!(
+            config.only_players,
+            Some(vec!["org.mpris.MediaPlayer2.\"odd\"".to_owned()])
+        );
+        assert_eq!(
+            config.enrichment.stages,
+            [Stage::GenreImages, Stage::StreamingLinks]
+        );
+        assert!(!config.history.enabled);
+        assert!(config.last_played.enabled);
+    }
+}
@@ -0,0 +1,196 @@
+use crate::content::ContentType;
+use crate::duration::TrackDuration;
+use crate::events::{Bus, PlayerState, Reporter};
+use crate::position::Progress;
+use crate::{MediaInfo, PlaybackStatus};
+use anyhow::Context;
+use log::info;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// A track as written in a scenario, with its length in seconds.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Track {
+    title: String,
+    artist: String,
+    album: String,
+    genres: Vec<String>,
+    player: Option<String>,
+    length: Option<f64>,
+    url: Option<String>,
+    track_number: Option<u32>,
+}
+
+impl From<Track> for MediaInfo {
+    fn from(track: Track) -> Self {
+        let length = track
+            .length
+            .map(|secs| Duration::from_secs_f64(secs).into());
+        MediaInfo {
+            title: track.title,
+            artist: track.artist,
+            album: track.album,
+            track_id: None,
+            genres: track.genres,
+            player: track.player.unwrap_or_else(|| "Simulator".to_owned()),
+            length,
+            content: ContentType::detect(track.url.as_deref(), length),
+            url: track.url,
+            unseekable: false,
+            track_number: track.track_number,
+            image: None,
+            art_url: None,
+            link: None,
+            bus_name: String::new(),
+        }
+    }
+}
+
+/// One moment in a scenario: after `wait` seconds, whatever is given
+/// changes and everything else carries on as it was.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Step {
+    wait: f64,
+    track: Option<Track>,
+    status: Option<PlaybackStatus>,
+    /// Seconds into the track; otherwise it carries on from where it was,
+    /// or starts at zero for a new track.
+    position: Option<f64>,
+}
+
+/// Scripted player readings, in place of a real player.
+pub struct Scenario(Vec<Step>);
+
+impl Scenario {
+    /// Reads a JSON array of steps.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let steps =
+            serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        Ok(Scenario(steps))
+    }
+
+    /// Plays the steps in real time onto `bus`, as the player reader would.
+    pub async fn play(self, bus: &Bus) {
+        let mut reporter = Reporter::default();
+        let mut state = PlayerState::not_playing(PlaybackStatus::Stopped);
+        let mut track = None;
+        for step in self.0 {
+            tokio::time::sleep(Duration::from_secs_f64(step.wait.max(0.0))).await;
+            state = advance(&state, &mut track, step, SystemTime::now());
+            info!("simulating {:?} {:?}", state.status, state.track);
+            for event in reporter.report(state.clone()) {
+                bus.send(event);
+            }
+        }
+    }
+}
+
+/// The reading after `step`. `track` remembers the loaded track while
+/// stopped, as a player would.
+fn advance(
+    previous: &PlayerState,
+    track: &mut Option<MediaInfo>,
+    step: Step,
+    now: SystemTime,
+) -> PlayerState {
+    let new_track = step.track.is_some();
+    if let Some(next) = step.track {
+        *track = Some(next.into());
+    }
+    let status = step.status.unwrap_or(previous.status);
+    let (Some(mi), PlaybackStatus::Playing | PlaybackStatus::Paused) = (track.as_ref(), status)
+    else {
+        return PlayerState::not_playing(status);
+    };
+    let position = match (step.position, &previous.progress) {
+        (Some(secs), _) => Duration::from_secs_f64(secs.max(0.0)).into(),
+        (None, Some(progress)) if !new_track => {
+            progress.position_at(now, previous.status == PlaybackStatus::Playing)
+        }
+        _ => TrackDuration::default(),
+    };
+    PlayerState {
+        track: Some(mi.clone()),
+        status,
+        progress: Some(Progress {
+            position,
+            rate: 1.0,
+            at: now,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steps(json: &str) -> Vec<Step> {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn run(json: &str, now: SystemTime) -> Vec<PlayerState> {
+        let mut state = PlayerState::not_playing(PlaybackStatus::Stopped);
+        let mut track = None;
+        steps(json)
+            .into_iter()
+            .map(|step| {
+                state = advance(&state, &mut track, step, now);
+                state.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scenario_track_becomes_media_info() {
+        let states = run(
+            r#"[{"track": {"title": "River", "artist": "Joni Mitchell", "length": 240},
+                 "status": "Playing"}]"#,
+            SystemTime::now(),
+        );
+        let mi = states[0].track.as_ref().unwrap();
+        assert_eq!(mi.title, "River");
+        assert_eq!(mi.player, "Simulator");
+        assert_eq!(mi.length, TrackDuration::from_micros(240_000_000));
+    }
+
+    #[test]
+    fn unchanged_fields_carry_over() {
+        let states = run(
+            r#"[{"track": {"title": "a"}, "status": "Playing", "position": 30},
+                {"status": "Paused"},
+                {"status": "Stopped"},
+                {"status": "Playing"}]"#,
+            SystemTime::now(),
+        );
+        assert_eq!(states[1].track.as_ref().unwrap().title, "a");
+        assert_eq!(
+            states[1].progress.unwrap().position,
+            TrackDuration::from_micros(30_000_000).unwrap()
+        );
+        assert_eq!(states[2].track, None);
+        assert_eq!(states[3].track.as_ref().unwrap().title, "a");
+    }
+
+    #[test]
+    fn new_track_starts_at_zero() {
+        let states = run(
+            r#"[{"track": {"title": "a"}, "status": "Playing", "position": 30},
+                {"track": {"title": "b"}}]"#,
+            SystemTime::now(),
+        );
+        assert_eq!(
+            states[1].progress.unwrap().position,
+            TrackDuration::default()
+        );
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(serde_json::from_str::<Vec<Step>>(r#"[{"lyrics": "la"}]"#).is_err());
+    }
+}
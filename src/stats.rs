@@ -0,0 +1,149 @@
+use crate::history::{Outcome, Play};
+use crate::locale::{self, Locale};
+use crate::track::TrackKey;
+use std::collections::HashMap;
+use std::fmt::Display;
+
+/// How many of the most skipped tracks to list.
+const TOP: usize = 10;
+
+#[derive(Debug, PartialEq)]
+struct TrackStats {
+    label: String,
+    plays: usize,
+    skips: usize,
+}
+
+/// Skip and completion counts over the play history.
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    plays: usize,
+    skips: usize,
+    most_skipped: Vec<TrackStats>,
+}
+
+impl Stats {
+    pub fn new(history: &[Play]) -> Self {
+        let mut by_track: HashMap<&TrackKey, TrackStats> = HashMap::new();
+        for play in history {
+            let track = by_track.entry(&play.track).or_insert_with(|| TrackStats {
+                label: format!("{} - {}", play.artist, play.title),
+                plays: 0,
+                skips: 0,
+            });
+            track.plays += 1;
+            if play.outcome == Outcome::Skipped {
+                track.skips += 1;
+            }
+        }
+        let mut most_skipped: Vec<_> = by_track
+            .into_values()
+            .filter(|track| track.skips > 0)
+            .collect();
+        most_skipped.sort_by(|a, b| {
+            b.skips
+                .cmp(&a.skips)
+                .then(a.plays.cmp(&b.plays))
+                .then_with(|| a.label.cmp(&b.label))
+        });
+        most_skipped.truncate(TOP);
+        Stats {
+            plays: history.len(),
+            skips: history
+                .iter()
+                .filter(|play| play.outcome == Outcome::Skipped)
+                .count(),
+            most_skipped,
+        }
+    }
+}
+
+fn percent(part: usize, whole: usize) -> u64 {
+    match whole {
+        0 => 0,
+        whole => (part as f64 * 100.0 / whole as f64).round() as u64,
+    }
+}
+
+impl Stats {
+    /// The report, with counts written for `locale`.
+    pub fn render(&self, locale: Locale) -> String {
+        let n = |count: usize| locale.number(count as u64);
+        let mut report = format!(
+            "plays: {}\ncompleted: {}\nskipped: {} ({}%)",
+            n(self.plays),
+            n(self.plays - self.skips),
+            n(self.skips),
+            percent(self.skips, self.plays)
+        );
+        if !self.most_skipped.is_empty() {
+            report.push_str("\nmost skipped:");
+            for track in &self.most_skipped {
+                report.push_str(&format!(
+                    "\n  {}/{} ({}%)  {}",
+                    n(track.skips),
+                    n(track.plays),
+                    percent(track.skips, track.plays),
+                    track.label
+                ));
+            }
+        }
+        report
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render(locale::current()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(title: &str, outcome: Outcome) -> Play {
+        Play {
+            track: TrackKey::new(None, "A", title, None),
+            artist: "A".to_owned(),
+            title: title.to_owned(),
+            album: String::new(),
+            player: String::new(),
+            ended_at: 0,
+            reached: 0,
+            outcome,
+        }
+    }
+
+    #[test]
+    fn counts_skips_per_track() {
+        let history = [
+            play("x", Outcome::Skipped),
+            play("x", Outcome::Skipped),
+            play("y", Outcome::Skipped),
+            play("y", Outcome::Completed),
+            play("z", Outcome::Completed),
+        ];
+        assert_eq!(
+            Stats::new(&history).to_string(),
+            "plays: 5\ncompleted: 2\nskipped: 3 (60%)\nmost skipped:\n  2/2 (100%)  A - x\n  1/2 (50%)  A - y"
+        );
+    }
+
+    #[test]
+    fn counts_follow_locale() {
+        let history: Vec<_> = (0..1500).map(|_| play("x", Outcome::Completed)).collect();
+        let locale = Locale::from_tag("de_DE", crate::config::Hours::Auto);
+        assert!(Stats::new(&history)
+            .render(locale)
+            .starts_with("plays: 1.500\n"));
+    }
+
+    #[test]
+    fn empty_history_has_no_rate() {
+        assert_eq!(
+            Stats::new(&[]).to_string(),
+            "plays: 0\ncompleted: 0\nskipped: 0 (0%)"
+        );
+    }
+}
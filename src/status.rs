@@ -0,0 +1,228 @@
+use crate::events::Published;
+use crate::paths;
+use crate::position::Progress;
+use crate::updates::Strategy;
+use crate::wire::{self, Track};
+use crate::PlaybackStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// What the running daemon currently knows, kept on disk so other
+/// invocations (e.g. `now`) can read it without talking to the player.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The wire format version; missing from files written before there
+    /// was one.
+    #[serde(default)]
+    pub version: u32,
+    pub pid: u32,
+    pub status: PlaybackStatus,
+    pub track: Option<Track>,
+    /// The last position reading, from which widgets can extrapolate the
+    /// current position without polling the player themselves.
+    pub progress: Option<Progress>,
+    /// The track rendered through the status template.
+    #[serde(default)]
+    pub text: Option<String>,
+    /// The most recently published presences, oldest first.
+    #[serde(default)]
+    pub recent: Vec<Published>,
+    /// How the daemon notices the player's changes.
+    #[serde(default)]
+    pub updates: Option<Strategy>,
+    /// The dominant colour of the track's cover, as `#rrggbb`, for widgets
+    /// to theme themselves by.
+    #[serde(default)]
+    pub color: Option<String>,
+    /// Whether the daemon is getting through to Discord.
+    #[serde(default)]
+    pub discord: bool,
+    /// Whether the daemon is connected to the session bus.
+    #[serde(default)]
+    pub dbus: bool,
+}
+
+/// The last few presences published, for answering "what was that?"
+/// without a scrobbler. Republishing the same thing (say, to move the
+/// timestamps) doesn't count as a new entry.
+pub struct Recent {
+    entries: VecDeque<Published>,
+    capacity: usize,
+}
+
+impl Recent {
+    pub fn new(capacity: usize) -> Self {
+        Recent {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, published: Published) {
+        if let Some(last) = self.entries.back() {
+            if last.details == published.details && last.state == published.state {
+                return;
+            }
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        if self.capacity > 0 {
+            self.entries.push_back(published);
+        }
+    }
+
+    pub fn to_vec(&self) -> Vec<Published> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+/// One line of `recent`: how long ago, then what was shown.
+pub struct Entry<'a> {
+    pub published: &'a Published,
+    pub now: u64,
+}
+
+impl Display for Entry<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let minutes = self.now.saturating_sub(self.published.at) / 60;
+        match minutes {
+            0 => write!(f, "just now")?,
+            m if m < 60 => write!(f, "{}m ago", m)?,
+            m => write!(f, "{}h{:02}m ago", m / 60, m % 60)?,
+        }
+        write!(f, "  {}", self.published.details)?;
+        match &self.published.state {
+            Some(state) => write!(f, " · {}", state),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn path() -> PathBuf {
+    paths::runtime_dir().join("now.json")
+}
+
+pub fn write(snapshot: &Snapshot) -> anyhow::Result<()> {
+    write_to(&path(), snapshot)
+}
+
+/// The daemon's snapshot, if there is one in a format this build reads
+/// and the daemon that wrote it is still alive.
+pub fn read_live() -> Option<Snapshot> {
+    read_from(&path()).filter(|snapshot| Path::new(&format!("/proc/{}", snapshot.pid)).exists())
+}
+
+pub fn remove() {
+    let _ = std::fs::remove_file(path());
+}
+
+fn write_to(path: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
+    paths::write_atomic(path, serde_json::to_vec(snapshot)?)
+}
+
+fn read_from(path: &Path) -> Option<Snapshot> {
+    let bytes = std::fs::read(path).ok()?;
+    serde_json::from_slice(&bytes)
+        .ok()
+        .filter(|snapshot: &Snapshot| wire::readable(snapshot.version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duration::TrackDuration;
+    use crate::MediaInfo;
+
+    #[test]
+    fn snapshot_round_trips_through_file() {
+        let dir = std::env::temp_dir().join(format!("dmr-status-{}", std::process::id()));
+        let path = dir.join("now.json");
+        let snapshot = Snapshot {
+            version: wire::VERSION,
+            pid: 1,
+            status: PlaybackStatus::Playing,
+            track: Some(Track::from(&MediaInfo {
+                title: "title".to_owned(),
+                ..Default::default()
+            })),
+            progress: Some(Progress {
+                position: TrackDuration::from_micros(90_000_000).unwrap(),
+                rate: 1.0,
+                at: std::time::UNIX_EPOCH,
+            }),
+            text: Some("title".to_owned()),
+            recent: vec![published(0, "Playing A - T")],
+            updates: Some(Strategy::Polling),
+            color: Some("#c81e1e".to_owned()),
+            discord: true,
+            dbus: true,
+        };
+
+        write_to(&path, &snapshot).unwrap();
+        assert_eq!(read_from(&path), Some(snapshot));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn published(at: u64, details: &str) -> Published {
+        Published {
+            at,
+            details: details.to_owned(),
+            state: None,
+        }
+    }
+
+    #[test]
+    fn recent_keeps_last_n_distinct() {
+        let mut recent = Recent::new(2);
+        recent.push(published(1, "a"));
+        recent.push(published(2, "b"));
+        recent.push(published(3, "b"));
+        recent.push(published(4, "c"));
+        let details: Vec<_> = recent.to_vec().into_iter().map(|p| p.details).collect();
+        assert_eq!(details, ["b", "c"]);
+    }
+
+    #[test]
+    fn entry_says_how_long_ago() {
+        let mut shown = published(0, "Playing A - T");
+        shown.state = Some("From Al".to_owned());
+        let line = |now| {
+            Entry {
+                published: &shown,
+                now,
+            }
+            .to_string()
+        };
+        assert_eq!(line(30), "just now  Playing A - T · From Al");
+        assert_eq!(line(600), "10m ago  Playing A - T · From Al");
+        assert_eq!(line(3900), "1h05m ago  Playing A - T · From Al");
+    }
+
+    #[test]
+    fn unversioned_snapshot_is_read() {
+        let dir = std::env::temp_dir().join(format!("dmr-status-v0-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("now.json");
+        std::fs::write(
+            &path,
+            r#"{"pid": 1, "status": "Stopped", "track": null, "progress": null}"#,
+        )
+        .unwrap();
+        assert_eq!(read_from(&path).map(|snapshot| snapshot.version), Some(0));
+        std::fs::write(
+            &path,
+            r#"{"version": 99, "pid": 1, "status": "Stopped", "track": null, "progress": null}"#,
+        )
+        .unwrap();
+        assert_eq!(read_from(&path), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_file_reads_as_none() {
+        assert_eq!(read_from(Path::new("/nonexistent/now.json")), None);
+    }
+}
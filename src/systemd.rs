@@ -0,0 +1,43 @@
+use log::debug;
+use std::io;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+/// Tells systemd how the daemon is getting on, as a `Type=notify` service
+/// expects: `READY=1` once it's following the player, `STOPPING=1` as it
+/// winds down. Does nothing when not started by systemd.
+pub fn notify(state: &str) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    if let Err(e) = notify_to(&socket.to_string_lossy(), state) {
+        debug!("couldn't tell systemd {}: {}", state, e);
+    }
+}
+
+/// Sends `state` to the socket at `path`, which starts with `@` when it's
+/// in the abstract namespace.
+fn notify_to(path: &str, state: &str) -> io::Result<()> {
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &address)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_is_sent_as_is() {
+        let path = std::env::temp_dir().join(format!("dmr-notify-{}", std::process::id()));
+        let listener = UnixDatagram::bind(&path).unwrap();
+        notify_to(path.to_str().unwrap(), "READY=1").unwrap();
+        let mut buffer = [0; 16];
+        let received = listener.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..received], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
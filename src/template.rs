@@ -0,0 +1,179 @@
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// A value that can be substituted into a template as `{name}`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placeholder {
+    Title,
+    Artist,
+    Album,
+    Player,
+    Length,
+    Position,
+    Genre,
+    Status,
+}
+
+impl FromStr for Placeholder {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "title" => Ok(Placeholder::Title),
+            "artist" => Ok(Placeholder::Artist),
+            "album" => Ok(Placeholder::Album),
+            "player" => Ok(Placeholder::Player),
+            "length" => Ok(Placeholder::Length),
+            "position" => Ok(Placeholder::Position),
+            "genre" => Ok(Placeholder::Genre),
+            "status" => Ok(Placeholder::Status),
+            other => Err(anyhow!("unknown placeholder `{{{}}}`", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// A placeholder, and what to put in its place when it's empty.
+    Value(Placeholder, String),
+}
+
+/// A format string such as `{artist} - {title}`. A placeholder may say what
+/// to show when the track has nothing for it, as in `{album|a single}`.
+/// Literal braces are written `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Template(Vec<Segment>);
+
+impl Template {
+    pub fn parse(text: &str) -> anyhow::Result<Template> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => bail!("unclosed `{{` in template `{}`", text),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    let (name, fallback) = name.split_once('|').unwrap_or((&name, ""));
+                    segments.push(Segment::Value(name.parse()?, fallback.to_owned()));
+                }
+                '}' => bail!("unmatched `}}` in template `{}`", text),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Template(segments))
+    }
+
+    /// The text the template always begins with, whatever's substituted
+    /// into it.
+    pub fn prefix(&self) -> &str {
+        match self.0.first() {
+            Some(Segment::Literal(text)) => text,
+            _ => "",
+        }
+    }
+
+    pub fn render(&self, value: impl Fn(Placeholder) -> String) -> String {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(text) => text.clone(),
+                Segment::Value(placeholder, fallback) => match value(*placeholder) {
+                    empty if empty.is_empty() => fallback.clone(),
+                    value => value,
+                },
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<String> for Template {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Template::parse(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(placeholder: Placeholder) -> String {
+        match placeholder {
+            Placeholder::Title => "Title".to_owned(),
+            Placeholder::Artist => "Artist".to_owned(),
+            _ => String::new(),
+        }
+    }
+
+    #[test]
+    fn substitutes_placeholders() {
+        let template = Template::parse("{artist} — {title}").unwrap();
+        assert_eq!(template.render(values), "Artist — Title");
+    }
+
+    #[test]
+    fn prefix_is_the_leading_literal() {
+        let template = Template::parse("https://example.com/{title}").unwrap();
+        assert_eq!(template.prefix(), "https://example.com/");
+        assert_eq!(Template::parse("{title}").unwrap().prefix(), "");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        let template = Template::parse("{{{title}}}").unwrap();
+        assert_eq!(template.render(values), "{Title}");
+    }
+
+    #[test]
+    fn missing_values_render_empty() {
+        let template = Template::parse("[{album}]").unwrap();
+        assert_eq!(template.render(values), "[]");
+    }
+
+    #[test]
+    fn fallback_fills_in_for_missing_values() {
+        let template = Template::parse("{title|Untitled} from {album|a single}").unwrap();
+        assert_eq!(template.render(values), "Title from a single");
+        assert!(Template::parse("{lyrics|none}").is_err());
+    }
+
+    #[test]
+    fn unknown_placeholder_is_rejected() {
+        assert!(Template::parse("{lyrics}").is_err());
+    }
+
+    #[test]
+    fn unclosed_placeholder_is_rejected() {
+        assert!(Template::parse("{title").is_err());
+    }
+
+    #[test]
+    fn stray_closing_brace_is_rejected() {
+        assert!(Template::parse("title}").is_err());
+    }
+}
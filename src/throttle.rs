@@ -0,0 +1,251 @@
+use crate::config;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long to hold back after a failed update, doubling with each failure
+/// in a row up to the longest.
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const LONGEST_RETRY: Duration = Duration::from_secs(60);
+
+/// The independently throttled parts of a presence update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Facet {
+    /// Track text: title, artist, album.
+    Metadata,
+    /// Playing/paused/stopped transitions.
+    Playback,
+    /// Elapsed/remaining time, e.g. after a seek.
+    Timestamps,
+}
+
+/// Decides when the next presence update may go out, honouring each facet's
+/// cadence as well as the global Discord rate limit.
+pub struct Scheduler {
+    cadence: HashMap<Facet, Duration>,
+    last_published: HashMap<Facet, Instant>,
+    dirty: HashSet<Facet>,
+    sent: VecDeque<Instant>,
+    burst: usize,
+    window: Duration,
+    settle: Duration,
+    held_until: Option<Instant>,
+    coalesce: Duration,
+    /// When the first of the pending changes was marked.
+    pending_since: Option<Instant>,
+    failures: u32,
+}
+
+impl Scheduler {
+    pub fn new(throttle: &config::Throttle) -> Self {
+        Scheduler {
+            cadence: HashMap::from([
+                (Facet::Metadata, Duration::from_secs(throttle.metadata)),
+                (Facet::Playback, Duration::from_secs(throttle.playback)),
+                (Facet::Timestamps, Duration::from_secs(throttle.timestamps)),
+            ]),
+            last_published: HashMap::new(),
+            dirty: HashSet::new(),
+            sent: VecDeque::new(),
+            burst: throttle.burst,
+            window: Duration::from_secs(throttle.window),
+            settle: Duration::from_secs(throttle.settle),
+            held_until: None,
+            coalesce: Duration::from_millis(throttle.coalesce_ms),
+            pending_since: None,
+            failures: 0,
+        }
+    }
+
+    /// Holds everything back for the settle period, e.g. after attaching to
+    /// a player.
+    pub fn settle(&mut self, now: Instant) {
+        self.held_until = Some(now + self.settle);
+    }
+
+    /// Notes that `facet` changed at `now` and needs publishing. Changes
+    /// marked soon after go out with it.
+    pub fn mark(&mut self, facet: Facet, now: Instant) {
+        self.dirty.insert(facet);
+        self.pending_since.get_or_insert(now);
+    }
+
+    /// When the pending changes may be published, or `None` if nothing is pending.
+    pub fn next_due(&self, now: Instant) -> Option<Instant> {
+        let facet_due = self
+            .dirty
+            .iter()
+            .map(|facet| match self.last_published.get(facet) {
+                Some(last) => *last + self.cadence[facet],
+                None => now,
+            })
+            .min()?;
+        let rate_due = match self.sent.len() {
+            n if n < self.burst => now,
+            n => self.sent[n - self.burst] + self.window,
+        };
+        let held = self.held_until.unwrap_or(now);
+        let coalesced = self
+            .pending_since
+            .map_or(now, |since| since + self.coalesce);
+        Some(facet_due.max(rate_due).max(held).max(coalesced).max(now))
+    }
+
+    /// Records that all pending changes went out at `now`.
+    pub fn published(&mut self, now: Instant) {
+        for facet in self.dirty.drain() {
+            self.last_published.insert(facet, now);
+        }
+        self.sent.push_back(now);
+        while self.sent.len() > self.burst {
+            self.sent.pop_front();
+        }
+        self.pending_since = None;
+        self.failures = 0;
+    }
+
+    /// Records that the pending changes failed to go out at `now`, so they
+    /// stay pending, held back for longer after each failure in a row.
+    pub fn failed(&mut self, now: Instant) {
+        let backoff = FIRST_RETRY
+            .saturating_mul(1 << self.failures.min(16))
+            .min(LONGEST_RETRY);
+        self.failures += 1;
+        self.held_until = Some(now + backoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle(metadata: u64, playback: u64, burst: usize, window: u64) -> config::Throttle {
+        config::Throttle {
+            metadata,
+            playback,
+            burst,
+            window,
+            coalesce_ms: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn nothing_due_when_clean() {
+        let scheduler = Scheduler::new(&config::Throttle::default());
+        assert_eq!(scheduler.next_due(Instant::now()), None);
+    }
+
+    #[test]
+    fn first_change_is_due_immediately() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(&throttle(30, 30, 5, 20));
+        scheduler.mark(Facet::Metadata, now);
+        assert_eq!(scheduler.next_due(now), Some(now));
+    }
+
+    #[test]
+    fn facet_waits_for_its_own_cadence() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(&throttle(0, 30, 5, 20));
+        scheduler.mark(Facet::Playback, now);
+        scheduler.published(now);
+
+        scheduler.mark(Facet::Playback, now);
+        assert_eq!(
+            scheduler.next_due(now + Duration::from_secs(1)),
+            Some(now + Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn faster_facet_is_not_held_back_by_slower_one() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(&throttle(0, 30, 5, 20));
+        scheduler.mark(Facet::Playback, now);
+        scheduler.published(now);
+
+        let later = now + Duration::from_secs(1);
+        scheduler.mark(Facet::Playback, later);
+        scheduler.mark(Facet::Metadata, later);
+        assert_eq!(scheduler.next_due(later), Some(later));
+    }
+
+    #[test]
+    fn settling_holds_changes_back() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(&config::Throttle {
+            settle: 3,
+            ..Default::default()
+        });
+        scheduler.settle(now);
+        scheduler.mark(Facet::Metadata, now);
+        assert_eq!(
+            scheduler.next_due(now + Duration::from_secs(1)),
+            Some(now + Duration::from_secs(3))
+        );
+        assert_eq!(
+            scheduler.next_due(now + Duration::from_secs(4)),
+            Some(now + Duration::from_secs(4))
+        );
+    }
+
+    #[test]
+    fn global_rate_limit_applies_across_facets() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(&throttle(0, 0, 2, 20));
+        scheduler.mark(Facet::Metadata, now);
+        scheduler.published(now);
+        scheduler.mark(Facet::Playback, now + Duration::from_secs(1));
+        scheduler.published(now + Duration::from_secs(1));
+
+        scheduler.mark(Facet::Metadata, now + Duration::from_secs(2));
+        assert_eq!(
+            scheduler.next_due(now + Duration::from_secs(2)),
+            Some(now + Duration::from_secs(20))
+        );
+    }
+
+    #[test]
+    fn burst_of_changes_goes_out_once() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(&config::Throttle::default());
+        let mut published = 0;
+        // A player announcing a new track in several PropertiesChanged.
+        for millis in [0, 20, 40, 120, 250, 500, 1000] {
+            let at = now + Duration::from_millis(millis);
+            if millis <= 120 {
+                scheduler.mark(Facet::Metadata, at);
+                scheduler.mark(Facet::Playback, at);
+            }
+            if scheduler.next_due(at).is_some_and(|due| due <= at) {
+                scheduler.published(at);
+                published += 1;
+            }
+        }
+        assert_eq!(published, 1);
+        assert_eq!(scheduler.next_due(now + Duration::from_secs(1)), None);
+    }
+
+    #[test]
+    fn failures_back_off_until_one_goes_out() {
+        let now = Instant::now();
+        let mut scheduler = Scheduler::new(&config::Throttle::default());
+        scheduler.mark(Facet::Metadata, now);
+        scheduler.failed(now);
+        assert_eq!(scheduler.next_due(now), Some(now + Duration::from_secs(1)));
+        scheduler.failed(now);
+        assert_eq!(scheduler.next_due(now), Some(now + Duration::from_secs(2)));
+        (0..10).for_each(|_| scheduler.failed(now));
+        assert_eq!(scheduler.next_due(now), Some(now + LONGEST_RETRY));
+
+        let later = now + LONGEST_RETRY;
+        scheduler.published(later);
+        scheduler.mark(Facet::Playback, later);
+        scheduler.failed(later);
+        assert_eq!(
+            scheduler.next_due(later),
+            Some(later + Duration::from_secs(1))
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use crate::config::Config;
+use crate::seat;
+use anyhow::anyhow;
+use dbus::channel::MatchingReceiver;
+use dbus::message::MatchRule;
+use dbus::nonblock::{Proxy, SyncConnection};
+use dbus::MethodErr;
+use dbus_crossroads::Crossroads;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// The daemon's own bus name, taken so scripts can reach it.
+pub const BUS_NAME: &str = "io.github.dyercode.DiscordMediaplayerRpc";
+const PATH: &str = "/io/github/dyercode/DiscordMediaplayerRpc";
+const INTERFACE: &str = "io.github.dyercode.DiscordMediaplayerRpc.Toggles";
+
+/// Parts of the presence that can be switched off while the daemon runs,
+/// with `toggle` or over D-Bus, without touching the config. They're all
+/// back as configured after a restart.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Toggles {
+    /// The presence as a whole; off clears it until it's back on, with the
+    /// daemon carrying on following the player meanwhile.
+    pub presence: bool,
+    /// The large image, whether the track's own or one enrichment found.
+    pub art: bool,
+    pub buttons: bool,
+    pub timestamps: bool,
+    /// Say only that something's playing, just as while the screen is
+    /// being shared, and honouring `screen_share` the same way.
+    pub privacy: bool,
+}
+
+impl Default for Toggles {
+    fn default() -> Self {
+        Toggles {
+            presence: true,
+            art: true,
+            buttons: true,
+            timestamps: true,
+            privacy: false,
+        }
+    }
+}
+
+impl Toggles {
+    const NAMES: [&'static str; 5] = ["presence", "art", "buttons", "timestamps", "privacy"];
+
+    fn slot(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "presence" => Some(&mut self.presence),
+            "art" => Some(&mut self.art),
+            "buttons" => Some(&mut self.buttons),
+            "timestamps" => Some(&mut self.timestamps),
+            "privacy" => Some(&mut self.privacy),
+            _ => None,
+        }
+    }
+
+    pub fn set(&mut self, name: &str, on: bool) -> anyhow::Result<()> {
+        let slot = self.slot(name).ok_or_else(|| {
+            anyhow!(
+                "no toggle named `{}`; there's {}",
+                name,
+                Toggles::NAMES.join(", ")
+            )
+        })?;
+        *slot = on;
+        Ok(())
+    }
+
+    fn by_name(mut self) -> HashMap<String, bool> {
+        Toggles::NAMES
+            .iter()
+            .map(|&name| (name.to_owned(), *self.slot(name).unwrap()))
+            .collect()
+    }
+}
+
+/// Takes the daemon's bus name on `conn` and answers `Set` and `List`
+/// calls, passing changes on through `toggles`.
+pub async fn serve(
+    conn: &Arc<SyncConnection>,
+    toggles: watch::Sender<Toggles>,
+) -> Result<(), dbus::Error> {
+    conn.request_name(BUS_NAME, false, true, true).await?;
+    let mut cr = Crossroads::new();
+    let iface = cr.register(INTERFACE, |b| {
+        b.method(
+            "Set",
+            ("name", "on"),
+            (),
+            |_, toggles: &mut watch::Sender<Toggles>, (name, on): (String, bool)| {
+                let mut result = Ok(());
+                toggles.send_if_modified(|current| {
+                    let before = *current;
+                    result = current.set(&name, on);
+                    *current != before
+                });
+                result.map_err(|e| MethodErr::failed(&e))
+            },
+        );
+        b.method(
+            "List",
+            (),
+            ("toggles",),
+            |_, toggles: &mut watch::Sender<Toggles>, (): ()| Ok((toggles.borrow().by_name(),)),
+        );
+    });
+    cr.insert(PATH, &[iface], toggles);
+    conn.start_receive(
+        MatchRule::new_method_call().with_path(PATH),
+        Box::new(move |msg, conn| {
+            let _ = cr.handle_message(msg, conn);
+            true
+        }),
+    );
+    Ok(())
+}
+
+/// Runs `toggle`: lists the toggles, or sets one `on` or `off`, or flips it
+/// when neither is given.
+pub async fn run(config: &Config, name: Option<String>, on: Option<bool>) -> anyhow::Result<()> {
+    let (resource, conn) = seat::connect(config.session_bus).await?;
+    let resource = tokio::spawn(resource);
+    let proxy = Proxy::new(
+        BUS_NAME,
+        PATH,
+        Duration::from_secs(config.resilience.dbus_timeout),
+        conn,
+    );
+    let result = async {
+        let (toggles,): (HashMap<String, bool>,) =
+            proxy
+                .method_call(INTERFACE, "List", ())
+                .await
+                .map_err(|e| anyhow!("can't reach the daemon: {}", e))?;
+        let Some(name) = name else {
+            for name in Toggles::NAMES {
+                let on = toggles.get(name).copied().unwrap_or_default();
+                println!("{}: {}", name, describe(on));
+            }
+            return Ok(());
+        };
+        let on = on.unwrap_or_else(|| !toggles.get(&name).copied().unwrap_or_default());
+        let () = proxy
+            .method_call(INTERFACE, "Set", (name.as_str(), on))
+            .await
+            .map_err(|e| anyhow!("{}", e.message().unwrap_or("the daemon refused")))?;
+        println!("{}: {}", name, describe(on));
+        Ok(())
+    }
+    .await;
+    resource.abort();
+    result
+}
+
+fn describe(on: bool) -> &'static str {
+    match on {
+        true => "on",
+        false => "off",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toggles_are_set_by_name() {
+        let mut toggles = Toggles::default();
+        toggles.set("privacy", true).unwrap();
+        toggles.set("art", false).unwrap();
+        toggles.set("presence", false).unwrap();
+        assert!(toggles.privacy);
+        assert!(!toggles.presence);
+        assert!(!toggles.art);
+        let err = toggles.set("lyrics", false).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("art, buttons, timestamps, privacy"));
+    }
+
+    #[test]
+    fn listing_names_every_toggle() {
+        let listed = Toggles::default().by_name();
+        assert_eq!(listed.len(), Toggles::NAMES.len());
+        assert!(listed["timestamps"]);
+        assert!(!listed["privacy"]);
+    }
+}
@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+/// The trackid players send when nothing is loaded.
+const NO_TRACK: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// A stable identity for a track, for telling tracks apart without
+/// comparing how they're displayed: the player's `mpris:trackid` when it
+/// has one, otherwise a hash of the artist, title and URL.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TrackKey(String);
+
+impl TrackKey {
+    pub fn new(track_id: Option<&str>, artist: &str, title: &str, url: Option<&str>) -> Self {
+        match track_id {
+            Some(id) if !id.is_empty() && id != NO_TRACK => TrackKey(format!("id:{}", id)),
+            _ => TrackKey(format!(
+                "hash:{:016x}",
+                stable_hash(&[artist, title, url.unwrap_or_default()])
+            )),
+        }
+    }
+}
+
+impl TrackKey {
+    /// Whether the key is the player's own id for the track.
+    pub fn is_id(&self) -> bool {
+        self.0.starts_with("id:")
+    }
+}
+
+impl Display for TrackKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+// FNV-1a rather than std's hasher, whose output may change between Rust
+// releases and so can't be stored.
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// A hash of `fields` that stays the same across runs and builds, so it can
+/// be stored or used to name files.
+pub fn stable_hash(fields: &[&str]) -> u64 {
+    fields.iter().fold(FNV_OFFSET, |hash, field| {
+        fnv1a(fnv1a(hash, field.as_bytes()), &[0])
+    })
+}
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_id_is_the_key() {
+        let key = TrackKey::new(Some("/org/fake/Track/1"), "a", "t", None);
+        assert_eq!(key.to_string(), "id:/org/fake/Track/1");
+        assert_eq!(
+            key,
+            TrackKey::new(Some("/org/fake/Track/1"), "b", "u", None)
+        );
+    }
+
+    #[test]
+    fn hash_is_stable() {
+        assert_eq!(
+            TrackKey::new(None, "artist", "title", None).to_string(),
+            "hash:e786525d4c6ee7cc"
+        );
+    }
+
+    #[test]
+    fn no_track_id_falls_back_to_hash() {
+        let key = TrackKey::new(Some(NO_TRACK), "artist", "title", None);
+        assert_eq!(key, TrackKey::new(None, "artist", "title", None));
+    }
+
+    #[test]
+    fn fields_do_not_run_together() {
+        assert_ne!(
+            TrackKey::new(None, "ab", "c", None),
+            TrackKey::new(None, "a", "bc", None)
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// How a player's changes come to be noticed.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strategy {
+    /// Polled while waiting to see whether it sends PropertiesChanged.
+    Detecting,
+    /// It sends PropertiesChanged, so it's only read when it does.
+    Signals,
+    /// It changed without saying so, and is polled from then on.
+    Polling,
+}
+
+/// Works out, for one player, whether its signals can be relied on. Any
+/// signal settles that they can; a poll finding a change nothing announced
+/// settles that they can't, until a signal turns up after all.
+#[derive(Debug)]
+pub struct Detector {
+    strategy: Strategy,
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Detector {
+            strategy: Strategy::Detecting,
+        }
+    }
+}
+
+impl Detector {
+    /// Whether the player needs reading on a timer.
+    pub fn polls(&self) -> bool {
+        self.strategy != Strategy::Signals
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn signalled(&mut self) -> Option<Strategy> {
+        self.settle(Strategy::Signals)
+    }
+
+    /// A poll read the player; `changed` if it found something different
+    /// from the reading before.
+    pub fn polled(&mut self, changed: bool) -> Option<Strategy> {
+        match self.strategy {
+            Strategy::Detecting if changed => self.settle(Strategy::Polling),
+            _ => None,
+        }
+    }
+
+    /// Switches to `strategy`, returning it if that's a change.
+    fn settle(&mut self, strategy: Strategy) -> Option<Strategy> {
+        (std::mem::replace(&mut self.strategy, strategy) != strategy).then_some(strategy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_settles_on_signals() {
+        let mut detector = Detector::default();
+        assert!(detector.polls());
+        assert_eq!(detector.polled(false), None);
+        assert_eq!(detector.signalled(), Some(Strategy::Signals));
+        assert_eq!(detector.signalled(), None);
+        assert!(!detector.polls());
+    }
+
+    #[test]
+    fn unannounced_change_settles_on_polling() {
+        let mut detector = Detector::default();
+        assert_eq!(detector.polled(true), Some(Strategy::Polling));
+        assert_eq!(detector.polled(true), None);
+        assert!(detector.polls());
+        // A late signal means it does send them after all.
+        assert_eq!(detector.signalled(), Some(Strategy::Signals));
+    }
+}
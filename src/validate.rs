@@ -0,0 +1,223 @@
+use crate::config::{MAX_BUTTONS, MAX_BUTTON_LABEL};
+use crate::presence::{Activity, Button, Party};
+use log::debug;
+
+/// Discord's bounds on the details, state and hover texts, in characters.
+const MIN_TEXT: usize = 2;
+const MAX_TEXT: usize = 128;
+/// Longer image keys and URLs are refused.
+const MAX_IMAGE: usize = 256;
+/// As are longer button URLs.
+const MAX_BUTTON_URL: usize = 512;
+const MAX_PARTY_ID: usize = 128;
+
+/// Brings `activity` within what Discord accepts, since it refuses the
+/// whole presence over any one field. Text is cut short or padded out, and
+/// what can't be mended, such as a link that isn't one, is left out.
+pub fn activity(activity: Activity) -> Activity {
+    let mut buttons: Vec<Button> = activity.buttons.into_iter().filter_map(button).collect();
+    // The buttons come in order of preference, so the last ones make way.
+    buttons.truncate(MAX_BUTTONS);
+    Activity {
+        details: text(&activity.details),
+        state: activity.state.as_deref().and_then(optional_text),
+        large_image: activity.large_image.filter(|image| valid_image(image)),
+        large_text: activity.large_text.as_deref().and_then(optional_text),
+        small_image: activity.small_image.filter(|image| valid_image(image)),
+        small_text: activity.small_text.as_deref().and_then(optional_text),
+        buttons,
+        party: activity.party.and_then(party),
+        ..activity
+    }
+}
+
+/// Trims `text`, padding it out to Discord's minimum or cutting it short
+/// with an ellipsis.
+fn text(text: &str) -> String {
+    let text = text.trim();
+    let length = text.chars().count();
+    if length > MAX_TEXT {
+        let mut cut: String = text.chars().take(MAX_TEXT - 1).collect();
+        cut.push('…');
+        return cut;
+    }
+    // Discord strips spaces, but keeps a zero-width one.
+    let mut padded = text.to_owned();
+    padded.extend(std::iter::repeat_n(
+        '\u{200b}',
+        MIN_TEXT.saturating_sub(length),
+    ));
+    padded
+}
+
+/// A blank optional field is left out, rather than padded.
+fn optional_text(value: &str) -> Option<String> {
+    (!value.trim().is_empty()).then(|| text(value))
+}
+
+/// Whether `url` is one Discord will follow or fetch.
+pub fn is_web_url(url: &str) -> bool {
+    ["https://", "http://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+/// An asset key uploaded to the application, or a web URL Discord fetches.
+fn valid_image(image: &str) -> bool {
+    let valid = !image.trim().is_empty()
+        && image.len() <= MAX_IMAGE
+        && (!image.contains("://") || is_web_url(image));
+    if !valid {
+        debug!(
+            "leaving out the image `{}`, which Discord won't take",
+            image
+        );
+    }
+    valid
+}
+
+fn button(button: Button) -> Option<Button> {
+    if button.url.len() > MAX_BUTTON_URL || !is_web_url(&button.url) {
+        debug!(
+            "leaving out button `{}`, as its URL is too long or not a web link",
+            button.label
+        );
+        return None;
+    }
+    let label = button.label.trim();
+    if label.is_empty() {
+        debug!("leaving out a button to {} with no label", button.url);
+        return None;
+    }
+    Some(Button {
+        label: label.chars().take(MAX_BUTTON_LABEL).collect(),
+        url: button.url,
+    })
+}
+
+fn party(party: Party) -> Option<Party> {
+    if party.id.is_empty() || party.id.len() > MAX_PARTY_ID {
+        return None;
+    }
+    let size = party
+        .size
+        .filter(|&(current, max)| current >= 1 && current <= max);
+    Some(Party { size, ..party })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content::ContentType;
+
+    fn blank() -> Activity {
+        Activity {
+            kind: ContentType::Audio,
+            state: None,
+            details: "Playing A - T".to_owned(),
+            large_image: None,
+            large_text: None,
+            small_image: None,
+            small_text: None,
+            timestamps: None,
+            buttons: Vec::new(),
+            party: None,
+            status_display: Default::default(),
+        }
+    }
+
+    fn button(label: &str, url: &str) -> Button {
+        Button {
+            label: label.to_owned(),
+            url: url.to_owned(),
+        }
+    }
+
+    #[test]
+    fn long_text_is_cut_with_ellipsis() {
+        let cut = text(&"é".repeat(200));
+        assert_eq!(cut.chars().count(), MAX_TEXT);
+        assert!(cut.ends_with("é…"));
+        assert_eq!(text("  River  "), "River");
+    }
+
+    #[test]
+    fn short_text_is_padded() {
+        assert_eq!(text("X"), "X\u{200b}");
+        assert_eq!(text(" "), "\u{200b}\u{200b}");
+    }
+
+    #[test]
+    fn blank_optional_fields_are_dropped() {
+        let checked = activity(Activity {
+            state: Some("  ".to_owned()),
+            large_text: Some(String::new()),
+            small_image: None,
+            small_text: None,
+            ..blank()
+        });
+        assert_eq!(checked.state, None);
+        assert_eq!(checked.large_text, None);
+    }
+
+    #[test]
+    fn images_must_be_keys_or_web_urls() {
+        assert!(valid_image("jazz"));
+        assert!(valid_image(
+            "https://coverartarchive.org/release/1/front-250"
+        ));
+        assert!(!valid_image("file:///home/me/cover.jpg"));
+        assert!(!valid_image(""));
+        assert!(!valid_image(&format!(
+            "https://x/{}",
+            "a".repeat(MAX_IMAGE)
+        )));
+    }
+
+    #[test]
+    fn bad_buttons_make_way_for_good_ones() {
+        let checked = activity(Activity {
+            buttons: vec![
+                button("Lyrics", "javascript:alert(1)"),
+                button(
+                    "Search",
+                    &format!("https://x/{}", "a".repeat(MAX_BUTTON_URL)),
+                ),
+                button("  ", "https://example.com"),
+                button(&"Last.fm".repeat(6), "https://last.fm"),
+                button("Listen", "https://song.link/s/1"),
+                button("More", "https://example.com"),
+            ],
+            ..blank()
+        });
+        assert_eq!(
+            checked.buttons,
+            [
+                button(&"Last.fm".repeat(6)[..MAX_BUTTON_LABEL], "https://last.fm"),
+                button("Listen", "https://song.link/s/1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn party_size_must_fit() {
+        let checked = party(Party {
+            id: "ab".to_owned(),
+            size: Some((2, 1)),
+        });
+        assert_eq!(
+            checked,
+            Some(Party {
+                id: "ab".to_owned(),
+                size: None
+            })
+        );
+        assert_eq!(
+            party(Party {
+                id: String::new(),
+                size: None
+            }),
+            None
+        );
+    }
+}
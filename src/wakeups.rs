@@ -0,0 +1,55 @@
+use crate::logging;
+use log::info;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+static TRACER: Tracer = Tracer::new();
+
+/// Counts the daemon's wake-ups, once `--trace-wakeups` turns it on, to
+/// check that it sleeps when there's nothing to do.
+struct Tracer {
+    enabled: AtomicBool,
+    count: AtomicU64,
+}
+
+impl Tracer {
+    const fn new() -> Self {
+        Tracer {
+            enabled: AtomicBool::new(false),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    /// How many wake-ups there have been with this one, if they're being
+    /// counted.
+    fn woke(&self) -> Option<u64> {
+        self.enabled
+            .load(Ordering::Relaxed)
+            .then(|| self.count.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+}
+
+pub fn enable() {
+    TRACER.enabled.store(true, Ordering::Relaxed);
+}
+
+/// Logs that `task` woke up because of `cause`, when tracing wake-ups.
+pub fn trace(task: &str, cause: impl Display) {
+    if let Some(count) = TRACER.woke() {
+        info!(event = logging::event::WAKEUP; "wake-up {}: {} ({})", count, task, cause);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_counted_once_enabled() {
+        let tracer = Tracer::new();
+        assert_eq!(tracer.woke(), None);
+        tracer.enabled.store(true, Ordering::Relaxed);
+        assert_eq!(tracer.woke(), Some(1));
+        assert_eq!(tracer.woke(), Some(2));
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::{self, Severity};
+use crate::events::{Bus, Event, PlayerState};
+use crate::notify::{Problem, Streak};
+use crate::paths;
+use crate::template::Template;
+use crate::throttle::{Facet, Scheduler};
+use crate::track::stable_hash;
+use crate::PlaybackStatus;
+use anyhow::Context;
+use log::{debug, warn};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{sleep_until, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the channel message shows.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Embed {
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    footer: Option<Footer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Footer {
+    text: String,
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+    embeds: [&'a Embed; 1],
+}
+
+#[derive(Deserialize)]
+struct Posted {
+    id: String,
+}
+
+impl Embed {
+    fn new(state: &PlayerState, template: Option<&Template>) -> Self {
+        let Some(mi) = &state.track else {
+            return Embed {
+                title: "Nothing playing".to_owned(),
+                description: None,
+                footer: None,
+            };
+        };
+        let description = match template {
+            Some(template) => mi.render(template, state.status, state.progress.as_ref()),
+            None => match mi.album.as_str() {
+                "" => format!("by {}", mi.artist),
+                album => format!("by {}\non {}", mi.artist, album),
+            },
+        };
+        let title = match state.status {
+            PlaybackStatus::Playing => mi.title.clone(),
+            _ => format!("{} (paused)", mi.title),
+        };
+        Embed {
+            title,
+            description: Some(description).filter(|d| !d.trim().is_empty()),
+            footer: (!mi.player.is_empty()).then(|| Footer {
+                text: format!("via {}", mi.player),
+            }),
+        }
+    }
+}
+
+/// Keeps one message in a channel up to date through a webhook, editing it
+/// in place rather than posting a new one for every track.
+pub struct Webhook {
+    url: Url,
+    http: reqwest::Client,
+    message_id: Option<String>,
+    template: Option<Template>,
+    /// Edits are held to at most one per this many seconds, so skipping
+    /// through a playlist doesn't run into Discord's rate limit.
+    throttle: config::Throttle,
+}
+
+impl Webhook {
+    pub fn new(config: &config::Webhook, template: Option<Template>) -> anyhow::Result<Self> {
+        let url = config.url.as_deref().context("no webhook URL")?;
+        let url = Url::parse(url).context("the webhook URL isn't valid")?;
+        let message_id = std::fs::read_to_string(id_path(url.as_str())?).ok();
+        Ok(Webhook {
+            url,
+            http: reqwest::Client::builder().timeout(TIMEOUT).build()?,
+            message_id: message_id.map(|id| id.trim().to_owned()),
+            template,
+            throttle: config::Throttle {
+                metadata: config.min_interval,
+                ..Default::default()
+            },
+        })
+    }
+
+    /// Follows the bus until it closes, updating the message whenever what
+    /// it would show changes, as often as the throttle allows.
+    pub async fn run(mut self, mut events: broadcast::Receiver<Event>, bus: Bus) {
+        let mut scheduler = Scheduler::new(&self.throttle);
+        let mut shown: Option<Embed> = None;
+        let mut wanted: Option<Embed> = None;
+        let mut failing = Streak::default();
+        loop {
+            let due = scheduler.next_due(Instant::now());
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(Event::State(state)) => {
+                        let embed = Embed::new(&state, self.template.as_ref());
+                        if wanted.as_ref() != Some(&embed) {
+                            scheduler.mark(Facet::Metadata, Instant::now());
+                            wanted = Some(embed);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        debug!("webhook sink missed {} events", missed)
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                    let Some(embed) = wanted.as_ref().filter(|&embed| shown.as_ref() != Some(embed))
+                    else {
+                        // Changed and changed back before it was due.
+                        scheduler.published(Instant::now());
+                        continue;
+                    };
+                    match self.show(embed).await {
+                        Ok(()) => {
+                            shown = Some(embed.clone());
+                            scheduler.published(Instant::now());
+                            failing.succeed(&bus, "The webhook");
+                        }
+                        Err(e) => {
+                            warn!("couldn't update webhook message: {:#}", e);
+                            scheduler.failed(Instant::now());
+                            failing.fail(&bus, |failures| {
+                                Problem::new(
+                                    Severity::Warning,
+                                    format!(
+                                        "The webhook message couldn't be updated the last {} \
+                                         times: {:#}",
+                                        failures, e
+                                    ),
+                                )
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn show(&mut self, embed: &Embed) -> anyhow::Result<()> {
+        let message = Message { embeds: [embed] };
+        if let Some(id) = &self.message_id {
+            let url = message_url(&self.url, id);
+            let response = self.http.patch(url).json(&message).send().await?;
+            // Deleted from the channel, so start a new one.
+            if response.status() != reqwest::StatusCode::NOT_FOUND {
+                response.error_for_status()?;
+                return Ok(());
+            }
+        }
+        let response = self
+            .http
+            .post(posting_url(&self.url))
+            .json(&message)
+            .send()
+            .await?
+            .error_for_status()?;
+        let posted: Posted = response.json().await?;
+        if let Err(e) = save_id(self.url.as_str(), &posted.id) {
+            debug!("couldn't save webhook message id: {}", e);
+        }
+        self.message_id = Some(posted.id);
+        Ok(())
+    }
+}
+
+/// Where a message is edited, keeping the webhook's own query, such as the
+/// thread it posts in.
+fn message_url(webhook: &Url, id: &str) -> Url {
+    let mut url = webhook.clone();
+    if let Ok(mut segments) = url.path_segments_mut() {
+        segments.pop_if_empty().extend(["messages", id]);
+    }
+    url
+}
+
+/// Where a message is posted, asking for it back so its id is known.
+fn posting_url(webhook: &Url) -> Url {
+    let mut url = webhook.clone();
+    url.query_pairs_mut().append_pair("wait", "true");
+    url
+}
+
+// Remembered across restarts so the same message keeps being edited.
+fn id_path(url: &str) -> anyhow::Result<PathBuf> {
+    let dir = paths::state_dir().context("no state directory")?;
+    Ok(dir.join(format!("webhook-{:016x}", stable_hash(&[url]))))
+}
+
+fn save_id(url: &str, id: &str) -> anyhow::Result<()> {
+    paths::write_atomic(&id_path(url)?, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MediaInfo;
+
+    fn state(status: PlaybackStatus) -> PlayerState {
+        PlayerState {
+            track: Some(MediaInfo {
+                title: "River".to_owned(),
+                artist: "Joni Mitchell".to_owned(),
+                album: "Blue".to_owned(),
+                player: "mpv".to_owned(),
+                ..Default::default()
+            }),
+            status,
+            progress: None,
+        }
+    }
+
+    #[test]
+    fn urls_keep_the_webhook_query() {
+        let webhook = Url::parse("https://discord.com/api/webhooks/1/abc?thread_id=7").unwrap();
+        assert_eq!(
+            posting_url(&webhook).as_str(),
+            "https://discord.com/api/webhooks/1/abc?thread_id=7&wait=true"
+        );
+        assert_eq!(
+            message_url(&webhook, "42").as_str(),
+            "https://discord.com/api/webhooks/1/abc/messages/42?thread_id=7"
+        );
+        let plain = Url::parse("https://discord.com/api/webhooks/1/abc/").unwrap();
+        assert_eq!(
+            posting_url(&plain).as_str(),
+            "https://discord.com/api/webhooks/1/abc/?wait=true"
+        );
+        assert_eq!(
+            message_url(&plain, "42").as_str(),
+            "https://discord.com/api/webhooks/1/abc/messages/42"
+        );
+    }
+
+    #[test]
+    fn embed_describes_track() {
+        let embed = Embed::new(&state(PlaybackStatus::Playing), None);
+        assert_eq!(embed.title, "River");
+        assert_eq!(
+            embed.description.as_deref(),
+            Some("by Joni Mitchell\non Blue")
+        );
+        assert_eq!(embed.footer.unwrap().text, "via mpv");
+    }
+
+    #[test]
+    fn embed_uses_template_when_given() {
+        let template = Template::parse("{artist} – {album}").unwrap();
+        let embed = Embed::new(&state(PlaybackStatus::Paused), Some(&template));
+        assert_eq!(embed.title, "River (paused)");
+        assert_eq!(embed.description.as_deref(), Some("Joni Mitchell – Blue"));
+    }
+
+    #[test]
+    fn embed_says_when_nothing_plays() {
+        let embed = Embed::new(&PlayerState::not_playing(PlaybackStatus::Stopped), None);
+        assert_eq!(embed.title, "Nothing playing");
+        assert_eq!(embed.description, None);
+    }
+
+    #[test]
+    fn message_serializes_as_single_embed() {
+        let embed = Embed::new(&state(PlaybackStatus::Playing), None);
+        let json = serde_json::to_value(Message { embeds: [&embed] }).unwrap();
+        assert_eq!(json["embeds"][0]["title"], "River");
+        assert_eq!(json["embeds"][0]["footer"]["text"], "via mpv");
+    }
+}
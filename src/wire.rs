@@ -0,0 +1,109 @@
+use crate::content::ContentType;
+use crate::duration::TrackDuration;
+use crate::MediaInfo;
+use serde::{Deserialize, Serialize};
+
+/// The version of the JSON that other programs read, written into every
+/// payload. Fields may be added within a version; removing or changing one
+/// needs a new version.
+pub const VERSION: u32 = 1;
+
+/// Whether a payload written as `version` can be read. Payloads from before
+/// versioning say 0, and are the same as version 1.
+pub fn readable(version: u32) -> bool {
+    version <= VERSION
+}
+
+/// A track as it appears in payloads. This is kept apart from `MediaInfo`,
+/// so that fields added to that don't change the format until they're
+/// added here.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub track_id: Option<String>,
+    pub genres: Vec<String>,
+    pub player: String,
+    pub length: Option<TrackDuration>,
+    pub url: Option<String>,
+    pub content: ContentType,
+    #[serde(default)]
+    pub track_number: Option<u32>,
+    #[serde(default)]
+    pub image: Option<String>,
+}
+
+impl From<&MediaInfo> for Track {
+    fn from(mi: &MediaInfo) -> Self {
+        Track {
+            title: mi.title.clone(),
+            artist: mi.artist.clone(),
+            album: mi.album.clone(),
+            track_id: mi.track_id.clone(),
+            genres: mi.genres.clone(),
+            player: mi.player.clone(),
+            length: mi.length,
+            url: mi.url.clone(),
+            content: mi.content,
+            track_number: mi.track_number,
+            image: mi.image.clone(),
+        }
+    }
+}
+
+impl From<Track> for MediaInfo {
+    fn from(track: Track) -> Self {
+        MediaInfo {
+            title: track.title,
+            artist: track.artist,
+            album: track.album,
+            track_id: track.track_id,
+            genres: track.genres,
+            player: track.player,
+            length: track.length,
+            url: track.url,
+            content: track.content,
+            unseekable: false,
+            track_number: track.track_number,
+            image: track.image,
+            art_url: None,
+            link: None,
+            bus_name: String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_round_trips_through_media_info() {
+        let mi = MediaInfo {
+            title: "River".to_owned(),
+            length: TrackDuration::from_micros(240_000_000),
+            track_number: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(MediaInfo::from(Track::from(&mi)), mi);
+    }
+
+    #[test]
+    fn fields_added_within_a_version_are_optional() {
+        let track: Track = serde_json::from_str(
+            r#"{"title": "River", "artist": "", "album": "", "track_id": null,
+                "genres": [], "player": "", "length": null, "url": null,
+                "content": "audio"}"#,
+        )
+        .unwrap();
+        assert_eq!(track.track_number, None);
+    }
+
+    #[test]
+    fn only_known_versions_are_readable() {
+        assert!(readable(0));
+        assert!(readable(VERSION));
+        assert!(!readable(VERSION + 1));
+    }
+}